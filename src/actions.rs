@@ -3,6 +3,10 @@ use crate::map::Map;
 
 pub enum Action {
     MoveAction{id: Entity, x: i32, y: i32},
+    RestAction{id: Entity},
+    SearchAction{id: Entity},
+    ReadAction{id: Entity},
+    ClimbAction{id: Entity},
     NoAction
 }
 
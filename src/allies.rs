@@ -0,0 +1,194 @@
+use crate::map::Map;
+use crate::components::{Position, Name, Companion};
+use crate::combat::{self, LastTarget};
+use crate::gamelog::GameLog;
+use crate::gui::Menu;
+use crate::player;
+use specs::{Entity, World, WorldExt, Join};
+
+/*
+Companions: following the player around, fighting at their side and doing as they're told
+*/
+
+// how far from the player a companion strays to pick a fight, squared
+const LEASH_SQ: f32 = 36.0;
+// a following companion closes in once the player gets further away than this, squared
+const HEEL_SQ: f32 = 5.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Follow,
+    // hold position and only fight whatever comes close
+    Stay,
+    Attack(Entity)
+}
+
+fn position(ecs: &World, id: Entity) -> Option<(i32, i32)> {
+    ecs.read_storage::<Position>().get(id).map(|pos| (pos.x, pos.y))
+}
+
+fn distance_sq(ecs: &World, a: Entity, b: Entity) -> Option<f32> {
+    let ((ax, ay), (bx, by)) = (position(ecs, a)?, position(ecs, b)?);
+    Some(ecs.fetch::<Map>().get_distance_sq(ax, ay, bx, by))
+}
+
+// still around to be fought
+fn standing(ecs: &World, id: Entity) -> bool {
+    position(ecs, id).is_some() && !combat::is_dead(ecs, id)
+}
+
+/// A companion at (x, y), which the player swaps places with rather than bumping into.
+pub fn companion_at(ecs: &World, x: i32, y: i32) -> Option<Entity> {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let companions = ecs.read_storage::<Companion>();
+    (&entities, &positions, &companions).join()
+        .find(|(_, pos, _)| pos.x == x && pos.y == y)
+        .map(|(ent, _, _)| ent)
+}
+
+/// What companion `id` should fight: whatever it was ordered to attack, otherwise what the player
+/// is fighting or anything hostile near them, or, if it was told to stay, only what comes right up to it.
+pub fn pick_target(ecs: &World, id: Entity) -> Option<Entity> {
+    let player = *ecs.fetch::<Entity>();
+    let order = ecs.read_storage::<Companion>().get(id)?.order;
+    match order {
+        Order::Attack(target) if standing(ecs, target) => return Some(target),
+        Order::Attack(_) => {
+            // the job's done, back to the player's side
+            if let Some(companion) = ecs.write_storage::<Companion>().get_mut(id) {
+                companion.order = Order::Follow;
+            }
+        },
+        Order::Stay => return combat::pick_target(ecs, id).filter(|t| distance_sq(ecs, id, *t).is_some_and(|d| d <= 2.0)),
+        Order::Follow => {}
+    }
+    let near_player = |target: &Entity| distance_sq(ecs, player, *target).is_some_and(|d| d <= LEASH_SQ);
+    let players_target = ecs.try_fetch::<LastTarget>().and_then(|last| last.0);
+    players_target.filter(|t| standing(ecs, *t) && near_player(t))
+        .or_else(|| combat::pick_target(ecs, id).filter(near_player))
+}
+
+/// Where a companion with nothing to fight goes: back to the player if it's following and has fallen behind.
+pub fn heel(ecs: &World, id: Entity) -> Option<Entity> {
+    let player = *ecs.fetch::<Entity>();
+    let following = ecs.read_storage::<Companion>().get(id).is_some_and(|c| c.order == Order::Follow);
+    if following && distance_sq(ecs, id, player).is_some_and(|d| d > HEEL_SQ) {
+        Some(player)
+    } else {
+        None
+    }
+}
+
+/// The companions that would come along if the player left the floor now: those following
+/// them and close enough to keep up. Anyone told to stay is left behind.
+pub fn travelling_companions(ecs: &World) -> Vec<Entity> {
+    let player = *ecs.fetch::<Entity>();
+    let following: Vec<Entity> = {
+        let entities = ecs.entities();
+        let companions = ecs.read_storage::<Companion>();
+        (&entities, &companions).join()
+            .filter(|(_, companion)| companion.order != Order::Stay)
+            .map(|(ent, _)| ent)
+            .collect()
+    };
+    following.into_iter().filter(|ent| distance_sq(ecs, player, *ent).is_some_and(|d| d <= LEASH_SQ)).collect()
+}
+
+/// The orders the player can give right now, and the menu to pick one from.
+pub fn orders(ecs: &World) -> (Vec<Order>, Menu) {
+    let mut orders = vec![Order::Follow, Order::Stay];
+    let mut hostiles: Vec<Entity> = player::hostiles_in_view(ecs).into_iter().collect();
+    hostiles.sort_by_key(|ent| ent.id());
+    orders.extend(hostiles.into_iter().map(Order::Attack));
+    let names = ecs.read_storage::<Name>();
+    let options: Vec<String> = orders.iter().map(|order| match order {
+        Order::Follow => "Follow me".to_string(),
+        Order::Stay => "Stay here".to_string(),
+        Order::Attack(target) => format!("Attack the {}", names.get(*target).map_or("something", |n| n.name.as_str()))
+    }).collect();
+    (orders, Menu::new(options.len() as i16 - 1, options))
+}
+
+/// Gives every companion the same order. Returns false if there was nobody to give it to.
+pub fn give_order(ecs: &World, order: Order) -> bool {
+    let mut count = 0;
+    for companion in (&mut ecs.write_storage::<Companion>()).join() {
+        companion.order = order;
+        count += 1;
+    }
+    let message = match (count, order) {
+        (0, _) => "There is nobody to give orders to.",
+        (_, Order::Follow) => "You call your companions to your side.",
+        (_, Order::Stay) => "You tell your companions to stay.",
+        (_, Order::Attack(_)) => "You point out the enemy to your companions."
+    };
+    ecs.write_resource::<GameLog>().log(message);
+    count > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Player, Health, Faction, Vision, Submerged, Peaceful};
+    use crate::factions::Factions;
+    use crate::map::Tile;
+    use specs::{Builder, World, WorldExt};
+    use std::collections::HashSet;
+
+    fn world() -> (World, Entity, Entity) {
+        let mut ecs = World::new();
+        ecs.register::<Position>();
+        ecs.register::<Name>();
+        ecs.register::<Player>();
+        ecs.register::<Health>();
+        ecs.register::<Faction>();
+        ecs.register::<Vision>();
+        ecs.register::<Submerged>();
+        ecs.register::<Companion>();
+        ecs.register::<Peaceful>();
+        let map = Map::new(30, 10);
+        // everyone sees everything
+        let everything: HashSet<Tile> = (0..30).flat_map(|x| (0..10).map(move |y| (x, y)))
+            .map(|(x, y)| Tile{x, y, tile_type: map.get_tile_type(x, y)}).collect();
+        ecs.insert(map);
+        ecs.insert(GameLog::default());
+        ecs.insert(LastTarget::default());
+        ecs.insert(Factions::parse("[orcs]\nplayer = -50").unwrap());
+        let player = ecs.create_entity().with(Player{}).with(Position{x: 2, y: 2}).with(Health{current: 10, max: 10}).build();
+        ecs.insert(player);
+        let hound = ecs.create_entity().with(Companion{order: Order::Follow}).with(Position{x: 3, y: 2})
+            .with(Health{current: 8, max: 8}).with(Vision{field_of_vision: everything}).build();
+        (ecs, player, hound)
+    }
+
+    fn orc(ecs: &mut World, x: i32) -> Entity {
+        ecs.create_entity().with(Faction{name: "orcs".to_string()}).with(Position{x, y: 2}).with(Health{current: 5, max: 5}).build()
+    }
+
+    #[test]
+    fn companions_fight_near_the_player_and_come_back_to_heel() {
+        let (mut ecs, player, hound) = world();
+        let far = orc(&mut ecs, 25);
+        assert_eq!(pick_target(&ecs, hound), None);
+        assert_eq!(heel(&ecs, hound), None);
+
+        // too far from the player to bother with, unless ordered to
+        give_order(&ecs, Order::Attack(far));
+        assert_eq!(pick_target(&ecs, hound), Some(far));
+        ecs.write_storage::<Health>().get_mut(far).unwrap().current = 0;
+        assert_eq!(pick_target(&ecs, hound), None);
+        assert_eq!(ecs.read_storage::<Companion>().get(hound).unwrap().order, Order::Follow);
+
+        let near = orc(&mut ecs, 6);
+        assert_eq!(pick_target(&ecs, hound), Some(near));
+
+        ecs.write_storage::<Position>().get_mut(hound).unwrap().x = 7;
+        give_order(&ecs, Order::Stay);
+        assert_eq!(heel(&ecs, hound), None);
+        assert!(travelling_companions(&ecs).is_empty());
+        give_order(&ecs, Order::Follow);
+        assert_eq!(heel(&ecs, hound), Some(player));
+        assert_eq!(travelling_companions(&ecs), vec![hound]);
+    }
+}
@@ -0,0 +1,304 @@
+use crate::components::{Actor, Position, Sprite, Speed, Vision, Player, Name, Description, StatusEffects, Perception, Health,
+                        Languages, Melee, Experience, Swimmer, Item, Carried, Character};
+use crate::conlang::{self, NameKind};
+use crate::rng::GameRng;
+use crate::gamelog::GameLog;
+use crate::gui::Menu;
+use crate::keymap::{Command, RawKey};
+use crate::player;
+use crate::stats::RunStats;
+use crate::language::Tongue;
+use specs::{Builder, Entity, World, WorldExt};
+use tcod::colors::*;
+use tcod::input::Key;
+use rand::Rng;
+use std::collections::HashSet;
+
+/*
+Character creation: who the player was before they came to the tower, and what they brought with them
+*/
+
+// longest name or seed that can be typed in
+const MAX_TEXT: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Background {
+    Scribe,
+    Soldier,
+    Sailor
+}
+
+pub const BACKGROUNDS: [Background; 3] = [Background::Scribe, Background::Soldier, Background::Sailor];
+
+impl Background {
+    pub fn name(self) -> &'static str {
+        match self {
+            Background::Scribe => "scribe",
+            Background::Soldier => "soldier",
+            Background::Sailor => "sailor"
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Background::Scribe => "A copyist come to read the tower's walls, better with a pen than a blade.",
+            Background::Soldier => "A veteran of the border wars, scarred, hardy and handy in a fight.",
+            Background::Sailor => "A deckhand who jumped ship, at home in the water and with a smattering of every port's tongue."
+        }
+    }
+
+    pub fn health(self) -> i32 {
+        match self {
+            Background::Scribe => 16,
+            Background::Soldier => 26,
+            Background::Sailor => 20
+        }
+    }
+
+    pub fn melee(self) -> i32 {
+        match self {
+            Background::Scribe => 3,
+            Background::Soldier => 5,
+            Background::Sailor => 4
+        }
+    }
+
+    pub fn perception(self) -> i32 {
+        match self {
+            Background::Scribe => 3,
+            Background::Soldier => 1,
+            Background::Sailor => 2
+        }
+    }
+
+    pub fn swims(self) -> bool {
+        self == Background::Sailor
+    }
+
+    // fluency in each tongue besides Common to start with
+    pub fn languages(self) -> Vec<(Tongue, u32)> {
+        match self {
+            Background::Scribe => vec![(Tongue::Babelic, 40)],
+            Background::Soldier => vec![(Tongue::Orcish, 30)],
+            Background::Sailor => vec![(Tongue::Orcish, 15), (Tongue::Babelic, 15)]
+        }
+    }
+
+    // name, glyph and description of everything in the starting pack
+    pub fn items(self) -> &'static [(&'static str, char, &'static str)] {
+        match self {
+            Background::Scribe => &[("quill", '/', "A goose feather quill, the nib worn down by years of copying."),
+                                    ("ink pot", '!', "A stoppered pot of oak gall ink, nearly full.")],
+            Background::Soldier => &[("dented shield", '[', "A round shield, its paint long since hacked away.")],
+            Background::Sailor => &[("coil of rope", '&', "Thirty feet of tarred hemp rope."),
+                                    ("tin whistle", '-', "A bosun's whistle, shrill enough to carry over a storm.")]
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Background,
+    Name,
+    Seed,
+    Summary
+}
+
+// what a keypress did to the character being made
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+    Editing,
+    Abandoned,
+    Finished
+}
+
+/// A character being put together on the new game screens.
+pub struct Creation {
+    pub step: Step,
+    // the backgrounds to pick from
+    pub menu: Menu,
+    pub name: String,
+    // left blank for a random game
+    pub seed: String
+}
+
+impl Default for Creation {
+    fn default() -> Creation {
+        let options = BACKGROUNDS.iter().map(|b| conlang::capitalise(b.name())).collect();
+        Creation{step: Step::Background, menu: Menu::new(BACKGROUNDS.len() as i16 - 1, options), name: String::new(), seed: String::new()}
+    }
+}
+
+impl Creation {
+    /// A new character with `seed` already filled in, to play an old run's tower again.
+    pub fn replay(seed: u64) -> Creation {
+        Creation{seed: seed.to_string(), ..Creation::default()}
+    }
+
+    pub fn background(&self) -> Background {
+        BACKGROUNDS[self.menu.cursor_index as usize]
+    }
+
+    /// The seed typed in, numbers as they are and anything else hashed, or None to roll one.
+    pub fn seed(&self) -> Option<u64> {
+        let seed = self.seed.trim();
+        if seed.is_empty() {
+            None
+        } else {
+            Some(seed.parse().unwrap_or_else(|_| conlang::hash(seed, 0)))
+        }
+    }
+
+    /// Confirm moves on a step and cancel goes back one, typing fills in the name and seed.
+    pub fn handle(&mut self, key: &Key, command: Option<Command>) -> Progress {
+        match (self.step, command) {
+            (_, Some(Command::Confirm)) => {
+                self.step = match self.step {
+                    Step::Background => Step::Name,
+                    Step::Name => Step::Seed,
+                    Step::Seed => Step::Summary,
+                    Step::Summary => return Progress::Finished
+                };
+            },
+            (_, Some(Command::Cancel)) => {
+                self.step = match self.step {
+                    Step::Background => return Progress::Abandoned,
+                    Step::Name => Step::Background,
+                    Step::Seed => Step::Name,
+                    Step::Summary => Step::Seed
+                };
+            },
+            (Step::Background, Some(Command::MoveUp)) => player::decrement_cursor(&mut self.menu.cursor_index, self.menu.menu_size),
+            (Step::Background, Some(Command::MoveDown)) => player::increment_cursor(&mut self.menu.cursor_index, self.menu.menu_size),
+            (Step::Name, _) => edit(&mut self.name, key),
+            (Step::Seed, _) => edit(&mut self.seed, key),
+            _ => {}
+        }
+        Progress::Editing
+    }
+
+    /// Everything about the character, as shown on the summary screen.
+    pub fn summary(&self) -> Vec<String> {
+        let background = self.background();
+        let name = if self.name.trim().is_empty() { "(a name will be picked for you)".to_string() } else { self.name.trim().to_string() };
+        let tongues: Vec<String> = background.languages().iter().map(|(tongue, fluency)| format!("{} {}", tongue.name(), fluency)).collect();
+        let items: Vec<&str> = background.items().iter().map(|(name, _, _)| *name).collect();
+        let mut lines = vec![
+            format!("Name: {}", name),
+            format!("Background: {}", conlang::capitalise(background.name())),
+            String::new(),
+            format!("Health: {}   Damage: {}   Perception: {}", background.health(), background.melee(), background.perception()),
+            format!("Tongues: Common, {}", tongues.join(", ")),
+            format!("Pack: {}", items.join(", ")),
+        ];
+        if background.swims() {
+            lines.push("Can swim.".to_string());
+        }
+        lines.push(String::new());
+        lines.push(format!("Seed: {}", self.seed().map_or("random".to_string(), |seed| seed.to_string())));
+        lines
+    }
+}
+
+// typing into a name or seed, backspace takes the last character off
+fn edit(text: &mut String, key: &Key) {
+    match RawKey::from_key(key) {
+        Some(RawKey::Char(c)) if text.len() < MAX_TEXT => text.push(c),
+        Some(RawKey::Named("Space", _)) if !text.is_empty() && text.len() < MAX_TEXT => text.push(' '),
+        Some(RawKey::Named("Backspace", _)) => {
+            text.pop();
+        },
+        _ => {}
+    }
+}
+
+/// Creates the player from the finished character at (x, y), and seeds the game if a seed was given.
+pub fn build(ecs: &mut World, creation: &Creation, x: i32, y: i32) -> Entity {
+    let background = creation.background();
+    // only the seed itself is left to chance, everything after it comes from the game's rng
+    let seed = creation.seed().unwrap_or_else(|| rand::thread_rng().gen());
+    ecs.insert(GameRng::new(seed));
+    let name = match creation.name.trim() {
+        "" => Tongue::Common.conlang().name(NameKind::Monster, &mut ecs.write_resource::<GameRng>().0),
+        name => name.to_string()
+    };
+    let languages = Languages{fluency: background.languages().into_iter().collect()};
+    let mut player = ecs.create_entity().with(Actor{action_points: 0, threshold: 5})
+                                       .with(Position{x, y})
+                                       .with(Sprite{sprite: '@', color: WHITE})
+                                       .with(Speed{speed: 1})
+                                       .with(Vision{field_of_vision: HashSet::new()})
+                                       .with(Name{name: "you".to_string()})
+                                       .with(Description{text: background.description().to_string()})
+                                       .with(StatusEffects::default())
+                                       .with(Perception{skill: background.perception()})
+                                       .with(Health{current: background.health(), max: background.health()})
+                                       .with(languages)
+                                       .with(Melee{damage: background.melee()})
+                                       .with(Experience::default())
+                                       .with(Character{name: name.clone(), background})
+                                       .with(Player{});
+    if background.swims() {
+        player = player.with(Swimmer{});
+    }
+    let player = player.build();
+    for (item, glyph, description) in background.items() {
+        ecs.create_entity().with(Sprite{sprite: *glyph, color: LIGHTEST_GREY})
+                           .with(Name{name: item.to_string()})
+                           .with(Description{text: description.to_string()})
+                           .with(Carried{owner: player})
+                           .with(Item{}).build();
+    }
+    ecs.insert(player);
+    ecs.write_resource::<RunStats>().seed = seed;
+    ecs.write_resource::<GameLog>().log(format!("{} the {} begins the climb.", name, background.name()));
+    player
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tcod::input::KeyCode;
+
+    fn typed(c: char) -> Key {
+        let mut key = Key::default();
+        key.code = KeyCode::Char;
+        key.printable = c;
+        key
+    }
+
+    #[test]
+    fn creation_steps_through_to_a_summary() {
+        let mut creation = Creation::default();
+        let none = Key::default();
+        assert_eq!(creation.handle(&none, Some(Command::MoveDown)), Progress::Editing);
+        assert_eq!(creation.background(), Background::Soldier);
+        creation.handle(&none, Some(Command::Confirm));
+
+        // letters that are also movement keys go into the name, not the cursor
+        for c in "Kjell".chars() {
+            creation.handle(&typed(c), Some(Command::MoveDown));
+        }
+        let mut backspace = Key::default();
+        backspace.code = KeyCode::Backspace;
+        creation.handle(&backspace, None);
+        assert_eq!(creation.name, "Kjel");
+        creation.handle(&none, Some(Command::Confirm));
+
+        assert_eq!(creation.seed(), None);
+        for c in "42".chars() {
+            creation.handle(&typed(c), None);
+        }
+        assert_eq!(creation.seed(), Some(42));
+        creation.seed = "babel".to_string();
+        assert_eq!(creation.seed(), Some(conlang::hash("babel", 0)));
+        creation.handle(&none, Some(Command::Confirm));
+
+        assert_eq!(creation.step, Step::Summary);
+        assert!(creation.summary().contains(&"Tongues: Common, Orcish 30".to_string()));
+        creation.handle(&none, Some(Command::Cancel));
+        assert_eq!(creation.step, Step::Seed);
+        creation.handle(&none, Some(Command::Confirm));
+        assert_eq!(creation.handle(&none, Some(Command::Confirm)), Progress::Finished);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::map::{Map, Tile};
+use crate::components::{Position, Name, Health, Melee, Player, Carried, Vision, Submerged};
+use crate::factions::{self, Stance};
+use crate::progression;
+use crate::stats::{self, RunStats};
+use crate::gamelog::GameLog;
+use crate::rng::GameRng;
+use rand::Rng;
+use specs::{Entity, World, WorldExt, Join};
+
+/*
+Fighting hand to hand, and what happens to whoever loses
+*/
+
+// whatever the player last attacked, which their companions join in on
+#[derive(Default)]
+pub struct LastTarget(pub Option<Entity>);
+
+fn name_of(ecs: &World, id: Entity) -> String {
+    ecs.read_storage::<Name>().get(id).map_or("something".to_string(), |n| n.name.clone())
+}
+
+fn in_view(ecs: &World, id: Entity) -> bool {
+    ecs.read_storage::<Position>().get(id).is_some_and(|pos| ecs.fetch::<Map>().tile_in_view(pos.x, pos.y))
+}
+
+pub fn is_dead(ecs: &World, id: Entity) -> bool {
+    ecs.read_storage::<Health>().get(id).is_some_and(|h| h.current <= 0)
+}
+
+/// Something at (x, y) that `id` would fight.
+pub fn hostile_at(ecs: &World, id: Entity, x: i32, y: i32) -> Option<Entity> {
+    let found: Vec<Entity> = {
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let health = ecs.read_storage::<Health>();
+        (&entities, &positions, &health).join()
+            .filter(|(ent, pos, _)| *ent != id && pos.x == x && pos.y == y)
+            .map(|(ent, _, _)| ent)
+            .collect()
+    };
+    found.into_iter().find(|ent| factions::stance(ecs, id, *ent) == Stance::Hostile)
+}
+
+/// What `id` goes after: the closest thing in sight it would fight, otherwise the player if they're enemies.
+pub fn pick_target(ecs: &World, id: Entity) -> Option<Entity> {
+    let player = *ecs.fetch::<Entity>();
+    let seen: Vec<(Entity, f32)> = {
+        let map = ecs.fetch::<Map>();
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let health = ecs.read_storage::<Health>();
+        let submerged = ecs.read_storage::<Submerged>();
+        let vision = ecs.read_storage::<Vision>();
+        match (positions.get(id), vision.get(id)) {
+            (Some(here), Some(vision)) => (&entities, &positions, &health, !&submerged).join()
+                .filter(|(ent, pos, _, _)| *ent != id && vision.field_of_vision.contains(&Tile{x: pos.x, y: pos.y, tile_type: map.get_tile_type(pos.x, pos.y)}))
+                .map(|(ent, pos, _, _)| (ent, map.get_distance_sq(here.x, here.y, pos.x, pos.y)))
+                .collect(),
+            _ => Vec::new()
+        }
+    };
+    let nearest = seen.into_iter()
+        .filter(|(ent, _)| factions::stance(ecs, id, *ent) == Stance::Hostile)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(ent, _)| ent);
+    let hunting = factions::stance(ecs, id, player) == Stance::Hostile;
+    nearest.or(if hunting { Some(player) } else { None })
+}
+
+/// `attacker` hits `victim` for up to its melee damage, killing it if that's the last of its health.
+pub fn attack(ecs: &World, attacker: Entity, victim: Entity) {
+    let most = ecs.read_storage::<Melee>().get(attacker).map_or(1, |m| m.damage);
+    let damage = ecs.write_resource::<GameRng>().0.gen_range(1..=most.max(1));
+    let dead = match ecs.write_storage::<Health>().get_mut(victim) {
+        Some(health) => {
+            health.current -= damage;
+            health.current <= 0
+        },
+        None => return
+    };
+
+    let player = *ecs.fetch::<Entity>();
+    if attacker == player {
+        if let Some(mut stats) = ecs.try_fetch_mut::<RunStats>() {
+            stats.damage_dealt += damage;
+        }
+    }
+    let name = name_of(ecs, attacker);
+    stats::player_hurt(ecs, victim, damage, &format!("was killed by {} {}", stats::article(&name), name));
+    let message = if attacker == player {
+        Some(format!("You hit the {} for {}.", name_of(ecs, victim), damage))
+    } else if victim == player {
+        Some(format!("The {} hits you for {}.", name_of(ecs, attacker), damage))
+    } else if in_view(ecs, attacker) || in_view(ecs, victim) {
+        Some(format!("The {} hits the {}.", name_of(ecs, attacker), name_of(ecs, victim)))
+    } else {
+        None
+    };
+    if let Some(message) = message {
+        ecs.write_resource::<GameLog>().log(message);
+    }
+    if attacker == player {
+        if let Some(mut last) = ecs.try_fetch_mut::<LastTarget>() {
+            last.0 = Some(victim);
+        }
+        factions::attacked(ecs, victim);
+    }
+    if dead && victim != player {
+        kill(ecs, victim, Some(attacker));
+    }
+}
+
+/// Removes `victim` from the game, leaving whatever it carried where it fell.
+pub fn kill(ecs: &World, victim: Entity, killer: Option<Entity>) {
+    let player = *ecs.fetch::<Entity>();
+    if in_view(ecs, victim) {
+        let message = format!("The {} dies.", name_of(ecs, victim));
+        ecs.write_resource::<GameLog>().log(message);
+    }
+    if killer == Some(player) {
+        factions::killed(ecs, victim);
+        if let Some(mut stats) = ecs.try_fetch_mut::<RunStats>() {
+            *stats.kills.entry(name_of(ecs, victim)).or_insert(0) += 1;
+        }
+    }
+    if let Some(killer) = killer {
+        progression::killed(ecs, victim, killer);
+    }
+    let position = ecs.write_storage::<Position>().remove(victim);
+    if let Some(pos) = position {
+        let dropped: Vec<Entity> = (&ecs.entities(), &ecs.read_storage::<Carried>()).join()
+            .filter(|(_, carried)| carried.owner == victim)
+            .map(|(ent, _)| ent)
+            .collect();
+        for item in dropped {
+            ecs.write_storage::<Carried>().remove(item);
+            ecs.write_storage::<Position>().insert(item, Position{x: pos.x, y: pos.y}).expect("dropped a dead item");
+        }
+    }
+    ecs.entities().delete(victim).expect("killed a dead entity");
+}
+
+/// Anything other than the player that has run out of health, from fire, gas or drowning, dies.
+pub fn reap(ecs: &World) {
+    let dead: Vec<Entity> = {
+        let entities = ecs.entities();
+        let health = ecs.read_storage::<Health>();
+        let players = ecs.read_storage::<Player>();
+        (&entities, &health, !&players).join()
+            .filter(|(_, health, _)| health.current <= 0)
+            .map(|(ent, _, _)| ent)
+            .collect()
+    };
+    for victim in dead {
+        kill(ecs, victim, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Faction, Item, Companion, Peaceful};
+    use crate::factions::Factions;
+    use specs::{Builder, World, WorldExt};
+
+    #[test]
+    fn rivals_fight_and_the_dead_drop_what_they_carried() {
+        let mut ecs = World::new();
+        ecs.register::<Position>();
+        ecs.register::<Name>();
+        ecs.register::<Health>();
+        ecs.register::<Melee>();
+        ecs.register::<Player>();
+        ecs.register::<Carried>();
+        ecs.register::<Item>();
+        ecs.register::<Faction>();
+        ecs.register::<Companion>();
+        ecs.register::<Peaceful>();
+        ecs.insert(Map::new(8, 8));
+        ecs.insert(GameLog::default());
+        ecs.insert(Factions::parse("[orcs]\nhostile = demons\n[demons]\n[scribes]").unwrap());
+        ecs.insert(GameRng::new(0));
+        let player = ecs.create_entity().with(Player{}).with(Position{x: 1, y: 1}).with(Health{current: 10, max: 10}).build();
+        ecs.insert(player);
+        let orc = ecs.create_entity().with(Faction{name: "orcs".to_string()}).with(Position{x: 3, y: 3})
+            .with(Health{current: 10, max: 10}).with(Melee{damage: 10}).build();
+        let imp = ecs.create_entity().with(Faction{name: "demons".to_string()}).with(Position{x: 4, y: 3})
+            .with(Health{current: 1, max: 1}).build();
+        let scribe = ecs.create_entity().with(Faction{name: "scribes".to_string()}).with(Position{x: 3, y: 4})
+            .with(Health{current: 1, max: 1}).build();
+        let charm = ecs.create_entity().with(Item{}).with(Carried{owner: imp}).build();
+
+        assert_eq!(hostile_at(&ecs, orc, 4, 3), Some(imp));
+        assert_eq!(hostile_at(&ecs, orc, 3, 4), None);
+        assert_eq!(hostile_at(&ecs, player, 3, 3), None);
+
+        attack(&ecs, orc, imp);
+        ecs.maintain();
+        assert!(!ecs.entities().is_alive(imp));
+        assert!(ecs.read_storage::<Carried>().get(charm).is_none());
+        assert_eq!(ecs.read_storage::<Position>().get(charm).map(|p| (p.x, p.y)), Some((4, 3)));
+
+        ecs.write_storage::<Health>().get_mut(scribe).unwrap().current = 0;
+        reap(&ecs);
+        ecs.maintain();
+        assert!(!ecs.entities().is_alive(scribe));
+        assert!(ecs.entities().is_alive(player));
+    }
+
+    #[test]
+    fn hostiles_hunt_the_player_sight_unseen() {
+        let mut ecs = World::new();
+        ecs.register::<Position>();
+        ecs.register::<Health>();
+        ecs.register::<Vision>();
+        ecs.register::<Submerged>();
+        ecs.register::<Player>();
+        ecs.register::<Faction>();
+        ecs.register::<Companion>();
+        ecs.register::<Peaceful>();
+        ecs.insert(Map::new(8, 8));
+        ecs.insert(Factions::parse("[orcs]\nplayer = -50\n[scribes]").unwrap());
+        let player = ecs.create_entity().with(Player{}).with(Position{x: 1, y: 1}).with(Health{current: 10, max: 10}).build();
+        ecs.insert(player);
+        // neither of them can see anything
+        let orc = ecs.create_entity().with(Faction{name: "orcs".to_string()}).with(Position{x: 6, y: 6}).build();
+        let scribe = ecs.create_entity().with(Faction{name: "scribes".to_string()}).with(Position{x: 6, y: 5}).build();
+        assert_eq!(pick_target(&ecs, orc), Some(player));
+        assert_eq!(pick_target(&ecs, scribe), None);
+    }
+}
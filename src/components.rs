@@ -1,8 +1,12 @@
 use crate::map::{Tile};
+use crate::pathfinding::NavigationPath;
+use crate::language::Tongue;
+use crate::allies::Order;
+use crate::chargen::Background;
 
-use specs::{Component, VecStorage, NullStorage};
+use specs::{Component, VecStorage, NullStorage, Entity};
 use tcod::colors::Color;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Component, Debug, Default)]
 #[storage(NullStorage)]
@@ -44,4 +48,244 @@ pub struct Vision {
 
 #[derive(Component, Debug, Default)]
 #[storage(NullStorage)]
-pub struct Blocking;
\ No newline at end of file
+pub struct Blocking;
+
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Item;
+
+// capability flag for movers that can work a door handle, used by pathfinding
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct OpensDoors;
+
+// a key, opens doors locked with the same id
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Unlocks {
+    pub lock: u32
+}
+
+// a feature the player hasn't found yet, left out of rendering and descriptions until detected
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Hidden {
+    // perception roll needed to notice it
+    pub difficulty: i32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrapKind {
+    Pit,
+    Dart,
+    Teleport,
+    Alarm,
+    Summoning,
+    Fire,
+    PoisonGas,
+    ConfusionGas,
+    Amnesia
+}
+
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Trap {
+    pub kind: TrapKind,
+    // roll needed to disarm it
+    pub difficulty: i32
+}
+
+// a door that looks like wall until it is found, the tile under it is a wall until then
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct SecretDoor;
+
+// bonus to rolls for noticing hidden features and disarming traps
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Perception {
+    pub skill: i32
+}
+
+// an item taken off the map and carried by someone
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Carried {
+    pub owner: Entity
+}
+
+// items that burn up, and spread fire to their tile, when caught in a fire
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Flammable;
+
+// stumbles in random directions until the turns run out
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Confused {
+    pub turns: i32
+}
+
+// on fire, taking damage every turn until it burns out or gets doused
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Burning {
+    pub turns: i32
+}
+
+// under the surface of deep water, out of sight until it comes back up
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Submerged;
+
+// swims without any risk of drowning
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Swimmer;
+
+// lives in the water and won't leave it, lurking submerged until its prey comes close
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Aquatic;
+
+// items that float and drift on water instead of sinking
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Floats;
+
+// items too heavy to hold on to while swimming
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Heavy;
+
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Health {
+    pub current: i32,
+    pub max: i32
+}
+
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Name {
+    pub name: String
+}
+
+// flavour text shown when the entity is examined
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Description {
+    pub text: String
+}
+
+// conditions currently affecting an entity, i.e. "burning", listed when it's examined
+#[derive(Component, Debug, Default)]
+#[storage(VecStorage)]
+pub struct StatusEffects {
+    pub effects: Vec<String>
+}
+
+// how well an entity knows each tongue, from 0 to language::FLUENT
+#[derive(Component, Debug, Default)]
+#[storage(VecStorage)]
+pub struct Languages {
+    pub fluency: HashMap<Tongue, u32>
+}
+
+// the tongue a creature speaks, and what it shouts the first time it sees the player
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Speaks {
+    pub tongue: Tongue,
+    pub shout: Option<String>
+}
+
+// text on an inscription, book or scroll
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Writing {
+    pub tongue: Tongue,
+    pub text: String
+}
+
+// reading (or talking to) this teaches the reader some of a tongue, once
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Teaches {
+    pub tongue: Tongue,
+    pub amount: u32
+}
+
+// a map of the floor, reading it shows the reader the whole layout
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct FloorPlan;
+
+// won't chase or attack the player whatever its faction thinks of them, and bumping into it starts a conversation
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Peaceful;
+
+// which side a creature is on, one of the [sections] in data/factions.cfg
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Faction {
+    pub name: String
+}
+
+// a pet or ally that follows the player and fights at their side
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Companion {
+    pub order: Order
+}
+
+// the most damage a blow in melee does
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Melee {
+    pub damage: i32
+}
+
+// the player's level, experience towards the next one, and level ups still waiting on a perk
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Experience {
+    pub level: i32,
+    pub xp: i32,
+    pub pending: i32
+}
+
+impl Default for Experience {
+    fn default() -> Experience {
+        Experience{level: 1, xp: 0, pending: 0}
+    }
+}
+
+// who the player is, as picked when the game began
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Character {
+    pub name: String,
+    pub background: Background
+}
+
+// the conversation in data/dialogue.cfg this entity holds when talked to
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Dialogue {
+    pub conversation: String
+}
+
+// cached A* path for an ai mover, only recomputed when it goes stale
+#[derive(Component, Default)]
+#[storage(VecStorage)]
+pub struct PathFollower {
+    pub path: NavigationPath,
+    // index into path.steps of the tile the entity is currently standing on
+    pub step: usize,
+    // map revision the path was computed against
+    pub revision: u32,
+    // who the path leads to, a new quarry needs a new path even if it's standing close by
+    pub quarry: Option<Entity>
+}
@@ -0,0 +1,171 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+/*
+Constructed languages generated from a seed: sounds, how they make syllables, words, and names
+*/
+
+const CONSONANTS: &str = "bcdfghjklmnprstvwxz";
+const VOWELS: &str = "aeiouy";
+// consonants that can follow another at the start of a syllable, i.e. the r in "tra"
+const LIQUIDS: &str = "lr";
+
+// FNV-1a, stable between runs and builds unlike the std hasher
+pub fn hash(text: &str, seed: u64) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325 ^ seed, |h, b| (h ^ b as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+/// One slot of a syllable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sound {
+    Consonant,
+    Vowel,
+    // consonant followed by a liquid
+    Cluster
+}
+
+/// What a generated name is for, each gets its own shape.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameKind {
+    Monster,
+    Place,
+    Item
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conlang {
+    pub consonants: Vec<char>,
+    pub vowels: Vec<char>,
+    // syllable shapes, the first is always consonant-vowel and the most common
+    pub syllables: Vec<Vec<Sound>>,
+    // endings that mark a word as a place or an item, i.e. "-heim" or "-ir"
+    place_suffix: String,
+    item_suffix: String,
+    seed: u64
+}
+
+impl Conlang {
+    /// Generates a whole language from `seed`, the same seed always gives the same language.
+    pub fn generate(seed: u64) -> Conlang {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut consonants: Vec<char> = CONSONANTS.chars().collect();
+        consonants.shuffle(&mut rng);
+        consonants.truncate(rng.gen_range(6..=12));
+        let mut vowels: Vec<char> = VOWELS.chars().collect();
+        vowels.shuffle(&mut rng);
+        vowels.truncate(rng.gen_range(2..=5));
+
+        use Sound::*;
+        let mut syllables = vec![vec![Consonant, Vowel]];
+        let optional = [vec![Vowel], vec![Consonant, Vowel, Consonant], vec![Vowel, Consonant],
+                        vec![Cluster, Vowel], vec![Consonant, Vowel, Vowel], vec![Cluster, Vowel, Consonant]];
+        syllables.extend(optional.iter().filter(|_| rng.gen_bool(0.4)).cloned());
+        // clusters need a liquid to cluster with
+        if !consonants.iter().any(|c| LIQUIDS.contains(*c)) {
+            syllables.retain(|s| !s.contains(&Cluster));
+        }
+
+        let mut conlang = Conlang{consonants, vowels, syllables, place_suffix: String::new(), item_suffix: String::new(), seed};
+        conlang.place_suffix = conlang.syllable(&mut rng);
+        conlang.item_suffix = conlang.syllable(&mut rng);
+        conlang
+    }
+
+    fn syllable<R: Rng>(&self, rng: &mut R) -> String {
+        // plain consonant-vowel half the time, so every language has a bit of a rhythm to it
+        let shape = if rng.gen_bool(0.5) { &self.syllables[0] } else { self.syllables.choose(rng).unwrap() };
+        let (liquids, others): (Vec<char>, Vec<char>) = self.consonants.iter().partition(|c| LIQUIDS.contains(**c));
+        let mut syllable = String::new();
+        for sound in shape {
+            match sound {
+                Sound::Consonant => syllable.push(*self.consonants.choose(rng).unwrap()),
+                Sound::Vowel => syllable.push(*self.vowels.choose(rng).unwrap()),
+                Sound::Cluster => {
+                    // there are always at least six consonants, so never only liquids
+                    syllable.push(*others.choose(rng).unwrap());
+                    syllable.push(*liquids.choose(rng).unwrap());
+                }
+            }
+        }
+        syllable
+    }
+
+    /// A word of `count` syllables.
+    pub fn word<R: Rng>(&self, rng: &mut R, count: usize) -> String {
+        (0..count.max(1)).map(|_| self.syllable(rng)).collect()
+    }
+
+    /// The word for `english`, always the same one for the same word. Longer words get longer translations.
+    pub fn translate(&self, english: &str) -> String {
+        let english = english.to_lowercase();
+        let mut rng = StdRng::seed_from_u64(hash(&english, self.seed));
+        let count = english.chars().count().div_ceil(3).clamp(1, 4);
+        self.word(&mut rng, count)
+    }
+
+    /// A capitalised name drawn from `rng`, monsters get bare names, places and items their own endings.
+    pub fn name<R: Rng>(&self, kind: NameKind, rng: &mut R) -> String {
+        let (syllables, suffix) = match kind {
+            NameKind::Monster => (rng.gen_range(2..=3), ""),
+            NameKind::Place => (rng.gen_range(1..=2), self.place_suffix.as_str()),
+            NameKind::Item => (rng.gen_range(1..=2), self.item_suffix.as_str()),
+        };
+        let name = self.word(rng, syllables) + suffix;
+        capitalise(&name)
+    }
+}
+
+pub fn capitalise(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_same_language() {
+        assert_eq!(Conlang::generate(42), Conlang::generate(42));
+        assert_ne!(Conlang::generate(42), Conlang::generate(43));
+
+        let (a, b) = (Conlang::generate(7), Conlang::generate(7));
+        for word in ["tower", "stair", "voice", "listen"] {
+            assert_eq!(a.translate(word), b.translate(word));
+            assert_eq!(a.translate(word), a.translate(&word.to_uppercase()));
+        }
+        let (mut rng_a, mut rng_b) = (StdRng::seed_from_u64(1), StdRng::seed_from_u64(1));
+        for kind in [NameKind::Monster, NameKind::Place, NameKind::Item] {
+            assert_eq!(a.name(kind, &mut rng_a), b.name(kind, &mut rng_b));
+        }
+    }
+
+    #[test]
+    fn words_only_use_the_language_s_sounds() {
+        for seed in 0..20 {
+            let conlang = Conlang::generate(seed);
+            let mut rng = StdRng::seed_from_u64(seed);
+            let words = [conlang.translate("builders"), conlang.name(NameKind::Place, &mut rng).to_lowercase()];
+            for word in words.iter() {
+                assert!(!word.is_empty());
+                assert!(word.chars().all(|c| conlang.consonants.contains(&c) || conlang.vowels.contains(&c)), "{} in {:?}", word, conlang);
+            }
+        }
+    }
+
+    #[test]
+    fn names_are_capitalised_and_marked_by_kind() {
+        let conlang = Conlang::generate(3);
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..10 {
+            let place = conlang.name(NameKind::Place, &mut rng);
+            assert!(place.starts_with(char::is_uppercase));
+            assert!(place.ends_with(&conlang.place_suffix));
+            assert!(conlang.name(NameKind::Item, &mut rng).ends_with(&conlang.item_suffix));
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::gui::{self, Frontend};
+use crate::keymap::{Command, Keymap};
+use crate::RunState;
+use specs::World;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::OnceLock;
+
+/*
+Cutscenes: full screen text, ASCII art and camera pans over the floor, played between bits of the game
+*/
+
+const DEFAULT_CUTSCENES: &str = include_str!("../data/cutscenes.cfg");
+const CUTSCENES_PATH: &str = "data/cutscenes.cfg";
+
+// how long a camera pan takes when the frame waits for a key instead of a set time
+const PAN_MILLIS: u64 = 1500;
+
+static CUTSCENES: OnceLock<Cutscenes> = OnceLock::new();
+
+// how a frame moves on to the next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wait {
+    Key,
+    Millis(u64)
+}
+
+/// One screen of a cutscene.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub art: Vec<String>,
+    pub text: Vec<String>,
+    // the camera pans between these two tiles of the current floor
+    pub camera: Option<((i32, i32), (i32, i32))>,
+    pub wait: Wait
+}
+
+// what the player did while a frame was showing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shown {
+    Next,
+    Skip
+}
+
+#[derive(Debug)]
+pub struct Cutscenes {
+    scenes: HashMap<String, Vec<Frame>>
+}
+
+fn point(text: &str) -> Option<(i32, i32)> {
+    let (x, y) = text.split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+impl Cutscenes {
+    /// Parses `[name]` sections of `art`, `text` and `camera` lines, each frame ended by a `wait`.
+    pub fn parse(text: &str) -> Result<Cutscenes, String> {
+        let mut scenes: HashMap<String, Vec<Frame>> = HashMap::new();
+        let mut current: Option<String> = None;
+        let mut frame = Frame{art: Vec::new(), text: Vec::new(), camera: None, wait: Wait::Key};
+        let mut started = false;
+        for (line_no, raw) in text.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                if started {
+                    return Err(format!("line {}: the last frame of '{}' has no wait", line_no + 1, current.unwrap_or_default()));
+                }
+                let name = name.trim().to_string();
+                if scenes.insert(name.clone(), Vec::new()).is_some() {
+                    return Err(format!("line {}: cutscene '{}' is defined twice", line_no + 1, name));
+                }
+                current = Some(name);
+                continue;
+            }
+            let (field, value) = line.split_once('=').ok_or(format!("line {}: expected 'field = value'", line_no + 1))?;
+            let scene = current.clone().ok_or(format!("line {}: field outside of a [cutscene] section", line_no + 1))?;
+            started = true;
+            match field.trim() {
+                // art keeps its spacing, only the one space after the '=' goes
+                "art" => {
+                    let (_, art) = raw.split_once('=').unwrap();
+                    frame.art.push(art.strip_prefix(' ').unwrap_or(art).trim_end().to_string());
+                },
+                "text" => frame.text.push(value.trim().to_string()),
+                "camera" => {
+                    let (from, to) = value.split_once("->").and_then(|(from, to)| Some((point(from)?, point(to)?)))
+                        .ok_or(format!("line {}: 'camera' should be 'x, y -> x, y'", line_no + 1))?;
+                    frame.camera = Some((from, to));
+                },
+                "wait" => {
+                    frame.wait = match value.trim() {
+                        "key" => Wait::Key,
+                        seconds => {
+                            let seconds: f32 = seconds.parse().map_err(|_| format!("line {}: 'wait' should be 'key' or a number of seconds", line_no + 1))?;
+                            Wait::Millis((seconds.max(0.0) * 1000.0) as u64)
+                        }
+                    };
+                    let done = std::mem::replace(&mut frame, Frame{art: Vec::new(), text: Vec::new(), camera: None, wait: Wait::Key});
+                    scenes.get_mut(&scene).unwrap().push(done);
+                    started = false;
+                },
+                other => return Err(format!("line {}: unknown field '{}'", line_no + 1, other))
+            }
+        }
+        if started {
+            return Err(format!("the last frame of '{}' has no wait", current.unwrap_or_default()));
+        }
+        Ok(Cutscenes{scenes})
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[Frame]> {
+        self.scenes.get(name).map(|frames| frames.as_slice())
+    }
+}
+
+/// Loads data/cutscenes.cfg if it's there and valid, otherwise the copy built into the game.
+pub fn load() {
+    CUTSCENES.get_or_init(|| {
+        match fs::read_to_string(CUTSCENES_PATH).map_err(|e| e.to_string()).and_then(|text| Cutscenes::parse(&text)) {
+            Ok(cutscenes) => cutscenes,
+            Err(err) => {
+                eprintln!("could not load {}, using built-in cutscenes: {}", CUTSCENES_PATH, err);
+                Cutscenes::parse(DEFAULT_CUTSCENES).expect("built-in cutscenes are invalid")
+            }
+        }
+    });
+}
+
+pub fn cutscenes() -> &'static Cutscenes {
+    CUTSCENES.get_or_init(|| Cutscenes::parse(DEFAULT_CUTSCENES).expect("built-in cutscenes are invalid"))
+}
+
+/// Plays cutscene `name` and then carries on to `then`, or goes straight there if there's no such cutscene.
+pub fn start(name: &str, then: RunState) -> RunState {
+    if cutscenes().get(name).is_some_and(|frames| !frames.is_empty()) {
+        RunState::Cutscene{name: name.to_string(), frame: 0, then: Box::new(then)}
+    } else {
+        then
+    }
+}
+
+// a key pressed during a frame cuts it short, cancel skips the whole cutscene
+fn pressed(ecs: &World, key: tcod::input::Key) -> Shown {
+    if ecs.fetch::<Keymap>().command_for(&key) == Some(Command::Cancel) { Shown::Skip } else { Shown::Next }
+}
+
+/// Shows `frame` until its wait runs out or a key is pressed, panning the camera first if it has one.
+pub fn play(frontend: &mut dyn Frontend, ecs: &World, frame: &Frame) -> Shown {
+    if let Some(((fx, fy), (tx, ty))) = frame.camera {
+        let steps = (tx - fx).abs().max((ty - fy).abs()).max(1);
+        let total = match frame.wait {
+            Wait::Millis(millis) => millis,
+            Wait::Key => PAN_MILLIS
+        };
+        for step in 0..=steps {
+            let x = fx + (tx - fx) * step / steps;
+            let y = fy + (ty - fy) * step / steps;
+            gui::render_cutscene(frontend, ecs, frame, Some((x, y)));
+            if let Some(key) = frontend.wait_for_keypress_timeout(total / (steps as u64 + 1)) {
+                return pressed(ecs, key);
+            }
+        }
+        if frame.wait != Wait::Key {
+            return Shown::Next;
+        }
+    } else {
+        gui::render_cutscene(frontend, ecs, frame, None);
+    }
+    match frame.wait {
+        Wait::Key => pressed(ecs, frontend.wait_for_keypress()),
+        Wait::Millis(millis) => frontend.wait_for_keypress_timeout(millis).map_or(Shown::Next, |key| pressed(ecs, key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_cutscenes_parse_and_art_keeps_its_spacing() {
+        let cutscenes = Cutscenes::parse(DEFAULT_CUTSCENES).unwrap();
+        assert!(cutscenes.get("opening").is_some_and(|frames| !frames.is_empty()));
+
+        let scene = Cutscenes::parse("[test]\nart =   /\\\ntext = Hello.\nwait = 1.5\ncamera = 1, 2 -> 10, 2\nwait = key").unwrap();
+        let frames = scene.get("test").unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].art, vec!["  /\\".to_string()]);
+        assert_eq!(frames[0].wait, Wait::Millis(1500));
+        assert_eq!(frames[1].camera, Some(((1, 2), (10, 2))));
+
+        assert!(Cutscenes::parse("[test]\ntext = Hello.").unwrap_err().contains("no wait"));
+        assert!(Cutscenes::parse("[test]\ncamera = 1, 2\nwait = key").unwrap_err().contains("line 2"));
+    }
+}
@@ -0,0 +1,373 @@
+use crate::components::{Position, Name, Carried, Peaceful, Dialogue, Speaks};
+use crate::factions::{self, Factions, Stance};
+use crate::gamelog::GameLog;
+use crate::gui::Menu;
+use crate::language::{self, Tongue};
+use specs::{Entity, World, WorldExt, Join};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::OnceLock;
+
+/*
+Conversations with peaceful NPCs, loaded from data/dialogue.cfg as graphs of lines and the player's replies
+*/
+
+const DEFAULT_DIALOGUE: &str = include_str!("../data/dialogue.cfg");
+const DIALOGUE_PATH: &str = "data/dialogue.cfg";
+
+// every conversation opens on this node
+pub const START: &str = "start";
+// a choice leading here closes the conversation
+pub const END: &str = "end";
+
+static CONVERSATIONS: OnceLock<Conversations> = OnceLock::new();
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    // the player carries an item with this name
+    Has(String),
+    Knows(Tongue, u32),
+    Flag(String),
+    NotFlag(String)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Effect {
+    // the speaker hands over an item with this name, if they have one
+    Give(String),
+    // and the player hands one over
+    Take(String),
+    Teach(Tongue, u32),
+    SetFlag(String),
+    // the player's standing with a faction goes up or down
+    Standing(String, i32),
+    // the speaker stops being peaceful and its faction turns on the player
+    Hostile
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Choice {
+    pub text: String,
+    pub next: String,
+    // all have to hold for the choice to be offered
+    pub conditions: Vec<Condition>
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Node {
+    // what the speaker says
+    pub text: String,
+    pub choices: Vec<Choice>,
+    // applied as the node is reached
+    pub effects: Vec<Effect>
+}
+
+#[derive(Debug)]
+pub struct Conversations {
+    // keyed by "conversation.node"
+    nodes: HashMap<String, Node>
+}
+
+// story flags set by conversations, i.e. which favours the player has been asked for
+#[derive(Default)]
+pub struct StoryFlags(pub HashSet<String>);
+
+fn parse_tongue(name: &str) -> Result<Tongue, String> {
+    Tongue::named(name).ok_or(format!("unknown tongue '{}'", name))
+}
+
+fn parse_amount(amount: &str) -> Result<u32, String> {
+    amount.parse().map_err(|_| format!("'{}' should be a number", amount))
+}
+
+fn parse_condition(text: &str) -> Result<Condition, String> {
+    let (keyword, rest) = text.split_once(' ').ok_or(format!("condition '{}' is missing its argument", text))?;
+    let rest = rest.trim();
+    match keyword {
+        "has" => Ok(Condition::Has(rest.to_string())),
+        "knows" => {
+            let (tongue, amount) = rest.split_once(' ').ok_or("'knows' takes a tongue and a fluency".to_string())?;
+            Ok(Condition::Knows(parse_tongue(tongue)?, parse_amount(amount.trim())?))
+        },
+        "flag" => Ok(Condition::Flag(rest.to_string())),
+        "not" => rest.strip_prefix("flag ").map(|flag| Condition::NotFlag(flag.trim().to_string()))
+                     .ok_or("only flags can be negated, i.e. 'not flag asked'".to_string()),
+        _ => Err(format!("unknown condition '{}'", keyword))
+    }
+}
+
+fn parse_effect(text: &str, factions: &Factions) -> Result<Effect, String> {
+    if text == "hostile" {
+        return Ok(Effect::Hostile);
+    }
+    let (keyword, rest) = text.split_once(' ').ok_or(format!("unknown effect '{}'", text))?;
+    let rest = rest.trim();
+    match keyword {
+        "give" => Ok(Effect::Give(rest.to_string())),
+        "take" => Ok(Effect::Take(rest.to_string())),
+        "teach" => {
+            let (tongue, amount) = rest.split_once(' ').ok_or("'teach' takes a tongue and an amount".to_string())?;
+            Ok(Effect::Teach(parse_tongue(tongue)?, parse_amount(amount.trim())?))
+        },
+        "set" => Ok(Effect::SetFlag(rest.to_string())),
+        "standing" => {
+            let (faction, amount) = rest.split_once(' ').ok_or("'standing' takes a faction and an amount".to_string())?;
+            if !factions.exists(faction) {
+                return Err(format!("unknown faction '{}'", faction));
+            }
+            let amount = amount.trim().parse().map_err(|_| format!("'{}' should be a number", amount.trim()))?;
+            Ok(Effect::Standing(faction.to_string(), amount))
+        },
+        _ => Err(format!("unknown effect '{}'", keyword))
+    }
+}
+
+// "reply text -> next node if condition, condition"
+fn parse_choice(text: &str) -> Result<Choice, String> {
+    let (reply, rest) = text.rsplit_once(" -> ").ok_or("a choice needs a '-> node' to lead to".to_string())?;
+    let (next, conditions) = match rest.split_once(" if ") {
+        Some((next, conditions)) => (next, conditions.split(',').map(|c| parse_condition(c.trim())).collect::<Result<Vec<_>, _>>()?),
+        None => (rest, Vec::new())
+    };
+    Ok(Choice{text: reply.trim().to_string(), next: next.trim().to_string(), conditions})
+}
+
+impl Conversations {
+    /// Parses `[conversation.node]` sections of `text`, `choice` and `effect` lines, which may repeat.
+    /// Every choice has to lead to a node of the same conversation, or to `end`, and every faction has to be in `factions`.
+    pub fn parse(text: &str, factions: &Factions) -> Result<Conversations, String> {
+        let mut nodes: HashMap<String, Node> = HashMap::new();
+        let mut current: Option<String> = None;
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(id) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                let id = id.trim();
+                if !id.contains('.') {
+                    return Err(format!("line {}: '{}' should be named conversation.node", line_no + 1, id));
+                }
+                if nodes.insert(id.to_string(), Node::default()).is_some() {
+                    return Err(format!("line {}: node '{}' is defined twice", line_no + 1, id));
+                }
+                current = Some(id.to_string());
+                continue;
+            }
+            let (field, value) = line.split_once('=').ok_or(format!("line {}: expected 'field = value'", line_no + 1))?;
+            let node = current.as_ref().and_then(|id| nodes.get_mut(id)).ok_or(format!("line {}: field outside of a [node] section", line_no + 1))?;
+            let value = value.trim();
+            match field.trim() {
+                "text" => node.text = value.to_string(),
+                "choice" => node.choices.push(parse_choice(value).map_err(|e| format!("line {}: {}", line_no + 1, e))?),
+                "effect" => node.effects.push(parse_effect(value, factions).map_err(|e| format!("line {}: {}", line_no + 1, e))?),
+                other => return Err(format!("line {}: unknown field '{}'", line_no + 1, other))
+            }
+        }
+
+        for (id, node) in nodes.iter() {
+            let (conversation, _) = id.split_once('.').unwrap();
+            if node.text.is_empty() {
+                return Err(format!("node '{}' has no text", id));
+            }
+            if let Some(choice) = node.choices.iter().find(|c| c.next != END && !nodes.contains_key(&format!("{}.{}", conversation, c.next))) {
+                return Err(format!("node '{}' leads to '{}', which doesn't exist", id, choice.next));
+            }
+            if !nodes.contains_key(&format!("{}.{}", conversation, START)) {
+                return Err(format!("conversation '{}' has no '{}' node", conversation, START));
+            }
+        }
+        Ok(Conversations{nodes})
+    }
+
+    pub fn node(&self, conversation: &str, id: &str) -> Option<&Node> {
+        self.nodes.get(&format!("{}.{}", conversation, id))
+    }
+}
+
+/// Loads data/dialogue.cfg if it's there and valid against `factions`, otherwise the copy built into the game.
+/// Has no effect once any conversation has been held.
+pub fn load(factions: &Factions) {
+    CONVERSATIONS.get_or_init(|| {
+        match fs::read_to_string(DIALOGUE_PATH).map_err(|e| e.to_string()).and_then(|text| Conversations::parse(&text, factions)) {
+            Ok(conversations) => conversations,
+            Err(err) => {
+                eprintln!("could not load {}, using built-in dialogue: {}", DIALOGUE_PATH, err);
+                builtin()
+            }
+        }
+    });
+}
+
+fn builtin() -> Conversations {
+    Conversations::parse(DEFAULT_DIALOGUE, &Factions::builtin()).expect("built-in dialogue is invalid")
+}
+
+pub fn conversations() -> &'static Conversations {
+    CONVERSATIONS.get_or_init(builtin)
+}
+
+/// Someone at (x, y) with something to say, who isn't out for the player's blood.
+pub fn speaker_at(ecs: &World, x: i32, y: i32) -> Option<Entity> {
+    let player = *ecs.fetch::<Entity>();
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let dialogues = ecs.read_storage::<Dialogue>();
+    (&entities, &positions, &dialogues).join()
+        .find(|(ent, pos, _)| pos.x == x && pos.y == y && factions::stance(ecs, *ent, player) != Stance::Hostile)
+        .map(|(ent, _, _)| ent)
+}
+
+/// The tongue `speaker` talks in, Common unless it has a `Speaks`.
+pub fn tongue_of(ecs: &World, speaker: Entity) -> Tongue {
+    ecs.read_storage::<Speaks>().get(speaker).map_or(Tongue::Common, |s| s.tongue)
+}
+
+fn name_of(ecs: &World, id: Entity) -> String {
+    ecs.read_storage::<Name>().get(id).map_or("stranger".to_string(), |n| n.name.clone())
+}
+
+fn carried_by(ecs: &World, owner: Entity, name: &str) -> Option<Entity> {
+    let entities = ecs.entities();
+    let carried = ecs.read_storage::<Carried>();
+    let names = ecs.read_storage::<Name>();
+    (&entities, &carried, &names).join()
+        .find(|(_, carried, n)| carried.owner == owner && n.name == name)
+        .map(|(ent, _, _)| ent)
+}
+
+fn holds(ecs: &World, condition: &Condition) -> bool {
+    let player = *ecs.fetch::<Entity>();
+    match condition {
+        Condition::Has(item) => carried_by(ecs, player, item).is_some(),
+        Condition::Knows(tongue, amount) => language::fluency(ecs, player, *tongue) >= *amount,
+        Condition::Flag(flag) => ecs.fetch::<StoryFlags>().0.contains(flag),
+        Condition::NotFlag(flag) => !ecs.fetch::<StoryFlags>().0.contains(flag)
+    }
+}
+
+/// The replies the player can pick right now, in the order they're written.
+pub fn available_choices<'a>(ecs: &World, node: &'a Node) -> Vec<&'a Choice> {
+    node.choices.iter().filter(|choice| choice.conditions.iter().all(|c| holds(ecs, c))).collect()
+}
+
+fn apply(ecs: &World, speaker: Entity, effect: &Effect) {
+    let player = *ecs.fetch::<Entity>();
+    match effect {
+        Effect::Give(item) | Effect::Take(item) => {
+            let (from, to) = if let Effect::Give(_) = effect { (speaker, player) } else { (player, speaker) };
+            if let Some(found) = carried_by(ecs, from, item) {
+                ecs.write_storage::<Carried>().insert(found, Carried{owner: to}).expect("handed over a dead item");
+                let message = if to == player {
+                    format!("The {} gives you the {}.", name_of(ecs, speaker), item)
+                } else {
+                    format!("You hand over the {}.", item)
+                };
+                ecs.write_resource::<GameLog>().log(message);
+            }
+        },
+        Effect::Teach(tongue, amount) => language::learn(ecs, player, *tongue, *amount),
+        Effect::SetFlag(flag) => {
+            ecs.write_resource::<StoryFlags>().0.insert(flag.clone());
+        },
+        Effect::Standing(faction, amount) => factions::shift_standing(ecs, faction, *amount),
+        Effect::Hostile => {
+            ecs.write_storage::<Peaceful>().remove(speaker);
+            if let Some(faction) = factions::faction_of(ecs, speaker) {
+                factions::provoke(ecs, &faction);
+            }
+        }
+    }
+}
+
+/// Moves the conversation on to `id`: the speaker says its line and its effects happen.
+/// Returns None once the conversation is over.
+pub fn enter(ecs: &World, speaker: Entity, conversation: &str, id: &str) -> Option<&'static Node> {
+    if id == END {
+        return None;
+    }
+    let node = conversations().node(conversation, id)?;
+    language::say(ecs, &name_of(ecs, speaker), tongue_of(ecs, speaker), &node.text);
+    for effect in node.effects.iter() {
+        apply(ecs, speaker, effect);
+    }
+    Some(node)
+}
+
+/// The player's replies to `node` as a menu, with a way out if none of them are open to them.
+pub fn menu(ecs: &World, node: &Node) -> Menu {
+    let mut options: Vec<String> = available_choices(ecs, node).iter().map(|c| c.text.clone()).collect();
+    if options.is_empty() {
+        options.push("(Leave)".to_string());
+    }
+    Menu::new(options.len() as i16 - 1, options)
+}
+
+/// Where picking the `index`th reply to `node` leads.
+pub fn next(ecs: &World, node: &Node, index: usize) -> String {
+    available_choices(ecs, node).get(index).map_or(END.to_string(), |choice| choice.next.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Player, Languages};
+    use specs::{Builder, World, WorldExt};
+
+    #[test]
+    fn shipped_dialogue_parses() {
+        let conversations = Conversations::parse(DEFAULT_DIALOGUE, &Factions::builtin()).unwrap();
+        assert!(conversations.node("hermit", START).is_some());
+    }
+
+    #[test]
+    fn broken_graphs_are_rejected() {
+        let dangling = "[hermit.start]\ntext = Hello.\nchoice = Bye. -> nowhere";
+        let factions = Factions::builtin();
+        assert!(Conversations::parse(dangling, &factions).unwrap_err().contains("leads to 'nowhere'"));
+        let no_start = "[hermit.hello]\ntext = Hello.\nchoice = Bye. -> end";
+        assert!(Conversations::parse(no_start, &factions).unwrap_err().contains("no 'start' node"));
+        let bad_condition = "[hermit.start]\ntext = Hello.\nchoice = Bye. -> end if knows Elvish 10";
+        assert!(Conversations::parse(bad_condition, &factions).unwrap_err().contains("unknown tongue 'Elvish'"));
+        let bad_faction = "[hermit.start]\ntext = Hello.\neffect = standing elves 10\nchoice = Bye. -> end";
+        assert!(Conversations::parse(bad_faction, &factions).unwrap_err().contains("line 3: unknown faction 'elves'"));
+    }
+
+    #[test]
+    fn choices_depend_on_items_flags_and_languages_and_effects_apply() {
+        let conversations = Conversations::parse("[trader.start]\ntext = Well?\n\
+            choice = Here's the key. -> thanks if has key\n\
+            choice = Speak slower. -> start if knows Babelic 10, not flag slow\n\
+            choice = Bye. -> end\n\
+            [trader.thanks]\ntext = Take this.\neffect = take key\neffect = give charm\neffect = set traded\n\
+            choice = Bye. -> end", &Factions::builtin()).unwrap();
+        let mut ecs = World::new();
+        ecs.register::<Position>();
+        ecs.register::<Name>();
+        ecs.register::<Player>();
+        ecs.register::<Carried>();
+        ecs.register::<Languages>();
+        ecs.register::<Speaks>();
+        ecs.insert(GameLog::default());
+        ecs.insert(StoryFlags::default());
+        let player = ecs.create_entity().with(Player{}).with(Languages::default()).build();
+        ecs.insert(player);
+        let trader = ecs.create_entity().with(Name{name: "trader".to_string()}).build();
+        let key = ecs.create_entity().with(Name{name: "key".to_string()}).with(Carried{owner: player}).build();
+        let charm = ecs.create_entity().with(Name{name: "charm".to_string()}).with(Carried{owner: trader}).build();
+
+        let start = conversations.node("trader", START).unwrap();
+        assert_eq!(available_choices(&ecs, start).len(), 2);
+        language::learn(&ecs, player, Tongue::Babelic, 10);
+        assert_eq!(available_choices(&ecs, start).len(), 3);
+        assert_eq!(next(&ecs, start, 0), "thanks");
+
+        for effect in conversations.node("trader", "thanks").unwrap().effects.iter() {
+            apply(&ecs, trader, effect);
+        }
+        assert_eq!(ecs.read_storage::<Carried>().get(key).unwrap().owner, trader);
+        assert_eq!(ecs.read_storage::<Carried>().get(charm).unwrap().owner, player);
+        assert!(ecs.fetch::<StoryFlags>().0.contains("traded"));
+        assert_eq!(available_choices(&ecs, start).len(), 2);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::map::{Map, TileType};
+use crate::components::{Position, Item, Name, Unlocks, Carried, Player, Submerged};
+use crate::gamelog::GameLog;
+use specs::{Entity, World, WorldExt, Join};
+
+/*
+Opening, closing and unlocking doors, and picking up the keys that go with them
+*/
+
+// id of a key the entity is carrying for this lock, if any
+fn carried_key(ecs: &World, id: Entity, lock: u32) -> Option<Entity> {
+    let entities = ecs.entities();
+    let keys = ecs.read_storage::<Unlocks>();
+    let carried = ecs.read_storage::<Carried>();
+    (&entities, &keys, &carried).join()
+        .find(|(_, key, carried)| key.lock == lock && carried.owner == id)
+        .map(|(ent, _, _)| ent)
+}
+
+// only the player's door handling is worth a message
+fn log_for(ecs: &World, id: Entity, message: String) {
+    if ecs.read_storage::<Player>().contains(id) {
+        ecs.write_resource::<GameLog>().log(message);
+    }
+}
+
+/// Opens the closed door at (x, y), unlocking it first if `id` carries the matching key.
+/// Returns whether the door was opened, which takes the mover's turn.
+pub fn open_door(ecs: &World, id: Entity, x: i32, y: i32) -> bool {
+    let mut map = ecs.write_resource::<Map>();
+    let idx = map.get_index(x, y);
+    if map.get_tile_type(x, y) != TileType::DOOR_CLOSED {
+        return false;
+    }
+    if let Some(lock) = map.locks.get(&idx).copied() {
+        let key = carried_key(ecs, id, lock);
+        match key {
+            Some(key) => {
+                map.unlock(idx);
+                let name = ecs.read_storage::<Name>().get(key).map_or("key".to_string(), |n| n.name.clone());
+                log_for(ecs, id, format!("You unlock the door with the {}.", name));
+            },
+            None => {
+                log_for(ecs, id, "The door is locked.".to_string());
+                return false;
+            }
+        }
+    }
+    map.set_tile_type(x, y, TileType::DOOR_OPEN);
+    log_for(ecs, id, "You open the door.".to_string());
+    true
+}
+
+/// Closes the open door at (x, y) unless something is standing in the doorway.
+pub fn close_door(ecs: &World, id: Entity, x: i32, y: i32) -> bool {
+    let mut map = ecs.write_resource::<Map>();
+    if !map.in_bounds(x, y) || map.get_tile_type(x, y) != TileType::DOOR_OPEN {
+        log_for(ecs, id, "There is no open door there.".to_string());
+        return false;
+    }
+    let in_the_way = ecs.read_storage::<Position>().join().any(|pos| pos.x == x && pos.y == y);
+    if in_the_way {
+        log_for(ecs, id, "Something is in the way.".to_string());
+        return false;
+    }
+    map.set_tile_type(x, y, TileType::DOOR_CLOSED);
+    log_for(ecs, id, "You close the door.".to_string());
+    true
+}
+
+// open doors next to a position, for closing without asking for a direction
+pub fn adjacent_open_doors(map: &Map, x: i32, y: i32) -> Vec<(i32, i32)> {
+    let mut doors = Vec::new();
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            if (dx != 0 || dy != 0) && map.in_bounds(x + dx, y + dy) && map.get_tile_type(x + dx, y + dy) == TileType::DOOR_OPEN {
+                doors.push((x + dx, y + dy));
+            }
+        }
+    }
+    doors
+}
+
+/// Keys are picked up just by walking over them.
+pub fn pick_up_keys(ecs: &World, id: Entity) {
+    let here = match ecs.read_storage::<Position>().get(id) {
+        Some(pos) => (pos.x, pos.y),
+        None => return
+    };
+    let found: Vec<Entity> = {
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let items = ecs.read_storage::<Item>();
+        let keys = ecs.read_storage::<Unlocks>();
+        (&entities, &positions, &items, &keys).join()
+            .filter(|(_, pos, _, _)| (pos.x, pos.y) == here)
+            .map(|(ent, _, _, _)| ent)
+            .collect()
+    };
+    for key in found {
+        ecs.write_storage::<Position>().remove(key);
+        ecs.write_storage::<Submerged>().remove(key);
+        ecs.write_storage::<Carried>().insert(key, Carried{owner: id}).expect("picked up a dead entity");
+        let name = ecs.read_storage::<Name>().get(key).map_or("key".to_string(), |n| n.name.clone());
+        log_for(ecs, id, format!("You pick up the {}.", name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathfinding::astar_search;
+    use specs::{Builder, World, WorldExt};
+
+    // a wall across the map at y = 5 with a single closed door in it
+    fn walled_map() -> Map {
+        let mut map = Map::new(20, 10);
+        for x in 1..19 {
+            map.set_tile_type(x, 5, TileType::WALL);
+        }
+        map.set_tile_type(10, 5, TileType::DOOR_CLOSED);
+        map.set_tile_blocked();
+        map
+    }
+
+    #[test]
+    fn only_door_openers_path_through_closed_doors() {
+        let mut map = walled_map();
+        let (start, end) = (map.get_index(3, 2), map.get_index(3, 8));
+        assert!(astar_search(start, end, &map, true).success);
+        assert!(!astar_search(start, end, &map, false).success);
+
+        map.locks.insert(map.get_index(10, 5), 7);
+        assert!(!astar_search(start, end, &map, true).success);
+    }
+
+    #[test]
+    fn locked_doors_need_the_matching_key() {
+        let mut ecs = World::new();
+        ecs.register::<Position>();
+        ecs.register::<Item>();
+        ecs.register::<Name>();
+        ecs.register::<Unlocks>();
+        ecs.register::<Carried>();
+        ecs.register::<Player>();
+        ecs.register::<Submerged>();
+        let mut map = walled_map();
+        map.locks.insert(map.get_index(10, 5), 7);
+        ecs.insert(map);
+        ecs.insert(GameLog::default());
+        let player = ecs.create_entity().with(Position{x: 10, y: 4}).with(Player{}).build();
+        ecs.create_entity().with(Position{x: 10, y: 4}).with(Item{}).with(Unlocks{lock: 3}).build();
+
+        pick_up_keys(&ecs, player);
+        assert!(!open_door(&ecs, player, 10, 5));
+
+        ecs.create_entity().with(Position{x: 10, y: 4}).with(Item{}).with(Unlocks{lock: 7}).build();
+        pick_up_keys(&ecs, player);
+        assert!(open_door(&ecs, player, 10, 5));
+        assert_eq!(ecs.fetch::<Map>().get_tile_type(10, 5), TileType::DOOR_OPEN);
+        assert!(!ecs.fetch::<Map>().is_locked(ecs.fetch::<Map>().get_index(10, 5)));
+
+        // can't shut it on someone standing in the doorway
+        let blocker = ecs.create_entity().with(Position{x: 10, y: 5}).build();
+        assert!(!close_door(&ecs, player, 10, 5));
+        ecs.delete_entity(blocker).unwrap();
+        assert!(close_door(&ecs, player, 10, 5));
+    }
+}
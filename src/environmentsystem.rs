@@ -0,0 +1,407 @@
+use crate::components::{Position, Health, Item, Flammable, Confused, StatusEffects, Player, Name, Burning, Submerged, Swimmer, Floats};
+use crate::gamelog::GameLog;
+use crate::map::{Map, TileType, Gas, GasKind, add_gas};
+use crate::turnsystem::Clock;
+use crate::stats::RunStats;
+use crate::rng::GameRng;
+use rand::Rng;
+use specs::{System, Entities, Entity, ReadStorage, WriteStorage, Read, Write, WriteExpect};
+use std::collections::HashSet;
+
+/*
+Spread fire across flammable tiles and items and drift gas clouds around until they thin out,
+and let water put out the burning, drown the unwary, float some items away and sink the rest
+*/
+
+// the simulation steps once a turn at normal speed rather than every tick
+pub const TICKS_PER_STEP: u32 = 5;
+// turns a tile burns for once it catches
+pub const BURN_TURNS: u8 = 4;
+// chance each turn that a fire spreads to each flammable neighbour
+const SPREAD_CHANCE: f64 = 0.3;
+const FIRE_DAMAGE: i32 = 2;
+// smoke given off every turn by each burning tile
+const SMOKE_PER_FIRE: u8 = 3;
+const POISON_DAMAGE: i32 = 1;
+const CONFUSION_TURNS: i32 = 4;
+// chance each turn in deep water that a non-swimmer goes under
+const DROWN_CHANCE: f64 = 0.2;
+const DROWN_DAMAGE: i32 = 2;
+// chance each turn that something floating drifts to another water tile
+const DRIFT_CHANCE: f64 = 0.5;
+
+pub struct EnvironmentSystem;
+
+impl<'a> System<'a> for EnvironmentSystem {
+    type SystemData = (Entities<'a>,
+                       WriteStorage<'a, Position>,
+                       ReadStorage<'a, Item>,
+                       ReadStorage<'a, Flammable>,
+                       ReadStorage<'a, Floats>,
+                       ReadStorage<'a, Swimmer>,
+                       ReadStorage<'a, Player>,
+                       ReadStorage<'a, Name>,
+                       WriteStorage<'a, Health>,
+                       WriteStorage<'a, Confused>,
+                       WriteStorage<'a, Burning>,
+                       WriteStorage<'a, Submerged>,
+                       WriteStorage<'a, StatusEffects>,
+                       Read<'a, Clock>,
+                       Write<'a, GameLog>,
+                       WriteExpect<'a, GameRng>,
+                       WriteExpect<'a, Map>,
+                       Option<Write<'a, RunStats>>);
+
+    fn run(&mut self, (entities, mut pos, item, flammable, floats, swimmer, player, name, mut health, mut confused, mut burning, mut submerged, mut status,
+                       clock, mut log, mut rng, mut map, mut stats): Self::SystemData) {
+        use specs::Join;
+        if clock.tick % TICKS_PER_STEP != 0 {
+            return;
+        }
+
+        // confusion wears off before anyone breathes in more of it
+        let mut recovered: Vec<Entity> = Vec::new();
+        for (ent, confusion) in (&entities, &mut confused).join() {
+            confusion.turns -= 1;
+            if confusion.turns <= 0 {
+                recovered.push(ent);
+            }
+        }
+        for ent in recovered {
+            confused.remove(ent);
+            remove_status(&mut status, ent, "confused");
+            if player.contains(ent) {
+                log.log("Your head clears.");
+            }
+        }
+
+        // whatever is standing in the fire, water or gas
+        for (ent, pos, health) in (&entities, &pos, &mut health).join() {
+            let is_player = player.contains(ent);
+            let depth = map.get_tile_type(pos.x, pos.y).def().depth;
+            if map.is_burning(pos.x, pos.y) {
+                if is_player && !burning.contains(ent) {
+                    log.log("You catch fire!");
+                }
+                burning.insert(ent, Burning{turns: BURN_TURNS as i32}).expect("set a dead entity alight");
+                add_status(&mut status, ent, "burning");
+            }
+            if depth > 0 && burning.remove(ent).is_some() {
+                remove_status(&mut status, ent, "burning");
+                if is_player {
+                    log.log("You douse the flames.");
+                }
+            }
+            if let Some(fire) = burning.get_mut(ent) {
+                health.current -= FIRE_DAMAGE;
+                fire.turns -= 1;
+                if is_player {
+                    log.log("You are burned by the flames!");
+                    if let Some(stats) = stats.as_mut() {
+                        stats.hurt(FIRE_DAMAGE, "burned to death");
+                    }
+                }
+                if fire.turns <= 0 {
+                    burning.remove(ent);
+                    remove_status(&mut status, ent, "burning");
+                }
+            }
+            if depth > 1 && !swimmer.contains(ent) && rng.0.gen_bool(DROWN_CHANCE) {
+                health.current -= DROWN_DAMAGE;
+                if is_player {
+                    log.log("You go under and swallow a lungful of water!");
+                    if let Some(stats) = stats.as_mut() {
+                        stats.hurt(DROWN_DAMAGE, "drowned");
+                    }
+                }
+            }
+            match map.gas_at(pos.x, pos.y) {
+                Some(Gas{kind: GasKind::Poison, ..}) => {
+                    health.current -= POISON_DAMAGE;
+                    if is_player {
+                        log.log("You choke on the poison gas!");
+                        if let Some(stats) = stats.as_mut() {
+                            stats.hurt(POISON_DAMAGE, "choked on poison gas");
+                        }
+                    }
+                },
+                Some(Gas{kind: GasKind::Confusion, ..}) => {
+                    if is_player && !confused.contains(ent) {
+                        log.log("Your head swims.");
+                    }
+                    confused.insert(ent, Confused{turns: CONFUSION_TURNS}).expect("confused a dead entity");
+                    add_status(&mut status, ent, "confused");
+                },
+                _ => {}
+            }
+        }
+
+        // floating items drift about on the water, everything else sinks in deep water
+        let mut sunk: Vec<Entity> = Vec::new();
+        for (ent, pos, _, _) in (&entities, &mut pos, &item, !&submerged).join() {
+            let depth = map.get_tile_type(pos.x, pos.y).def().depth;
+            if floats.contains(ent) {
+                if depth > 0 && rng.0.gen_bool(DRIFT_CHANCE) {
+                    let idx = map.get_index(pos.x, pos.y);
+                    let water: Vec<usize> = neighbours(&map, idx).into_iter().filter(|n| tile_at(&map, *n).def().depth > 0).collect();
+                    if !water.is_empty() {
+                        let next = water[rng.0.gen_range(0..water.len())];
+                        pos.x = next as i32 % map.width;
+                        pos.y = next as i32 / map.width;
+                    }
+                }
+            } else if depth > 1 {
+                if map.tile_in_view(pos.x, pos.y) {
+                    log.log(format!("The {} sinks out of sight.", name.get(ent).map_or("item", |n| n.name.as_str())));
+                }
+                sunk.push(ent);
+            }
+        }
+        for ent in sunk {
+            submerged.insert(ent, Submerged{}).expect("sank a dead item");
+        }
+
+        // flammable items burn up in a fire, and give one something to spread to
+        let mut fuel: HashSet<usize> = HashSet::new();
+        for (ent, pos, _, _) in (&entities, &pos, &item, &flammable).join() {
+            let idx = map.get_index(pos.x, pos.y);
+            if map.fire[idx] > 0 {
+                if map.tile_in_view(pos.x, pos.y) {
+                    log.log(format!("The {} burns up.", name.get(ent).map_or("item", |n| n.name.as_str())));
+                }
+                entities.delete(ent).expect("burned a dead item");
+            } else {
+                fuel.insert(idx);
+            }
+        }
+
+        // fire, worked out from last turn's fires so that tiles are visited in a fixed order
+        let mut fire = map.fire.clone();
+        let mut burnt_out: Vec<usize> = Vec::new();
+        for idx in 0..fire.len() {
+            if map.fire[idx] == 0 {
+                continue;
+            }
+            fire[idx] -= 1;
+            add_gas(&mut map.gas[idx], GasKind::Smoke, SMOKE_PER_FIRE);
+            for next in neighbours(&map, idx) {
+                let def = tile_at(&map, next).def();
+                let catches = def.depth == 0 && (def.flammable || fuel.contains(&next));
+                if catches && fire[next] == 0 && rng.0.gen_bool(SPREAD_CHANCE) {
+                    fire[next] = BURN_TURNS;
+                }
+            }
+            if fire[idx] == 0 && tile_at(&map, idx).def().flammable {
+                burnt_out.push(idx);
+            }
+        }
+        map.fire = fire;
+        let ash = TileType::named("ash").unwrap_or(TileType::GROUND);
+        for idx in burnt_out {
+            let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+            map.set_tile_type(x, y, ash);
+            // nothing left of a burnt door to lock
+            map.unlock(idx);
+        }
+
+        // gas drifts into open neighbours then thins out a little
+        let mut gas: Vec<Option<Gas>> = vec![None; map.gas.len()];
+        for idx in 0..gas.len() {
+            if let Some(cloud) = map.gas[idx] {
+                let open: Vec<usize> = neighbours(&map, idx).into_iter().filter(|n| tile_at(&map, *n).def().transparent).collect();
+                let share = cloud.density / 5;
+                if share > 0 {
+                    for next in open.iter() {
+                        add_gas(&mut gas[*next], cloud.kind, share);
+                    }
+                }
+                add_gas(&mut gas[idx], cloud.kind, cloud.density - share * open.len() as u8);
+            }
+        }
+        for cell in gas.iter_mut() {
+            if let Some(cloud) = cell {
+                cloud.density = cloud.density.saturating_sub(1);
+                if cloud.density == 0 {
+                    *cell = None;
+                }
+            }
+        }
+        map.gas = gas;
+    }
+}
+
+fn add_status(status: &mut WriteStorage<StatusEffects>, ent: Entity, effect: &str) {
+    if let Some(status) = status.get_mut(ent).filter(|s| !s.effects.iter().any(|e| e == effect)) {
+        status.effects.push(effect.to_string());
+    }
+}
+
+fn remove_status(status: &mut WriteStorage<StatusEffects>, ent: Entity, effect: &str) {
+    if let Some(status) = status.get_mut(ent) {
+        status.effects.retain(|e| e != effect);
+    }
+}
+
+fn tile_at(map: &Map, idx: usize) -> TileType {
+    map.get_tile_type(idx as i32 % map.width, idx as i32 / map.width)
+}
+
+// up, down, left and right, where they're on the map
+fn neighbours(map: &Map, idx: usize) -> Vec<usize> {
+    let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+    [(x, y - 1), (x, y + 1), (x - 1, y), (x + 1, y)].iter()
+        .filter(|(nx, ny)| map.in_bounds(*nx, *ny))
+        .map(|(nx, ny)| map.get_index(*nx, *ny))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::{World, WorldExt, Builder, RunNow};
+
+    fn meadow(seed: u64) -> World {
+        let mut ecs = World::new();
+        ecs.register::<Position>();
+        ecs.register::<Item>();
+        ecs.register::<Flammable>();
+        ecs.register::<Player>();
+        ecs.register::<Name>();
+        ecs.register::<Health>();
+        ecs.register::<Confused>();
+        ecs.register::<StatusEffects>();
+        ecs.register::<Burning>();
+        ecs.register::<Submerged>();
+        ecs.register::<Swimmer>();
+        ecs.register::<Floats>();
+        let mut map = Map::new(20, 12);
+        let grass = TileType::named("grass").unwrap();
+        for y in 1..11 {
+            for x in 1..19 {
+                map.set_tile_type(x, y, grass);
+            }
+        }
+        map.ignite(10, 6, BURN_TURNS);
+        ecs.insert(map);
+        ecs.insert(Clock::default());
+        ecs.insert(GameLog::default());
+        ecs.insert(GameRng::new(seed));
+        ecs
+    }
+
+    fn step(ecs: &mut World, turns: u32) {
+        for _ in 0..turns {
+            ecs.write_resource::<Clock>().tick += TICKS_PER_STEP;
+            EnvironmentSystem{}.run_now(ecs);
+            ecs.maintain();
+        }
+    }
+
+    fn terrain(map: &Map) -> Vec<TileType> {
+        (0..map.height).flat_map(|y| (0..map.width).map(move |x| (x, y))).map(|(x, y)| map.get_tile_type(x, y)).collect()
+    }
+
+    #[test]
+    fn same_seed_burns_the_same_way() {
+        let (mut first, mut second) = (meadow(7), meadow(7));
+        step(&mut first, 12);
+        step(&mut second, 12);
+        let (first, second) = (first.fetch::<Map>(), second.fetch::<Map>());
+        assert_eq!(first.fire, second.fire);
+        assert_eq!(first.gas, second.gas);
+        assert_eq!(terrain(&first), terrain(&second));
+        assert_eq!(first.get_tile_type(10, 6), TileType::named("ash").unwrap());
+    }
+
+    #[test]
+    fn fire_burns_items_and_hurts_whoever_stands_in_it() {
+        let mut ecs = meadow(1);
+        let scroll = ecs.create_entity().with(Position{x: 10, y: 6}).with(Item{}).with(Flammable{}).build();
+        let victim = ecs.create_entity().with(Position{x: 10, y: 6}).with(Health{current: 10, max: 10}).build();
+        step(&mut ecs, 1);
+        assert!(!ecs.is_alive(scroll));
+        assert_eq!(ecs.read_storage::<Health>().get(victim).unwrap().current, 10 - FIRE_DAMAGE);
+    }
+
+    #[test]
+    fn smoke_blocks_vision_until_it_clears() {
+        let mut ecs = meadow(1);
+        {
+            let mut map = ecs.write_resource::<Map>();
+            map.fire = vec![0; map.fire.len()];
+            map.release_gas(5, 6, GasKind::Smoke, 30);
+            let seen = map.get_tiles_in_view(2, 6);
+            assert!(!seen.iter().any(|tile| (tile.x, tile.y) == (8, 6)));
+        }
+        step(&mut ecs, 30);
+        let mut map = ecs.write_resource::<Map>();
+        assert!(map.gas.iter().all(|cell| cell.is_none()));
+        let seen = map.get_tiles_in_view(2, 6);
+        assert!(seen.iter().any(|tile| (tile.x, tile.y) == (8, 6)));
+    }
+
+    // the meadow with its fire out and a deep pool from (5, 3) to (9, 8)
+    fn pool(seed: u64) -> World {
+        let ecs = meadow(seed);
+        {
+            let mut map = ecs.write_resource::<Map>();
+            map.fire = vec![0; map.fire.len()];
+            for y in 3..9 {
+                for x in 5..10 {
+                    map.set_tile_type(x, y, TileType::DEEP_WATER);
+                }
+            }
+        }
+        ecs
+    }
+
+    fn health(ecs: &World, id: Entity) -> i32 {
+        ecs.read_storage::<Health>().get(id).unwrap().current
+    }
+
+    #[test]
+    fn water_puts_out_the_burning() {
+        let mut ecs = pool(1);
+        let torch = ecs.create_entity().with(Position{x: 7, y: 5})
+                                       .with(Health{current: 10, max: 10})
+                                       .with(Swimmer{})
+                                       .with(Burning{turns: 5})
+                                       .with(StatusEffects{effects: vec!["burning".to_string()]}).build();
+        step(&mut ecs, 1);
+        assert!(!ecs.read_storage::<Burning>().contains(torch));
+        assert!(ecs.read_storage::<StatusEffects>().get(torch).unwrap().effects.is_empty());
+        assert_eq!(health(&ecs, torch), 10);
+    }
+
+    #[test]
+    fn only_non_swimmers_drown_and_a_seed_always_drowns_them_the_same() {
+        let drown = |seed| {
+            let mut ecs = pool(seed);
+            let sinker = ecs.create_entity().with(Position{x: 7, y: 5}).with(Health{current: 100, max: 100}).build();
+            let swimmer = ecs.create_entity().with(Position{x: 6, y: 5}).with(Health{current: 100, max: 100}).with(Swimmer{}).build();
+            step(&mut ecs, 20);
+            (health(&ecs, sinker), health(&ecs, swimmer))
+        };
+        let (sinker, swimmer) = drown(3);
+        assert_eq!(swimmer, 100);
+        // going under some turns but not every one of them
+        assert!(sinker < 100 && sinker > 100 - 20 * DROWN_DAMAGE);
+        assert_eq!((100 - sinker) % DROWN_DAMAGE, 0);
+        assert_eq!(drown(3), (sinker, swimmer));
+    }
+
+    #[test]
+    fn floating_items_drift_on_the_water_and_the_rest_sink() {
+        let mut ecs = pool(5);
+        let raft = ecs.create_entity().with(Position{x: 7, y: 5}).with(Item{}).with(Floats{}).build();
+        let stone = ecs.create_entity().with(Position{x: 7, y: 6}).with(Item{}).build();
+        step(&mut ecs, 10);
+        assert!(ecs.read_storage::<Submerged>().contains(stone));
+        assert_eq!(ecs.read_storage::<Position>().get(stone).map(|pos| (pos.x, pos.y)), Some((7, 6)));
+
+        assert!(!ecs.read_storage::<Submerged>().contains(raft));
+        let drifted = ecs.read_storage::<Position>().get(raft).map(|pos| (pos.x, pos.y)).unwrap();
+        assert_ne!(drifted, (7, 5));
+        assert!(ecs.fetch::<Map>().get_tile_type(drifted.0, drifted.1).def().depth > 0);
+    }
+}
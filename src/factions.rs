@@ -0,0 +1,274 @@
+use crate::components::{Faction, Player, Companion, Peaceful};
+use crate::gamelog::GameLog;
+use specs::{Entity, World, WorldExt};
+use std::collections::HashMap;
+use std::fs;
+
+/*
+Who fights whom: how factions get along with each other, and how each of them feels about the player
+*/
+
+const DEFAULT_FACTIONS: &str = include_str!("../data/factions.cfg");
+const FACTIONS_PATH: &str = "data/factions.cfg";
+
+// standing below this and a faction's members attack the player on sight
+pub const HOSTILE_BELOW: i32 = -20;
+// and from this up they count the player as one of their own
+pub const ALLIED_FROM: i32 = 50;
+// standing lost with a faction each time the player attacks one of its members
+const ATTACK_PENALTY: i32 = 20;
+// standing gained with a faction when the player kills one of its enemies
+const KILL_BONUS: i32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stance {
+    Hostile,
+    Neutral,
+    Allied
+}
+
+impl Stance {
+    fn from_standing(standing: i32) -> Stance {
+        if standing < HOSTILE_BELOW {
+            Stance::Hostile
+        } else if standing >= ALLIED_FROM {
+            Stance::Allied
+        } else {
+            Stance::Neutral
+        }
+    }
+
+    // how it reads on the examine panel, i.e. "One of the orcs, hostile to you."
+    pub fn describe(self) -> &'static str {
+        match self {
+            Stance::Hostile => "hostile to you",
+            Stance::Neutral => "indifferent to you",
+            Stance::Allied => "friendly to you"
+        }
+    }
+}
+
+/// The faction table, and the player's standing with each faction, which changes as they play.
+#[derive(Debug)]
+pub struct Factions {
+    // between two factions, stored both ways round
+    relations: HashMap<(String, String), Stance>,
+    standing: HashMap<String, i32>
+}
+
+fn list(value: &str) -> Vec<String> {
+    value.split(',').map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect()
+}
+
+impl Factions {
+    /// Parses `[faction]` sections of `player`, `hostile` and `allied` lines.
+    pub fn parse(text: &str) -> Result<Factions, String> {
+        let mut standing = HashMap::new();
+        let mut listed: Vec<(String, String, Stance, usize)> = Vec::new();
+        let mut current: Option<String> = None;
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                let name = name.trim().to_string();
+                if standing.insert(name.clone(), 0).is_some() {
+                    return Err(format!("line {}: faction '{}' is defined twice", line_no + 1, name));
+                }
+                current = Some(name);
+                continue;
+            }
+            let (field, value) = line.split_once('=').ok_or(format!("line {}: expected 'field = value'", line_no + 1))?;
+            let faction = current.clone().ok_or(format!("line {}: field outside of a [faction] section", line_no + 1))?;
+            let value = value.trim();
+            match field.trim() {
+                "player" => {
+                    let start: i32 = value.parse().map_err(|_| format!("line {}: 'player' should be a number", line_no + 1))?;
+                    standing.insert(faction, start.clamp(-100, 100));
+                },
+                "hostile" => listed.extend(list(value).into_iter().map(|other| (faction.clone(), other, Stance::Hostile, line_no + 1))),
+                "allied" => listed.extend(list(value).into_iter().map(|other| (faction.clone(), other, Stance::Allied, line_no + 1))),
+                other => return Err(format!("line {}: unknown field '{}'", line_no + 1, other))
+            }
+        }
+
+        let mut relations = HashMap::new();
+        for (faction, other, stance, line_no) in listed {
+            if !standing.contains_key(&other) {
+                return Err(format!("line {}: unknown faction '{}'", line_no, other));
+            }
+            // hostility wins if the two sides disagree
+            if relations.get(&(faction.clone(), other.clone())) == Some(&Stance::Hostile) {
+                continue;
+            }
+            relations.insert((faction.clone(), other.clone()), stance);
+            relations.insert((other, faction), stance);
+        }
+        Ok(Factions{relations, standing})
+    }
+
+    /// Loads data/factions.cfg if it's there and valid, otherwise the copy built into the game.
+    pub fn load() -> Factions {
+        match fs::read_to_string(FACTIONS_PATH).map_err(|e| e.to_string()).and_then(|text| Factions::parse(&text)) {
+            Ok(factions) => factions,
+            Err(err) => {
+                eprintln!("could not load {}, using built-in factions: {}", FACTIONS_PATH, err);
+                Factions::builtin()
+            }
+        }
+    }
+
+    pub fn builtin() -> Factions {
+        Factions::parse(DEFAULT_FACTIONS).expect("built-in factions are invalid")
+    }
+
+    pub fn exists(&self, faction: &str) -> bool {
+        self.standing.contains_key(faction)
+    }
+
+    pub fn between(&self, a: &str, b: &str) -> Stance {
+        if a == b {
+            return Stance::Allied;
+        }
+        self.relations.get(&(a.to_string(), b.to_string())).copied().unwrap_or(Stance::Neutral)
+    }
+
+    pub fn standing(&self, faction: &str) -> i32 {
+        self.standing.get(faction).copied().unwrap_or(0)
+    }
+
+    pub fn towards_player(&self, faction: &str) -> Stance {
+        Stance::from_standing(self.standing(faction))
+    }
+
+    /// Changes the player's standing with `faction`, returning the stance it leaves them in if that changed.
+    pub fn shift(&mut self, faction: &str, amount: i32) -> Option<Stance> {
+        let before = self.towards_player(faction);
+        let standing = self.standing.entry(faction.to_string()).or_insert(0);
+        *standing = (*standing + amount).clamp(-100, 100);
+        let after = self.towards_player(faction);
+        if before != after { Some(after) } else { None }
+    }
+
+    // every faction that fights `faction` on sight
+    fn enemies_of(&self, faction: &str) -> Vec<String> {
+        self.standing.keys().filter(|other| self.between(faction, other) == Stance::Hostile).cloned().collect()
+    }
+}
+
+pub fn faction_of(ecs: &World, id: Entity) -> Option<String> {
+    ecs.read_storage::<Faction>().get(id).map(|f| f.name.clone())
+}
+
+/// How `a` feels about `b`. Companions are on the player's side, anyone else without a faction keeps to themselves,
+/// and the peaceful leave the player's side alone whatever their faction.
+pub fn stance(ecs: &World, a: Entity, b: Entity) -> Stance {
+    let players = ecs.read_storage::<Player>();
+    let companions = ecs.read_storage::<Companion>();
+    let on_players_side = |id: Entity| players.contains(id) || companions.contains(id);
+    if on_players_side(a) && on_players_side(b) {
+        return Stance::Allied;
+    }
+    let peaceful = ecs.read_storage::<Peaceful>();
+    if (peaceful.contains(a) && on_players_side(b)) || (on_players_side(a) && peaceful.contains(b)) {
+        return Stance::Neutral;
+    }
+    let factions = ecs.fetch::<Factions>();
+    match (faction_of(ecs, a), faction_of(ecs, b)) {
+        (Some(faction), None) if on_players_side(b) => factions.towards_player(&faction),
+        (None, Some(faction)) if on_players_side(a) => factions.towards_player(&faction),
+        (Some(a), Some(b)) => factions.between(&a, &b),
+        _ => Stance::Neutral
+    }
+}
+
+/// Shifts the player's standing with `faction`, telling them when it changes how the faction treats them.
+pub fn shift_standing(ecs: &World, faction: &str, amount: i32) {
+    let changed = ecs.write_resource::<Factions>().shift(faction, amount);
+    let message = match changed {
+        Some(Stance::Hostile) => format!("The {} now count you as an enemy.", faction),
+        Some(Stance::Neutral) => format!("The {} no longer care much about you either way.", faction),
+        Some(Stance::Allied) => format!("The {} now count you as a friend.", faction),
+        None => return
+    };
+    ecs.write_resource::<GameLog>().log(message);
+}
+
+/// Makes `faction` hostile to the player if it wasn't already.
+pub fn provoke(ecs: &World, faction: &str) {
+    let standing = ecs.fetch::<Factions>().standing(faction);
+    if standing >= HOSTILE_BELOW {
+        shift_standing(ecs, faction, HOSTILE_BELOW - 1 - standing);
+    }
+}
+
+/// The player attacked `victim`, its faction won't forget it.
+pub fn attacked(ecs: &World, victim: Entity) {
+    if let Some(faction) = faction_of(ecs, victim) {
+        shift_standing(ecs, &faction, -ATTACK_PENALTY);
+    }
+}
+
+/// The player killed `victim`, which goes down well with its enemies.
+pub fn killed(ecs: &World, victim: Entity) {
+    if let Some(faction) = faction_of(ecs, victim) {
+        let enemies = ecs.fetch::<Factions>().enemies_of(&faction);
+        for enemy in enemies {
+            shift_standing(ecs, &enemy, KILL_BONUS);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::{Builder, World, WorldExt};
+
+    #[test]
+    fn shipped_factions_parse_and_relations_are_symmetric() {
+        let factions = Factions::parse(DEFAULT_FACTIONS).unwrap();
+        assert_eq!(factions.between("orcs", "demons"), Stance::Hostile);
+        assert_eq!(factions.between("scribes", "demons"), Stance::Hostile);
+        assert_eq!(factions.between("orcs", "orcs"), Stance::Allied);
+        assert_eq!(factions.between("orcs", "scribes"), Stance::Neutral);
+        assert_eq!(factions.towards_player("orcs"), Stance::Hostile);
+        assert_eq!(factions.towards_player("scribes"), Stance::Neutral);
+
+        assert!(Factions::parse("[orcs]\nhostile = elves").unwrap_err().contains("unknown faction 'elves'"));
+    }
+
+    #[test]
+    fn standing_follows_what_the_player_does() {
+        let mut ecs = World::new();
+        ecs.register::<Faction>();
+        ecs.register::<Player>();
+        ecs.register::<Companion>();
+        ecs.register::<Peaceful>();
+        ecs.insert(GameLog::default());
+        ecs.insert(Factions::parse("[orcs]\nplayer = -10\nhostile = demons\n[demons]\nplayer = -100").unwrap());
+        let player = ecs.create_entity().with(Player{}).build();
+        let orc = ecs.create_entity().with(Faction{name: "orcs".to_string()}).build();
+        let imp = ecs.create_entity().with(Faction{name: "demons".to_string()}).build();
+        assert_eq!(stance(&ecs, orc, player), Stance::Neutral);
+        assert_eq!(stance(&ecs, orc, imp), Stance::Hostile);
+
+        // killing their enemies wins a neutral faction over, attacking them loses it again
+        for _ in 0..6 {
+            killed(&ecs, imp);
+        }
+        assert_eq!(stance(&ecs, player, orc), Stance::Allied);
+        attacked(&ecs, orc);
+        assert_eq!(stance(&ecs, player, orc), Stance::Neutral);
+        provoke(&ecs, "orcs");
+        assert_eq!(stance(&ecs, orc, player), Stance::Hostile);
+
+        // a peaceful orc keeps out of it until it's made hostile
+        let shaman = ecs.create_entity().with(Faction{name: "orcs".to_string()}).with(Peaceful{}).build();
+        assert_eq!(stance(&ecs, shaman, player), Stance::Neutral);
+        assert_eq!(stance(&ecs, player, shaman), Stance::Neutral);
+        assert_eq!(stance(&ecs, shaman, imp), Stance::Hostile);
+        ecs.write_storage::<Peaceful>().remove(shaman);
+        assert_eq!(stance(&ecs, shaman, player), Stance::Hostile);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::map::{Map, TileType};
+use crate::components::{Position, Name, Carried, Companion, PathFollower};
+use crate::allies::{self, Order};
+use crate::combat::LastTarget;
+use crate::hpastar::ClusterGraph;
+use crate::gamelog::GameLog;
+use crate::player;
+use std::collections::HashSet;
+use specs::{Entity, World, WorldExt, Join};
+
+/*
+Climbing the tower: the stairs up, and who and what comes along to the next floor
+*/
+
+// how far from where the player arrives companions can be set down
+const LANDING_RADIUS: i32 = 2;
+
+fn on_stairs(ecs: &World, id: Entity) -> bool {
+    let stairs = TileType::named("stairs_up");
+    let map = ecs.fetch::<Map>();
+    ecs.read_storage::<Position>().get(id).is_some_and(|pos| stairs == Some(map.get_tile_type(pos.x, pos.y)))
+}
+
+// free ground around (x, y), nearest first
+fn landing(map: &Map, x: i32, y: i32) -> Vec<(i32, i32)> {
+    let mut tiles: Vec<(i32, i32)> = (-LANDING_RADIUS..=LANDING_RADIUS)
+        .flat_map(|dy| (-LANDING_RADIUS..=LANDING_RADIUS).map(move |dx| (x + dx, y + dy)))
+        .filter(|&(tx, ty)| (tx, ty) != (x, y) && map.in_bounds(tx, ty) && map.is_walkable(map.get_index(tx, ty)))
+        .collect();
+    tiles.sort_by_key(|&(tx, ty)| (tx - x).abs().max((ty - y).abs()));
+    tiles
+}
+
+/// Takes the player up the stairs they're standing on to a fresh floor, arriving at (x, y).
+/// Companions close enough to follow come too and are set down around them, along with anything
+/// the travellers carry. Everything else is left behind for good. Returns whether there was a way up.
+pub fn climb(ecs: &mut World, x: i32, y: i32) -> bool {
+    let player = *ecs.fetch::<Entity>();
+    if !on_stairs(ecs, player) {
+        ecs.write_resource::<GameLog>().log("There is no way up from here.");
+        return false;
+    }
+    let companions = allies::travelling_companions(ecs);
+    let travellers: HashSet<Entity> = companions.iter().copied().chain(std::iter::once(player)).collect();
+    let left_behind: Vec<Entity> = {
+        let entities = ecs.entities();
+        let carried = ecs.read_storage::<Carried>();
+        entities.join()
+            .filter(|ent| !travellers.contains(ent) && !carried.get(*ent).is_some_and(|c| travellers.contains(&c.owner)))
+            .collect()
+    };
+    ecs.delete_entities(&left_behind).expect("left something behind twice");
+    ecs.maintain();
+
+    let (width, height) = {
+        let map = ecs.fetch::<Map>();
+        (map.width, map.height)
+    };
+    let mut map = Map::new(width, height);
+    map.set_tile_blocked();
+    let mut free = landing(&map, x, y).into_iter();
+    ecs.insert(ClusterGraph::new(&map));
+    ecs.insert(map);
+
+    ecs.write_storage::<Position>().insert(player, Position{x, y}).expect("the player is dead");
+    for companion in companions.iter() {
+        let (cx, cy) = free.next().unwrap_or((x, y));
+        ecs.write_storage::<Position>().insert(*companion, Position{x: cx, y: cy}).expect("brought along a dead companion");
+        // paths and quarries from the floor below mean nothing here
+        ecs.write_storage::<PathFollower>().insert(*companion, PathFollower::default()).expect("brought along a dead companion");
+        if let Some(companion) = ecs.write_storage::<Companion>().get_mut(*companion) {
+            if let Order::Attack(_) = companion.order {
+                companion.order = Order::Follow;
+            }
+        }
+    }
+    ecs.write_resource::<Vec<Entity>>().clear();
+    if let Some(mut last) = ecs.try_fetch_mut::<LastTarget>() {
+        last.0 = None;
+    }
+    player::interrupt_activity(ecs);
+
+    ecs.write_resource::<GameLog>().log("You climb the stairs.");
+    for companion in companions {
+        let name = ecs.read_storage::<Name>().get(companion).map_or("companion".to_string(), |n| n.name.clone());
+        ecs.write_resource::<GameLog>().log(format!("Your {} follows you up.", name));
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Player, Item};
+    use crate::player::{Travel, AutoExplore, Repeat};
+    use specs::Builder;
+
+    #[test]
+    fn only_followers_close_by_and_what_they_carry_come_up_the_stairs() {
+        let mut ecs = World::new();
+        ecs.register::<Position>();
+        ecs.register::<Name>();
+        ecs.register::<Player>();
+        ecs.register::<Item>();
+        ecs.register::<Carried>();
+        ecs.register::<Companion>();
+        ecs.register::<PathFollower>();
+        ecs.insert(Map::new(80, 43));
+        ecs.insert(GameLog::default());
+        ecs.insert(Vec::<Entity>::new());
+        ecs.insert(Travel::default());
+        ecs.insert(AutoExplore::default());
+        ecs.insert(Repeat::default());
+        let player = ecs.create_entity().with(Position{x: 70, y: 4}).with(Player{}).build();
+        ecs.insert(player);
+        let hound = ecs.create_entity().with(Position{x: 71, y: 5}).with(Name{name: "hound".to_string()})
+                                       .with(Companion{order: Order::Follow}).build();
+        let cat = ecs.create_entity().with(Position{x: 69, y: 4}).with(Companion{order: Order::Stay}).build();
+        let key = ecs.create_entity().with(Item{}).with(Carried{owner: player}).build();
+        let orc = ecs.create_entity().with(Position{x: 72, y: 4}).build();
+        let charm = ecs.create_entity().with(Item{}).with(Carried{owner: orc}).build();
+
+        assert!(!climb(&mut ecs, 40, 25));
+        ecs.write_storage::<Position>().get_mut(player).unwrap().x = 74;
+        assert!(climb(&mut ecs, 40, 25));
+        assert!(ecs.is_alive(player) && ecs.is_alive(hound) && ecs.is_alive(key));
+        assert!(!ecs.is_alive(cat) && !ecs.is_alive(orc) && !ecs.is_alive(charm));
+
+        let positions = ecs.read_storage::<Position>();
+        assert_eq!(positions.get(player).map(|pos| (pos.x, pos.y)), Some((40, 25)));
+        let hound_pos = positions.get(hound).unwrap();
+        assert!((hound_pos.x - 40).abs() <= 1 && (hound_pos.y - 25).abs() <= 1);
+        assert_eq!(ecs.fetch::<GameLog>().entries.last().map(|e| e.text.as_str()), Some("Your hound follows you up."));
+    }
+}
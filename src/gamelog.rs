@@ -0,0 +1,34 @@
+use crate::language::Tongue;
+
+/*
+Messages shown to the player in the log panel under the map
+*/
+
+// a message, with anything said or written in another tongue kept apart so it can be shown
+// as well as the player understands it at the time
+pub struct LogEntry {
+    pub text: String,
+    pub speech: Option<(Tongue, String)>
+}
+
+#[derive(Default)]
+pub struct GameLog {
+    pub entries: Vec<LogEntry>
+}
+
+impl GameLog {
+    pub fn log<S: Into<String>>(&mut self, message: S) {
+        self.entries.push(LogEntry{text: message.into(), speech: None});
+    }
+
+    // i.e. `The orc says "..."` with the quote in Orcish
+    pub fn log_speech<S: Into<String>>(&mut self, message: S, tongue: Tongue, speech: &str) {
+        self.entries.push(LogEntry{text: message.into(), speech: Some((tongue, speech.to_string()))});
+    }
+
+    // newest last, at most `count` of them
+    pub fn recent(&self, count: usize) -> &[LogEntry] {
+        let start = self.entries.len().saturating_sub(count);
+        &self.entries[start..]
+    }
+}
@@ -1,9 +1,24 @@
 use tcod::colors::*;
 use tcod::console::*;
-use specs::{World, WorldExt};
-use super::{SCREEN_HEIGHT, SCREEN_WIDTH, Map};
-use crate::map::TileType;
-use crate::components::{Position, Sprite};
+use specs::{Entity, World, WorldExt};
+use super::{SCREEN_HEIGHT, SCREEN_WIDTH, MAP_HEIGHT, Map};
+use crate::map::GasKind;
+use crate::gamelog::GameLog;
+use crate::components::{Position, Sprite, Name, Health, Description, StatusEffects, Hidden, Submerged, Writing, Speaks, Faction, Experience, Character};
+use crate::factions::Factions;
+use crate::language;
+use crate::dialogue;
+use crate::progression;
+use crate::chargen::{Creation, Step};
+use crate::cutscene::{Frame, Wait};
+use crate::conlang::capitalise;
+use tcod::input::{self, Event, Key};
+use crate::keymap::Keymap;
+use crate::player::Repeat;
+use crate::turnsystem::Clock;
+use crate::morgue;
+use crate::scores::{History, Order};
+use std::path::PathBuf;
 
 
 
@@ -32,6 +47,69 @@ impl Menu {
     }
 }
 
+/// Drawing surface the game renders onto. Implemented by the libtcod window and by the
+/// in-memory `HeadlessRenderer` used in tests.
+pub trait Renderer {
+    fn put_char_ex(&mut self, x: i32, y: i32, glyph: char, foreground: Color, background: Color);
+    fn set_char_background(&mut self, x: i32, y: i32, background: Color);
+    fn print(&mut self, x: i32, y: i32, text: &str, alignment: TextAlignment);
+    fn clear(&mut self);
+    fn flush(&mut self);
+}
+
+/// Something the player did. Backends translate their native events into these (keys as tcod
+/// `Key`s, mouse positions in console cells) so the rest of the game only deals with one type.
+#[derive(Clone, Copy, Debug)]
+pub enum InputEvent {
+    Key(Key),
+    MouseMove{x: i32, y: i32},
+    MouseClick{x: i32, y: i32}
+}
+
+/// Source of input events.
+pub trait Input {
+    fn wait_for_event(&mut self) -> InputEvent;
+    // like wait_for_event, but gives up with None after `millis`
+    fn wait_for_event_timeout(&mut self, millis: u64) -> Option<InputEvent>;
+    fn window_closed(&self) -> bool;
+
+    // skips over mouse events, returns an empty key if the window closes while waiting
+    fn wait_for_keypress(&mut self) -> Key {
+        loop {
+            if let InputEvent::Key(key) = self.wait_for_event() {
+                return key;
+            }
+            if self.window_closed() {
+                return Key::default();
+            }
+        }
+    }
+
+    // same again, but only for `millis`, i.e. for cutscene frames that move on by themselves
+    fn wait_for_keypress_timeout(&mut self, millis: u64) -> Option<Key> {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(millis);
+        loop {
+            let left = deadline.saturating_duration_since(std::time::Instant::now()).as_millis() as u64;
+            match self.wait_for_event_timeout(left) {
+                Some(InputEvent::Key(key)) => return Some(key),
+                Some(_) if left > 0 && !self.window_closed() => {},
+                _ => return None
+            }
+        }
+    }
+}
+
+// cell the mouse is hovering over, if it has moved since the game started
+#[derive(Default)]
+pub struct MouseCursor {
+    pub position: Option<(i32, i32)>
+}
+
+/// A complete frontend: somewhere to draw and somewhere to read keys from.
+pub trait Frontend: Renderer + Input {}
+
+impl<T: Renderer + Input> Frontend for T {}
+
 impl Tcod {
 
     pub fn new() -> Self {
@@ -46,72 +124,589 @@ impl Tcod {
 
     return Tcod { root, con };
     }
+}
 
-    pub fn render_main_menu(&mut self, ecs: &mut  World) {
-        let menu = ecs.fetch::<Menu>();
+impl Renderer for Tcod {
+    fn put_char_ex(&mut self, x: i32, y: i32, glyph: char, foreground: Color, background: Color) {
+        self.con.put_char_ex(x, y, glyph, foreground, background);
+    }
+
+    fn set_char_background(&mut self, x: i32, y: i32, background: Color) {
+        self.con.set_char_background(x, y, background, BackgroundFlag::Set);
+    }
+
+    fn print(&mut self, x: i32, y: i32, text: &str, alignment: TextAlignment) {
+        self.con.set_default_foreground(WHITE);
+        self.con.print_ex(x, y, BackgroundFlag::None, alignment, text);
+    }
+
+    fn clear(&mut self) {
         self.con.set_default_background(BLACK);
         self.con.set_default_foreground(WHITE);
-        self.con.set_alignment(TextAlignment::Center);
         self.con.clear();
-        self.con.print(SCREEN_WIDTH/2, SCREEN_HEIGHT/2, "BABEL");
-        for (pos, option) in menu.options.iter().enumerate() {
-            self.con.print(SCREEN_WIDTH/2, SCREEN_HEIGHT/2 + 1 + pos as i32, option);
-        }
-        self.con.put_char(SCREEN_WIDTH/2 - 5, SCREEN_HEIGHT/2 + 1 + (menu.cursor_index as i32), '>', BackgroundFlag::None);
+    }
 
+    fn flush(&mut self) {
         blit( &self.con, (0, 0), (SCREEN_WIDTH, SCREEN_HEIGHT),
-        &mut self.root, (0, 0), 1.0, 1.0,);
+            &mut self.root, (0, 0), 1.0, 1.0,
+        );
         self.root.flush();
     }
+}
 
-    pub fn render_game(&mut self,  ecs: &mut World) {
-        // for map -> render all tiles in view
-        // for entities -> render all objects in view
-        self.con.set_default_foreground(WHITE);
-        self.con.clear();
+impl Input for Tcod {
+    fn wait_for_event(&mut self) -> InputEvent {
+        loop {
+            match input::check_for_event(input::KEY_PRESS | input::MOUSE) {
+                Some((_, Event::Key(key))) => return InputEvent::Key(key),
+                Some((_, Event::Mouse(mouse))) => {
+                    let (x, y) = (mouse.cx as i32, mouse.cy as i32);
+                    if mouse.lbutton_pressed {
+                        return InputEvent::MouseClick{x, y};
+                    }
+                    if mouse.dcx != 0 || mouse.dcy != 0 {
+                        return InputEvent::MouseMove{x, y};
+                    }
+                },
+                None => {
+                    if self.root.window_closed() {
+                        return InputEvent::Key(Key::default());
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+            }
+        }
+    }
 
-        // Render map
-        let current_level = ecs.write_resource::<Map>();
-        for tile in current_level.visited.iter() {
-          let tile_char: char;
-          let tile_color: Color;
-          match tile.tile_type {
-              TileType::Ground => {tile_char = '.'; tile_color = LIGHT_GREY},
-              TileType::Water => {tile_char = '.'; tile_color = BLUE},
-              TileType::Wall => {tile_char = 'X'; tile_color = LIGHT_GREY},
-          }
-          self.con.put_char_ex(tile.x, tile.y, tile_char, tile_color, BLACK);
+    fn wait_for_event_timeout(&mut self, millis: u64) -> Option<InputEvent> {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(millis);
+        loop {
+            match input::check_for_event(input::KEY_PRESS | input::MOUSE) {
+                Some((_, Event::Key(key))) => return Some(InputEvent::Key(key)),
+                Some((_, Event::Mouse(mouse))) if mouse.lbutton_pressed => return Some(InputEvent::MouseClick{x: mouse.cx as i32, y: mouse.cy as i32}),
+                _ => {
+                    if self.root.window_closed() || std::time::Instant::now() >= deadline {
+                        return None;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+            }
         }
+    }
 
-        // retrieve storages from ecs
-        let pos_store = ecs.read_storage::<Position>();
-        let sprite_store = ecs.read_storage::<Sprite>();  
+    fn window_closed(&self) -> bool {
+        self.root.window_closed()
+    }
+}
+
+pub fn render_main_menu<R: Renderer + ?Sized>(renderer: &mut R, ecs: &World) {
+    let menu = ecs.fetch::<Menu>();
+    renderer.clear();
+    renderer.print(SCREEN_WIDTH/2, SCREEN_HEIGHT/2, "BABEL", TextAlignment::Center);
+    for (pos, option) in menu.options.iter().enumerate() {
+        renderer.print(SCREEN_WIDTH/2, SCREEN_HEIGHT/2 + 1 + pos as i32, option, TextAlignment::Center);
+    }
+    renderer.put_char_ex(SCREEN_WIDTH/2 - 5, SCREEN_HEIGHT/2 + 1 + (menu.cursor_index as i32), '>', WHITE, BLACK);
+    renderer.flush();
+}
 
-        for tile in (*current_level).visible.iter() {
-            let tile_char: char;
-            let tile_color: Color;
-            match tile.tile_type {
-                TileType::Ground => {tile_char = '.'; tile_color = LIGHTEST_GREY},
-                TileType::Water => {tile_char = '.'; tile_color = LIGHT_BLUE},
-                TileType::Wall => {tile_char = 'X'; tile_color = WHITE},
+// left edge and width of the text on the character creation screens
+const CREATION_LEFT: i32 = 20;
+const CREATION_WIDTH: i32 = 40;
+
+/// Whichever step of making a new character the player is on.
+pub fn render_creation<R: Renderer + ?Sized>(renderer: &mut R, creation: &Creation) {
+    renderer.clear();
+    let (title, hint) = match creation.step {
+        Step::Background => ("Who were you, before the tower?", "Enter to choose, Escape for the main menu"),
+        Step::Name => ("What are you called?", "Leave it blank to be given a name"),
+        Step::Seed => ("Seed", "Type a seed to replay a game, or leave it blank"),
+        Step::Summary => ("Ready to climb?", "Enter to begin, Escape to go back")
+    };
+    renderer.print(SCREEN_WIDTH/2, 8, title, TextAlignment::Center);
+    let mut row = 12;
+    match creation.step {
+        Step::Background => {
+            for (index, option) in creation.menu.options.iter().enumerate() {
+                renderer.print(CREATION_LEFT + 2, row + index as i32, option, TextAlignment::Left);
+            }
+            renderer.put_char_ex(CREATION_LEFT, row + creation.menu.cursor_index as i32, '>', WHITE, BLACK);
+            row += creation.menu.options.len() as i32 + 1;
+            for line in wrap(creation.background().description(), CREATION_WIDTH as usize) {
+                renderer.print(CREATION_LEFT, row, &line, TextAlignment::Left);
+                row += 1;
+            }
+        },
+        Step::Name => renderer.print(CREATION_LEFT, row, &format!("{}_", creation.name), TextAlignment::Left),
+        Step::Seed => renderer.print(CREATION_LEFT, row, &format!("{}_", creation.seed), TextAlignment::Left),
+        Step::Summary => for line in creation.summary() {
+            for wrapped in wrap(&line, CREATION_WIDTH as usize) {
+                renderer.print(CREATION_LEFT, row, &wrapped, TextAlignment::Left);
+                row += 1;
+            }
+            // keep the blank lines between sections
+            if line.is_empty() {
+                row += 1;
             }
-            self.con.put_char_ex(tile.x, tile.y, tile_char, tile_color, BLACK);
         }
+    }
+    renderer.print(SCREEN_WIDTH/2, SCREEN_HEIGHT - 6, hint, TextAlignment::Center);
+    renderer.flush();
+}
+
+// the window a cutscene camera looks at the floor through, and how wide its text runs
+const CAMERA_WIDTH: i32 = 60;
+const CAMERA_HEIGHT: i32 = 21;
+const CUTSCENE_TEXT_WIDTH: i32 = 60;
+
+/// One frame of a cutscene, with the camera centred on `camera` if it has one.
+pub fn render_cutscene<R: Renderer + ?Sized>(renderer: &mut R, ecs: &World, frame: &Frame, camera: Option<(i32, i32)>) {
+    renderer.clear();
+    let text: Vec<String> = frame.text.iter().flat_map(|line| wrap(line, CUTSCENE_TEXT_WIDTH as usize)).collect();
+    let art_width = frame.art.iter().map(|line| line.chars().count() as i32).max().unwrap_or(0);
+    let height = if camera.is_some() { CAMERA_HEIGHT + 1 } else { 0 } + frame.art.len() as i32 + 1 + text.len() as i32;
+    let mut row = ((SCREEN_HEIGHT - height) / 2).max(1);
 
-            // Render visible entities
-            use specs::Join;
-            for (pos, sprite) in (&pos_store, &sprite_store).join() {
-                // TODO: Fix Map data structure to separate tiles from type
-                if (*current_level).tile_in_view(pos.x, pos.y){
-                self.con.put_char_ex(pos.x, pos.y, sprite.sprite, sprite.color, BLACK);
+    // the floor as it is, whether the player has seen it or not
+    if let Some((cx, cy)) = camera {
+        use specs::Join;
+        let map = ecs.fetch::<Map>();
+        let (left, top) = ((SCREEN_WIDTH - CAMERA_WIDTH) / 2, row);
+        let (ox, oy) = (cx - CAMERA_WIDTH / 2, cy - CAMERA_HEIGHT / 2);
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                if map.in_bounds(ox + x, oy + y) {
+                    let def = map.get_tile_type(ox + x, oy + y).def();
+                    renderer.put_char_ex(left + x, top + y, def.glyph, def.lit, BLACK);
                 }
             }
+        }
+        let positions = ecs.read_storage::<Position>();
+        let sprites = ecs.read_storage::<Sprite>();
+        let hidden = ecs.read_storage::<Hidden>();
+        let submerged = ecs.read_storage::<Submerged>();
+        for (pos, sprite, _, _) in (&positions, &sprites, !&hidden, !&submerged).join() {
+            let (x, y) = (pos.x - ox, pos.y - oy);
+            if (0..CAMERA_WIDTH).contains(&x) && (0..CAMERA_HEIGHT).contains(&y) {
+                renderer.put_char_ex(left + x, top + y, sprite.sprite, sprite.color, BLACK);
+            }
+        }
+        row += CAMERA_HEIGHT + 1;
+    }
+    for line in frame.art.iter() {
+        renderer.print((SCREEN_WIDTH - art_width) / 2, row, line, TextAlignment::Left);
+        row += 1;
+    }
+    row += 1;
+    for line in text.iter() {
+        renderer.print(SCREEN_WIDTH/2, row, line, TextAlignment::Center);
+        row += 1;
+    }
+    let hint = if frame.wait == Wait::Key { "Press any key, or Escape to skip" } else { "Escape to skip" };
+    renderer.print(SCREEN_WIDTH/2, SCREEN_HEIGHT - 2, hint, TextAlignment::Center);
+    renderer.flush();
+}
 
+/// How the run went, where it placed in the high scores and where its morgue file was written.
+pub fn render_game_over<R: Renderer + ?Sized>(renderer: &mut R, ecs: &World, won: bool, score: u32, written: &Result<PathBuf, String>, rank: &Result<usize, String>) {
+    renderer.clear();
+    renderer.print(SCREEN_WIDTH/2, 8, if won { "Victory" } else { "You have died" }, TextAlignment::Center);
+    let scored = match rank {
+        Ok(rank) => format!("Scored {} points, #{} in the high scores.", score, rank),
+        Err(err) => format!("Scored {} points, but the run could not be recorded: {}", score, err)
+    };
+    let saved = match written {
+        Ok(path) => format!("Morgue file written to {}", path.display()),
+        Err(err) => format!("Could not write the morgue file: {}", err)
+    };
+    let mut row = 12;
+    for line in morgue::summary(ecs, won).into_iter().chain([scored, saved]) {
+        for wrapped in wrap(&line, CREATION_WIDTH as usize) {
+            renderer.print(SCREEN_WIDTH/2, row, &wrapped, TextAlignment::Center);
+            row += 1;
+        }
+        row += 1;
+    }
+    renderer.print(SCREEN_WIDTH/2, SCREEN_HEIGHT - 3, "Press any key to return to the menu", TextAlignment::Center);
+    renderer.flush();
+}
 
-        blit( &self.con, (0, 0), (SCREEN_WIDTH, SCREEN_HEIGHT),
-            &mut self.root, (0, 0), 1.0, 1.0,
-        );
+// column headings over scores::Run::row, where the table starts and how many rows fit on the screen at once
+const SCORES_HEADER: &str = "  # Name                 Class     Floor  Score  Date";
+const SCORES_LEFT: i32 = 8;
+const SCORES_SHOWN: i32 = 30;
 
-        self.root.flush();
+/// Every recorded run, sorted by `order`, with more about the one under the cursor below.
+pub fn render_scores<R: Renderer + ?Sized>(renderer: &mut R, history: &History, order: Order, menu: &Menu) {
+    renderer.clear();
+    renderer.print(SCREEN_WIDTH/2, 2, &format!("High scores, by {}", order.label()), TextAlignment::Center);
+    if history.runs.is_empty() {
+        renderer.print(SCREEN_WIDTH/2, 8, "No runs have been finished yet.", TextAlignment::Center);
+    } else {
+        renderer.print(SCORES_LEFT, 5, SCORES_HEADER, TextAlignment::Left);
+        // scroll so the cursor stays on screen
+        let first = (menu.cursor_index as i32 - SCORES_SHOWN + 1).max(0);
+        for (index, option) in menu.options.iter().enumerate().skip(first as usize).take(SCORES_SHOWN as usize) {
+            renderer.print(SCORES_LEFT, 7 + index as i32 - first, option, TextAlignment::Left);
+        }
+        renderer.put_char_ex(SCORES_LEFT - 2, 7 + menu.cursor_index as i32 - first, '>', WHITE, BLACK);
+        if let Some(run) = history.runs.get(menu.cursor_index as usize) {
+            let row = 8 + SCORES_SHOWN;
+            renderer.print(SCORES_LEFT, row, &format!("{} the {} {}.", run.name, run.background, run.cause), TextAlignment::Left);
+            renderer.print(SCORES_LEFT, row + 1, &format!("Seed {}, version {}", run.seed, run.version), TextAlignment::Left);
+        }
+    }
+    renderer.print(SCREEN_WIDTH/2, SCREEN_HEIGHT - 3, "Enter to replay a seed, Left/Right to sort, Escape to return", TextAlignment::Center);
+    renderer.flush();
+}
+
+pub fn render_game<R: Renderer + ?Sized>(renderer: &mut R, ecs: &World) {
+    draw_game(renderer, ecs);
+    renderer.flush();
+}
+
+fn draw_game<R: Renderer + ?Sized>(renderer: &mut R, ecs: &World) {
+    // for map -> render all tiles in view
+    // for entities -> render all objects in view
+    renderer.clear();
+
+    // Render map
+    let current_level = ecs.fetch::<Map>();
+    for tile in current_level.visited.iter() {
+      let def = tile.tile_type.def();
+      renderer.put_char_ex(tile.x, tile.y, def.glyph, def.remembered, BLACK);
+    }
+
+    // retrieve storages from ecs
+    let pos_store = ecs.read_storage::<Position>();
+    let sprite_store = ecs.read_storage::<Sprite>();  
+
+    for tile in (*current_level).visible.iter() {
+        let def = tile.tile_type.def();
+        renderer.put_char_ex(tile.x, tile.y, def.glyph, def.lit, BLACK);
+    }
+
+    // entities remembered from earlier, dimmed since they may have moved on
+    for (idx, entries) in current_level.remembered.iter() {
+        let (x, y) = (*idx as i32 % current_level.width, *idx as i32 / current_level.width);
+        if let Some(entry) = entries.last() {
+            if !current_level.tile_in_view(x, y) {
+                renderer.put_char_ex(x, y, entry.glyph, lerp(entry.color, BLACK, 0.5), BLACK);
+            }
+        }
+    }
+
+        // Render visible entities
+        use specs::Join;
+        let hidden_store = ecs.read_storage::<Hidden>();
+        let submerged_store = ecs.read_storage::<Submerged>();
+        for (pos, sprite, _, _) in (&pos_store, &sprite_store, !&hidden_store, !&submerged_store).join() {
+            // TODO: Fix Map data structure to separate tiles from type
+            if (*current_level).tile_in_view(pos.x, pos.y){
+            renderer.put_char_ex(pos.x, pos.y, sprite.sprite, sprite.color, BLACK);
+            }
+        }
+
+    // fire and gas tint whatever is under them, thicker clouds more strongly
+    for tile in current_level.visible.iter() {
+        if current_level.is_burning(tile.x, tile.y) {
+            renderer.set_char_background(tile.x, tile.y, DARK_FLAME);
+        } else if let Some(gas) = current_level.gas_at(tile.x, tile.y) {
+            let color = match gas.kind {
+                GasKind::Poison => DARK_LIME,
+                GasKind::Smoke => DARK_GREY,
+                GasKind::Confusion => DARK_PURPLE,
+            };
+            renderer.set_char_background(tile.x, tile.y, lerp(BLACK, color, (gas.density as f32 / 10.0).min(1.0)));
+        }
+    }
+
+    // player status and message log panel under the map
+    if let Some(player) = ecs.try_fetch::<Entity>() {
+        if let Some(health) = ecs.read_storage::<Health>().get(*player) {
+            renderer.print(SCREEN_WIDTH - 2, MAP_HEIGHT, &format!("HP: {}/{}", health.current, health.max), TextAlignment::Right);
+        }
+        if let Some(exp) = ecs.read_storage::<Experience>().get(*player) {
+            renderer.print(SCREEN_WIDTH - 16, MAP_HEIGHT, &format!("Level {} ({}/{} XP)", exp.level, exp.xp, progression::needed(exp.level)), TextAlignment::Right);
+        }
+    }
+    // a count being typed in takes the place of the player's name
+    let count = ecs.try_fetch::<Repeat>().and_then(|repeat| repeat.pending_count);
+    if let Some(count) = count {
+        renderer.print(1, MAP_HEIGHT, &format!("Count: {}", count), TextAlignment::Left);
+    } else if let Some(player) = ecs.try_fetch::<Entity>() {
+        if let Some(character) = ecs.read_storage::<Character>().get(*player) {
+            renderer.print(1, MAP_HEIGHT, &format!("{} the {}", character.name, character.background.name()), TextAlignment::Left);
+        }
+    }
+    if let Some(log) = ecs.try_fetch::<GameLog>() {
+        for (row, message) in log.recent((SCREEN_HEIGHT - MAP_HEIGHT - 1) as usize).iter().enumerate() {
+            let line = match &message.speech {
+                Some((tongue, speech)) => format!("{} \"{}\"", message.text, language::render_for_player(ecs, speech, *tongue)),
+                None => message.text.clone()
+            };
+            renderer.print(1, MAP_HEIGHT + 1 + row as i32, &line, TextAlignment::Left);
+        }
+    }
+
+    // tooltip for whatever is under the mouse
+    if let Some(cursor) = ecs.try_fetch::<MouseCursor>() {
+        if let Some((x, y)) = cursor.position {
+            if let Some(text) = describe_tile(ecs, x, y) {
+                let len = text.chars().count() as i32;
+                // keep the tooltip on screen, flipping it to the left of the cursor near the edge
+                if x + 2 + len < SCREEN_WIDTH {
+                    renderer.print(x + 2, y, &text, TextAlignment::Left);
+                } else {
+                    renderer.print(x - 2, y, &text, TextAlignment::Right);
+                }
+            }
+        }
+    }
+}
+
+// what the player knows about a tile: terrain and entities if it's in view, remembered terrain otherwise
+pub fn describe_tile(ecs: &World, x: i32, y: i32) -> Option<String> {
+    let map = ecs.fetch::<Map>();
+    if map.tile_in_view(x, y) {
+        let pos_store = ecs.read_storage::<Position>();
+        let name_store = ecs.read_storage::<Name>();
+        let hidden_store = ecs.read_storage::<Hidden>();
+        let submerged_store = ecs.read_storage::<Submerged>();
+        use specs::Join;
+        let mut names: Vec<String> = (&pos_store, &name_store, !&hidden_store, !&submerged_store).join()
+            .filter(|(pos, _, _, _)| pos.x == x && pos.y == y)
+            .map(|(_, name, _, _)| name.name.clone())
+            .collect();
+        names.push(map.get_tile_type(x, y).name().to_string());
+        Some(names.join(", "))
+    } else if map.tile_visited(x, y) {
+        let mut names: Vec<String> = map.remembered_at(x, y).iter().map(|e| e.name.clone()).collect();
+        names.push(map.get_tile_type(x, y).name().to_string());
+        Some(format!("{} (remembered)", names.join(", ")))
+    } else {
+        None
+    }
+}
+
+// rough wording for how hurt something looks
+fn health_description(health: &Health) -> &'static str {
+    let fraction = health.current as f32 / health.max as f32;
+    if health.current >= health.max {
+        "unhurt"
+    } else if fraction > 0.66 {
+        "lightly wounded"
+    } else if fraction > 0.33 {
+        "wounded"
+    } else if health.current > 0 {
+        "badly wounded"
+    } else {
+        "dead"
+    }
+}
+
+// splits text into lines of at most width characters, breaking between words
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Full examine text for a tile, one entry per line before wrapping. Visible tiles list every entity
+/// on them with its description, health and status effects; remembered tiles only get the terrain
+/// as it was last seen.
+pub fn examine_tile(ecs: &World, x: i32, y: i32) -> Vec<String> {
+    let map = ecs.fetch::<Map>();
+    let mut lines = Vec::new();
+    if map.tile_in_view(x, y) {
+        use specs::Join;
+        let entities = ecs.entities();
+        let pos_store = ecs.read_storage::<Position>();
+        let name_store = ecs.read_storage::<Name>();
+        let description_store = ecs.read_storage::<Description>();
+        let health_store = ecs.read_storage::<Health>();
+        let status_store = ecs.read_storage::<StatusEffects>();
+        let writing_store = ecs.read_storage::<Writing>();
+        let speaks_store = ecs.read_storage::<Speaks>();
+        let faction_store = ecs.read_storage::<Faction>();
+        let factions = ecs.try_fetch::<Factions>();
+        let hidden_store = ecs.read_storage::<Hidden>();
+        let submerged_store = ecs.read_storage::<Submerged>();
+        for (ent, pos, name, _, _) in (&entities, &pos_store, &name_store, !&hidden_store, !&submerged_store).join() {
+            if pos.x != x || pos.y != y {
+                continue;
+            }
+            lines.push(name.name.clone());
+            if let Some(description) = description_store.get(ent) {
+                lines.push(description.text.clone());
+            }
+            if let Some(writing) = writing_store.get(ent) {
+                lines.push(format!("It is written in {}:", writing.tongue.name()));
+                lines.push(language::render_for_player(ecs, &writing.text, writing.tongue));
+            }
+            if let Some(speaks) = speaks_store.get(ent) {
+                lines.push(format!("It speaks {}.", speaks.tongue.name()));
+            }
+            if let (Some(faction), Some(factions)) = (faction_store.get(ent), factions.as_ref()) {
+                lines.push(format!("One of the {}, {}.", faction.name, factions.towards_player(&faction.name).describe()));
+            }
+            if let Some(health) = health_store.get(ent) {
+                lines.push(format!("Health: {}", health_description(health)));
+            }
+            if let Some(status) = status_store.get(ent).filter(|s| !s.effects.is_empty()) {
+                lines.push(format!("Status: {}", status.effects.join(", ")));
+            }
+            lines.push(String::new());
+        }
+        let tile_type = map.get_tile_type(x, y);
+        lines.push(format!("Terrain: {}", tile_type.name()));
+        lines.push(tile_type.description().to_string());
+        if map.is_locked(map.get_index(x, y)) {
+            lines.push("It is locked.".to_string());
+        }
+        if map.is_burning(x, y) {
+            lines.push("It is on fire!".to_string());
+        }
+        if let Some(gas) = map.gas_at(x, y) {
+            lines.push(format!("A cloud of {} hangs here.", gas.kind.name()));
+        }
+    } else if map.tile_visited(x, y) {
+        let tile_type = map.get_tile_type(x, y);
+        lines.push(format!("Terrain: {} (remembered)", tile_type.name()));
+        lines.push(tile_type.description().to_string());
+        lines.push(String::new());
+        let turn = ecs.try_fetch::<Clock>().map_or(0, |clock| clock.tick);
+        for entry in map.remembered_at(x, y) {
+            lines.push(format!("You saw the {} here {} turns ago.", entry.name, turn.saturating_sub(entry.turn_seen)));
+        }
+        lines.push("You can't see what is there now.".to_string());
+    } else {
+        lines.push("You haven't seen this place.".to_string());
     }
-}
\ No newline at end of file
+    lines
+}
+
+const LOOK_PANEL_WIDTH: i32 = 30;
+
+/// The game with a highlighted cursor and an examine panel for the tile under it,
+/// the panel sits on the half of the map away from the cursor.
+pub fn render_look<R: Renderer + ?Sized>(renderer: &mut R, ecs: &World, x: i32, y: i32) {
+    draw_game(renderer, ecs);
+    renderer.set_char_background(x, y, DARK_YELLOW);
+
+    let mut lines = Vec::new();
+    for line in examine_tile(ecs, x, y) {
+        if line.is_empty() {
+            lines.push(line);
+        } else {
+            lines.extend(wrap(&line, (LOOK_PANEL_WIDTH - 4) as usize));
+        }
+    }
+    let left = if x < SCREEN_WIDTH / 2 { SCREEN_WIDTH - LOOK_PANEL_WIDTH - 1 } else { 1 };
+    let height = (lines.len() as i32 + 4).min(MAP_HEIGHT - 2);
+    for row in 0..height {
+        for col in 0..LOOK_PANEL_WIDTH {
+            let border = row == 0 || row == height - 1 || col == 0 || col == LOOK_PANEL_WIDTH - 1;
+            renderer.put_char_ex(left + col, 1 + row, if border { '#' } else { ' ' }, DARK_GREY, BLACK);
+        }
+    }
+    renderer.print(left + LOOK_PANEL_WIDTH / 2, 1, " Look ", TextAlignment::Center);
+    for (row, line) in lines.iter().take((height - 4) as usize).enumerate() {
+        renderer.print(left + 2, 3 + row as i32, line, TextAlignment::Left);
+    }
+    renderer.print(SCREEN_WIDTH / 2, MAP_HEIGHT - 1, "Move to look around, Escape to return", TextAlignment::Center);
+    renderer.flush();
+}
+
+const DIALOGUE_PANEL_WIDTH: i32 = 60;
+
+/// The game with a conversation panel over the bottom of the map: what `speaker` just said, in
+/// their tongue as far as the player understands it, and the replies to pick from.
+pub fn render_dialogue<R: Renderer + ?Sized>(renderer: &mut R, ecs: &World, speaker: Entity, text: &str, menu: &Menu) {
+    draw_game(renderer, ecs);
+
+    let spoken = language::render_for_player(ecs, text, dialogue::tongue_of(ecs, speaker));
+    let lines = wrap(&format!("\"{}\"", spoken), (DIALOGUE_PANEL_WIDTH - 4) as usize);
+    let replies: Vec<Vec<String>> = menu.options.iter().map(|option| wrap(option, (DIALOGUE_PANEL_WIDTH - 8) as usize)).collect();
+    let height = lines.len() as i32 + replies.iter().map(|r| r.len() as i32).sum::<i32>() + 5;
+    let left = (SCREEN_WIDTH - DIALOGUE_PANEL_WIDTH) / 2;
+    let top = MAP_HEIGHT - height - 1;
+    for row in 0..height {
+        for col in 0..DIALOGUE_PANEL_WIDTH {
+            let border = row == 0 || row == height - 1 || col == 0 || col == DIALOGUE_PANEL_WIDTH - 1;
+            renderer.put_char_ex(left + col, top + row, if border { '#' } else { ' ' }, DARK_GREY, BLACK);
+        }
+    }
+    let name = ecs.read_storage::<Name>().get(speaker).map_or("Stranger".to_string(), |n| capitalise(&n.name));
+    renderer.print(left + DIALOGUE_PANEL_WIDTH / 2, top, &format!(" {} ", name), TextAlignment::Center);
+    let mut row = top + 2;
+    for line in lines.iter() {
+        renderer.print(left + 2, row, line, TextAlignment::Left);
+        row += 1;
+    }
+    row += 1;
+    for (index, reply) in replies.iter().enumerate() {
+        if index as i16 == menu.cursor_index {
+            renderer.put_char_ex(left + 3, row, '>', WHITE, BLACK);
+        }
+        for line in reply.iter() {
+            renderer.print(left + 5, row, line, TextAlignment::Left);
+            row += 1;
+        }
+    }
+    renderer.flush();
+}
+
+// a boxed list of options over the middle of the map, with the cursor on the one picked
+fn draw_menu_panel<R: Renderer + ?Sized>(renderer: &mut R, ecs: &World, title: &str, menu: &Menu) {
+    draw_game(renderer, ecs);
+    let height = menu.options.len() as i32 + 4;
+    let left = (SCREEN_WIDTH - LOOK_PANEL_WIDTH) / 2;
+    let top = (MAP_HEIGHT - height) / 2;
+    for row in 0..height {
+        for col in 0..LOOK_PANEL_WIDTH {
+            let border = row == 0 || row == height - 1 || col == 0 || col == LOOK_PANEL_WIDTH - 1;
+            renderer.put_char_ex(left + col, top + row, if border { '#' } else { ' ' }, DARK_GREY, BLACK);
+        }
+    }
+    renderer.print(left + LOOK_PANEL_WIDTH / 2, top, &format!(" {} ", title), TextAlignment::Center);
+    for (index, option) in menu.options.iter().enumerate() {
+        renderer.print(left + 4, top + 2 + index as i32, option, TextAlignment::Left);
+    }
+    renderer.put_char_ex(left + 2, top + 2 + menu.cursor_index as i32, '>', WHITE, BLACK);
+    renderer.flush();
+}
+
+/// The game with the orders that can be given to companions listed over it.
+pub fn render_orders<R: Renderer + ?Sized>(renderer: &mut R, ecs: &World, menu: &Menu) {
+    draw_menu_panel(renderer, ecs, "Orders", menu);
+}
+
+/// The game with the perks to pick from for a new level listed over it.
+pub fn render_level_up<R: Renderer + ?Sized>(renderer: &mut R, ecs: &World, menu: &Menu) {
+    draw_menu_panel(renderer, ecs, "Choose a perk", menu);
+}
+
+pub fn render_help<R: Renderer + ?Sized>(renderer: &mut R, keymap: &Keymap) {
+    renderer.clear();
+    renderer.print(SCREEN_WIDTH/2, 2, "Key bindings", TextAlignment::Center);
+    for (row, (command, keys)) in keymap.binding_table().iter().enumerate() {
+        let keys: Vec<String> = keys.iter().map(|k| k.label()).collect();
+        renderer.print(10, 5 + row as i32, command.description(), TextAlignment::Left);
+        renderer.print(32, 5 + row as i32, &keys.join(", "), TextAlignment::Left);
+    }
+    renderer.print(SCREEN_WIDTH/2, SCREEN_HEIGHT - 3, "Press any key to return", TextAlignment::Center);
+    renderer.flush();
+}
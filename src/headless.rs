@@ -0,0 +1,219 @@
+use tcod::colors::*;
+use tcod::console::TextAlignment;
+use crate::gui::Renderer;
+
+/*
+In-memory renderer, lets the game draw without opening a window so frames can be checked in tests
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub glyph: char,
+    pub foreground: Color,
+    pub background: Color
+}
+
+const BLANK: Cell = Cell{glyph: ' ', foreground: WHITE, background: BLACK};
+
+pub struct HeadlessRenderer {
+    pub width: i32,
+    pub height: i32,
+    // cells currently being drawn to, and the last flushed frame
+    back: Vec<Cell>,
+    front: Vec<Cell>,
+    pub frames: u32
+}
+
+impl HeadlessRenderer {
+    pub fn new(width: i32, height: i32) -> Self {
+        HeadlessRenderer{
+            width,
+            height,
+            back: vec![BLANK; (width * height) as usize],
+            front: vec![BLANK; (width * height) as usize],
+            frames: 0
+        }
+    }
+
+    pub fn cell(&self, x: i32, y: i32) -> Cell {
+        self.front[(x + y * self.width) as usize]
+    }
+
+    // glyphs of the last flushed frame, one line per row with trailing blanks trimmed
+    pub fn snapshot(&self) -> String {
+        let mut lines: Vec<String> = self.front.chunks(self.width as usize)
+            .map(|row| row.iter().map(|c| c.glyph).collect::<String>().trim_end().to_string())
+            .collect();
+        while lines.last().is_some_and(|l| l.is_empty()) {
+            lines.pop();
+        }
+        lines.join("\n")
+    }
+
+    fn set(&mut self, x: i32, y: i32, cell: Cell) {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            self.back[(x + y * self.width) as usize] = cell;
+        }
+    }
+}
+
+impl Renderer for HeadlessRenderer {
+    fn put_char_ex(&mut self, x: i32, y: i32, glyph: char, foreground: Color, background: Color) {
+        self.set(x, y, Cell{glyph, foreground, background});
+    }
+
+    fn set_char_background(&mut self, x: i32, y: i32, background: Color) {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            self.back[(x + y * self.width) as usize].background = background;
+        }
+    }
+
+    fn print(&mut self, x: i32, y: i32, text: &str, alignment: TextAlignment) {
+        let len = text.chars().count() as i32;
+        // same anchoring as libtcod's print_ex
+        let start = match alignment {
+            TextAlignment::Left => x,
+            TextAlignment::Center => x - len / 2,
+            TextAlignment::Right => x - len + 1,
+        };
+        for (i, glyph) in text.chars().enumerate() {
+            self.set(start + i as i32, y, Cell{glyph, foreground: WHITE, background: BLACK});
+        }
+    }
+
+    fn clear(&mut self) {
+        for cell in self.back.iter_mut() {
+            *cell = BLANK;
+        }
+    }
+
+    fn flush(&mut self) {
+        self.front.copy_from_slice(&self.back);
+        self.frames += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gui::{render_game, render_main_menu, examine_tile, Menu};
+    use crate::map::{Map, MemoryLoss};
+    use crate::components::{Player, Position, Sprite, Vision, Name, Description, Health, StatusEffects, Hidden, Submerged, Writing, Speaks, Faction};
+    use crate::visionsystem::VisionSystem;
+    use crate::memorysystem::MemorySystem;
+    use crate::turnsystem::Clock;
+    use crate::{SCREEN_HEIGHT, SCREEN_WIDTH, vec_of_strings};
+    use specs::{Builder, RunNow, World, WorldExt};
+    use std::collections::HashSet;
+
+    #[test]
+    fn main_menu_snapshot() {
+        let mut ecs = World::new();
+        ecs.insert(Menu::new(3, vec_of_strings!["New Game", "Load Game", "Options", "Quit"]));
+        let mut renderer = HeadlessRenderer::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+        render_main_menu(&mut renderer, &ecs);
+
+        let snapshot = renderer.snapshot();
+        let menu: Vec<&str> = snapshot.lines().skip(SCREEN_HEIGHT as usize / 2).collect();
+        assert_eq!(menu, vec![
+            "                                      BABEL",
+            "                                   >New Game",
+            "                                    Load Game",
+            "                                     Options",
+            "                                      Quit",
+        ]);
+        assert_eq!(renderer.frames, 1);
+    }
+
+    #[test]
+    fn game_snapshot_shows_walls_floor_and_player() {
+        let mut ecs = World::new();
+        ecs.register::<Player>();
+        ecs.register::<Position>();
+        ecs.register::<Sprite>();
+        ecs.register::<Vision>();
+        ecs.register::<Hidden>();
+        ecs.register::<Submerged>();
+        ecs.register::<Writing>();
+        ecs.register::<Speaks>();
+        ecs.register::<Faction>();
+        ecs.insert(Map::new(8, 5));
+        ecs.create_entity().with(Position{x: 3, y: 2})
+                           .with(Sprite{sprite: '@', color: WHITE})
+                           .with(Vision{field_of_vision: HashSet::new()})
+                           .with(Player{}).build();
+        VisionSystem{}.run_now(&ecs);
+
+        let mut renderer = HeadlessRenderer::new(8, 5);
+        render_game(&mut renderer, &ecs);
+        // the far corners fall outside the player's line of sight
+        assert_eq!(renderer.snapshot(), "\
+XXXXXXX
+X......X
+X..@...X
+X......X
+XXXXXXX");
+        assert_eq!(renderer.cell(3, 2).foreground, WHITE);
+        assert_eq!(renderer.cell(1, 1).foreground, LIGHTEST_GREY);
+    }
+
+    #[test]
+    fn examine_differs_for_visible_and_remembered_tiles() {
+        let mut ecs = World::new();
+        ecs.register::<Player>();
+        ecs.register::<Position>();
+        ecs.register::<Vision>();
+        ecs.register::<Name>();
+        ecs.register::<Description>();
+        ecs.register::<Health>();
+        ecs.register::<StatusEffects>();
+        ecs.register::<Sprite>();
+        ecs.register::<Hidden>();
+        ecs.register::<Submerged>();
+        ecs.register::<Writing>();
+        ecs.register::<Speaks>();
+        ecs.register::<Faction>();
+        ecs.insert(Map::new(8, 5));
+        ecs.insert(Clock{tick: 3});
+        ecs.create_entity().with(Position{x: 3, y: 2})
+                           .with(Vision{field_of_vision: HashSet::new()})
+                           .with(Player{}).build();
+        let orc = ecs.create_entity().with(Position{x: 5, y: 2})
+                           .with(Sprite{sprite: 'o', color: RED})
+                           .with(Name{name: "orc".to_string()})
+                           .with(Description{text: "A hulking brute.".to_string()})
+                           .with(Health{current: 4, max: 10})
+                           .with(StatusEffects{effects: vec_of_strings!["burning"]}).build();
+        VisionSystem{}.run_now(&ecs);
+        MemorySystem{}.run_now(&ecs);
+
+        assert_eq!(examine_tile(&ecs, 5, 2), vec_of_strings![
+            "orc", "A hulking brute.", "Health: wounded", "Status: burning", "",
+            "Terrain: ground", "Worn flagstones."
+        ]);
+
+        // lose sight of everything and let the orc wander off, it's only remembered where it was
+        ecs.write_resource::<Map>().visible.clear();
+        ecs.write_storage::<Position>().get_mut(orc).unwrap().x = 6;
+        ecs.write_resource::<Clock>().tick = 10;
+        let remembered = examine_tile(&ecs, 5, 2);
+        assert_eq!(remembered[0], "Terrain: ground (remembered)");
+        assert!(remembered.contains(&"You saw the orc here 7 turns ago.".to_string()));
+        assert!(!remembered.iter().any(|line| line.starts_with("Health")));
+
+        let mut renderer = HeadlessRenderer::new(8, 5);
+        render_game(&mut renderer, &ecs);
+        assert_eq!(renderer.cell(5, 2).glyph, 'o');
+        assert_eq!(renderer.cell(6, 2).glyph, '.');
+
+        // seeing the tile again replaces the stale memory
+        VisionSystem{}.run_now(&ecs);
+        MemorySystem{}.run_now(&ecs);
+        assert!(ecs.fetch::<Map>().remembered_at(5, 2).is_empty());
+        assert_eq!(ecs.fetch::<Map>().remembered_at(6, 2)[0].name, "orc");
+
+        ecs.write_resource::<Map>().forget(MemoryLoss::Amnesia);
+        assert!(ecs.fetch::<Map>().remembered.is_empty());
+        assert!(!ecs.fetch::<Map>().tile_visited(5, 2));
+    }
+}
@@ -0,0 +1,482 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::map::{Map, DIAGONAL_COST};
+use crate::pathfinding::NavigationPath;
+
+/// Width and height (in tiles) of a single cluster.
+const CLUSTER_SIZE: i32 = 16;
+
+/// Border openings at least this wide get a transition at each end instead of one in the middle.
+const WIDE_ENTRANCE: usize = 6;
+
+/// HPA* abstraction of a `Map`. The map is cut into `CLUSTER_SIZE` square clusters; every opening
+/// along a shared cluster border gets one or two transition tiles, and transitions inside the same
+/// cluster are linked by precomputed local paths. Searches run over this much smaller graph and
+/// only touch full tiles inside the start and end clusters.
+///
+/// The graph only considers terrain. Entities are left to the caller (see `PathFollower`).
+pub struct ClusterGraph {
+    width: i32,
+    height: i32,
+    clusters_x: i32,
+    clusters_y: i32,
+    // transitions for each pair of neighbouring clusters (lower id first), as (tile a, tile b)
+    borders: HashMap<(usize, usize), Vec<(usize, usize)>>,
+    // abstract nodes keyed by tile index
+    nodes: HashMap<usize, Vec<AbstractEdge>>,
+    // terrain snapshot used to find what changed between map revisions
+    walkable: Vec<bool>,
+    revision: u32,
+}
+
+/// An edge of the abstract graph. `path` excludes the tile we come from and ends on `to`.
+#[derive(Clone, Debug)]
+struct AbstractEdge {
+    to: usize,
+    cost: f32,
+    path: Vec<usize>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Node {
+    idx: usize,
+    f: f32,
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.f == other.f
+    }
+}
+
+impl Eq for Node {}
+
+impl Ord for Node {
+    fn cmp(&self, b: &Self) -> Ordering {
+        b.f.partial_cmp(&self.f).unwrap()
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, b: &Self) -> Option<Ordering> {
+        Some(self.cmp(b))
+    }
+}
+
+/// Request a hierarchical search between two tile indices. Falls back to nothing (an unsuccessful
+/// path) when the abstract graph has no route. The graph treats unlocked doors as passable, so this
+/// is only for movers that can open them.
+pub fn hpa_search(start: usize, end: usize, map: &Map, graph: &ClusterGraph) -> NavigationPath {
+    graph.search(start, end, map)
+}
+
+impl ClusterGraph {
+    /// Builds the full abstract graph for a map.
+    pub fn new(map: &Map) -> ClusterGraph {
+        let clusters_x = (map.width + CLUSTER_SIZE - 1) / CLUSTER_SIZE;
+        let clusters_y = (map.height + CLUSTER_SIZE - 1) / CLUSTER_SIZE;
+        let mut graph = ClusterGraph {
+            width: map.width,
+            height: map.height,
+            clusters_x,
+            clusters_y,
+            borders: HashMap::new(),
+            nodes: HashMap::new(),
+            walkable: (0..(map.width * map.height) as usize).map(|i| map.is_passable(i)).collect(),
+            revision: map.revision,
+        };
+        let all: HashSet<usize> = (0..(clusters_x * clusters_y) as usize).collect();
+        graph.rebuild(&all, map);
+        graph
+    }
+
+    /// Brings the graph up to date with the map's terrain. Only clusters containing changed tiles
+    /// (and their neighbours, whose shared borders may have moved) are recomputed.
+    pub fn sync(&mut self, map: &Map) {
+        if self.revision == map.revision {
+            return;
+        }
+        self.revision = map.revision;
+
+        let mut dirty = HashSet::new();
+        for idx in 0..self.walkable.len() {
+            let now = map.is_passable(idx);
+            if self.walkable[idx] != now {
+                self.walkable[idx] = now;
+                dirty.insert(self.cluster_of(idx));
+            }
+        }
+        if !dirty.is_empty() {
+            self.rebuild(&dirty, map);
+        }
+    }
+
+    fn cluster_of(&self, idx: usize) -> usize {
+        let x = idx as i32 % self.width;
+        let y = idx as i32 / self.width;
+        ((y / CLUSTER_SIZE) * self.clusters_x + x / CLUSTER_SIZE) as usize
+    }
+
+    // (min x, min y, max x, max y) of a cluster, inclusive
+    fn cluster_bounds(&self, cluster: usize) -> (i32, i32, i32, i32) {
+        let cx = cluster as i32 % self.clusters_x;
+        let cy = cluster as i32 / self.clusters_x;
+        let x0 = cx * CLUSTER_SIZE;
+        let y0 = cy * CLUSTER_SIZE;
+        (x0, y0, (x0 + CLUSTER_SIZE).min(self.width) - 1, (y0 + CLUSTER_SIZE).min(self.height) - 1)
+    }
+
+    fn neighbour_clusters(&self, cluster: usize) -> Vec<usize> {
+        let cx = cluster as i32 % self.clusters_x;
+        let cy = cluster as i32 / self.clusters_x;
+        let mut neighbours = Vec::new();
+        if cx > 0 { neighbours.push(cluster - 1); }
+        if cx < self.clusters_x - 1 { neighbours.push(cluster + 1); }
+        if cy > 0 { neighbours.push(cluster - self.clusters_x as usize); }
+        if cy < self.clusters_y - 1 { neighbours.push(cluster + self.clusters_x as usize); }
+        neighbours
+    }
+
+    fn rebuild(&mut self, dirty: &HashSet<usize>, map: &Map) {
+        // recompute every border touching a dirty cluster
+        let mut touched = dirty.clone();
+        for &cluster in dirty.iter() {
+            for other in self.neighbour_clusters(cluster) {
+                let key = (cluster.min(other), cluster.max(other));
+                let transitions = self.find_transitions(key.0, key.1, map);
+                self.borders.insert(key, transitions);
+                touched.insert(other);
+            }
+        }
+
+        // drop the old nodes of every cluster whose borders may have changed
+        let stale: Vec<usize> = self.nodes.keys().copied().filter(|idx| touched.contains(&self.cluster_of(*idx))).collect();
+        for idx in stale {
+            self.nodes.remove(&idx);
+        }
+
+        for &cluster in touched.iter() {
+            // inter-cluster edges from this cluster's side of every border
+            let mut entrances: HashMap<usize, Vec<AbstractEdge>> = HashMap::new();
+            for other in self.neighbour_clusters(cluster) {
+                let key = (cluster.min(other), cluster.max(other));
+                for &(a, b) in self.borders[&key].iter() {
+                    let (mine, theirs) = if key.0 == cluster { (a, b) } else { (b, a) };
+                    entrances.entry(mine).or_default().push(AbstractEdge { to: theirs, cost: 1.0, path: vec![theirs] });
+                }
+            }
+
+            // intra-cluster edges between every pair of entrances that can reach each other
+            let tiles: Vec<usize> = entrances.keys().copied().collect();
+            for &from in tiles.iter() {
+                let (dist, parents) = self.search_cluster(from, cluster, map);
+                for &to in tiles.iter() {
+                    if to == from { continue; }
+                    if let Some(cost) = dist.get(&to) {
+                        let path = unwind(from, to, &parents);
+                        entrances.get_mut(&from).unwrap().push(AbstractEdge { to, cost: *cost, path });
+                    }
+                }
+            }
+            self.nodes.extend(entrances);
+        }
+    }
+
+    // scans the shared border of two neighbouring clusters for openings
+    fn find_transitions(&self, a: usize, b: usize, map: &Map) -> Vec<(usize, usize)> {
+        let (ax0, ay0, ax1, ay1) = self.cluster_bounds(a);
+        // pairs of facing tiles along the border, a's side first
+        let pairs: Vec<(usize, usize)> = if b == a + 1 {
+            (ay0..=ay1).map(|y| (map.get_index(ax1, y), map.get_index(ax1 + 1, y))).collect()
+        } else {
+            (ax0..=ax1).map(|x| (map.get_index(x, ay1), map.get_index(x, ay1 + 1))).collect()
+        };
+
+        let mut transitions = Vec::new();
+        let mut run: Vec<(usize, usize)> = Vec::new();
+        for pair in pairs.into_iter().chain(std::iter::once((usize::MAX, usize::MAX))) {
+            let open = pair.0 != usize::MAX && map.is_passable(pair.0) && map.is_passable(pair.1);
+            if open {
+                run.push(pair);
+                continue;
+            }
+            if run.len() >= WIDE_ENTRANCE {
+                transitions.push(run[0]);
+                transitions.push(run[run.len() - 1]);
+            } else if !run.is_empty() {
+                transitions.push(run[run.len() / 2]);
+            }
+            run.clear();
+        }
+        transitions
+    }
+
+    // Dijkstra flood from `from`, restricted to the tiles of `cluster`
+    fn search_cluster(&self, from: usize, cluster: usize, map: &Map) -> (HashMap<usize, f32>, HashMap<usize, usize>) {
+        let (x0, y0, x1, y1) = self.cluster_bounds(cluster);
+        let mut dist: HashMap<usize, f32> = HashMap::new();
+        let mut parents: HashMap<usize, usize> = HashMap::new();
+        let mut open = BinaryHeap::new();
+        dist.insert(from, 0.0);
+        open.push(Node { idx: from, f: 0.0 });
+
+        while let Some(q) = open.pop() {
+            if q.f > dist[&q.idx] { continue; }
+            let x = q.idx as i32 % self.width;
+            let y = q.idx as i32 / self.width;
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if (dx == 0 && dy == 0) || nx < x0 || nx > x1 || ny < y0 || ny > y1 { continue; }
+                    let n = map.get_index(nx, ny);
+                    if !map.is_passable(n) { continue; }
+                    let cost = q.f + map.step_cost(q.idx, n);
+                    if dist.get(&n).is_none_or(|d| cost < *d) {
+                        dist.insert(n, cost);
+                        parents.insert(n, q.idx);
+                        open.push(Node { idx: n, f: cost });
+                    }
+                }
+            }
+        }
+        (dist, parents)
+    }
+
+    // octile distance, consistent with the step costs used above
+    fn heuristic(&self, a: usize, b: usize) -> f32 {
+        let dx = (a as i32 % self.width - b as i32 % self.width).abs() as f32;
+        let dy = (a as i32 / self.width - b as i32 / self.width).abs() as f32;
+        dx.max(dy) + (DIAGONAL_COST - 1.0) * dx.min(dy)
+    }
+
+    fn search(&self, start: usize, end: usize, map: &Map) -> NavigationPath {
+        let mut result = NavigationPath::new();
+        result.destination = end;
+        if !map.is_passable(end) && start != end {
+            return result;
+        }
+
+        let start_cluster = self.cluster_of(start);
+        let end_cluster = self.cluster_of(end);
+        let (start_dist, start_parents) = self.search_cluster(start, start_cluster, map);
+
+        // short trips inside one cluster never need the abstract graph
+        if start_cluster == end_cluster && start_dist.contains_key(&end) {
+            result.success = true;
+            result.steps.push(start);
+            result.steps.extend(unwind(start, end, &start_parents));
+            return result;
+        }
+
+        // temporary edges linking start and end into the abstract graph
+        let start_edges: Vec<AbstractEdge> = self.nodes.keys()
+            .filter(|idx| self.cluster_of(**idx) == start_cluster && **idx != start && start_dist.contains_key(idx))
+            .map(|&idx| AbstractEdge { to: idx, cost: start_dist[&idx], path: unwind(start, idx, &start_parents) })
+            .collect();
+        let (end_dist, end_parents) = self.search_cluster(end, end_cluster, map);
+        let end_edges: HashMap<usize, AbstractEdge> = self.nodes.keys()
+            .filter(|idx| self.cluster_of(**idx) == end_cluster && **idx != end && end_dist.contains_key(idx))
+            .map(|&idx| {
+                // walk the flood backwards so the path runs from the entrance towards the end
+                let mut path = unwind(end, idx, &end_parents);
+                path.pop();
+                path.reverse();
+                path.push(end);
+                (idx, AbstractEdge { to: end, cost: end_dist[&idx], path })
+            })
+            .collect();
+
+        let mut open = BinaryHeap::new();
+        let mut best: HashMap<usize, f32> = HashMap::new();
+        let mut parents: HashMap<usize, (usize, Vec<usize>)> = HashMap::new();
+        best.insert(start, 0.0);
+        open.push(Node { idx: start, f: self.heuristic(start, end) });
+
+        while let Some(q) = open.pop() {
+            if q.idx == end {
+                result.success = true;
+                let mut segments = Vec::new();
+                let mut current = end;
+                while current != start {
+                    let (parent, path) = &parents[&current];
+                    segments.push(path.clone());
+                    current = *parent;
+                }
+                result.steps.push(start);
+                for segment in segments.into_iter().rev() {
+                    result.steps.extend(segment);
+                }
+                return result;
+            }
+
+            let g = best[&q.idx];
+            let mut edges: Vec<&AbstractEdge> = Vec::new();
+            if q.idx == start { edges.extend(start_edges.iter()); }
+            if let Some(node_edges) = self.nodes.get(&q.idx) { edges.extend(node_edges.iter()); }
+            if let Some(edge) = end_edges.get(&q.idx) { edges.push(edge); }
+
+            for edge in edges {
+                let cost = g + edge.cost;
+                if best.get(&edge.to).is_none_or(|b| cost < *b) {
+                    best.insert(edge.to, cost);
+                    parents.insert(edge.to, (q.idx, edge.path.clone()));
+                    open.push(Node { idx: edge.to, f: cost + self.heuristic(edge.to, end) });
+                }
+            }
+        }
+
+        result
+    }
+}
+
+// follow a parent map from `to` back to `from`, returning the steps after `from` in walking order
+fn unwind(from: usize, to: usize, parents: &HashMap<usize, usize>) -> Vec<usize> {
+    let mut path = vec![to];
+    let mut current = to;
+    while current != from {
+        current = parents[&current];
+        path.push(current);
+    }
+    path.pop();
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::TileType;
+    use crate::pathfinding::astar_search;
+    use std::time::Instant;
+
+    // how far an HPA* path's cost may stray from A*'s, as a fraction of it; astar_search isn't exact
+    // either, so HPA* sometimes comes out cheaper
+    const COST_TOLERANCE: f32 = 0.1;
+
+    fn big_map() -> Map {
+        let mut map = Map::new(256, 256);
+        map.set_tile_blocked();
+        map
+    }
+
+    // corner-to-corner trips across most of the map
+    fn queries(map: &Map) -> Vec<(usize, usize)> {
+        (0..20).map(|i| (map.get_index(2 + i * 3, 2 + i * 5), map.get_index(250 - i * 7, 252 - i * 4))).collect()
+    }
+
+    fn assert_walkable_path(path: &NavigationPath, map: &Map) {
+        for pair in path.steps.windows(2) {
+            assert!(map.is_passable(pair[1]));
+            let (x0, y0) = (pair[0] as i32 % map.width, pair[0] as i32 / map.width);
+            let (x1, y1) = (pair[1] as i32 % map.width, pair[1] as i32 / map.width);
+            assert!((x0 - x1).abs() <= 1 && (y0 - y1).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn finds_path_across_clusters() {
+        let map = big_map();
+        let graph = ClusterGraph::new(&map);
+        let (start, end) = (map.get_index(3, 3), map.get_index(250, 240));
+        let path = hpa_search(start, end, &map, &graph);
+        assert!(path.success);
+        assert_eq!(path.steps[0], start);
+        assert_eq!(*path.steps.last().unwrap(), end);
+        assert_walkable_path(&path, &map);
+    }
+
+    #[test]
+    fn sync_picks_up_new_walls() {
+        let mut map = big_map();
+        let mut graph = ClusterGraph::new(&map);
+        // wall off the whole column between x=15 and x=16, leaving the left strip unreachable
+        for y in 0..map.height {
+            map.set_tile_type(15, y, TileType::WALL);
+        }
+        graph.sync(&map);
+        let path = hpa_search(map.get_index(3, 100), map.get_index(200, 100), &map, &graph);
+        assert!(!path.success);
+
+        map.set_tile_type(15, 100, TileType::GROUND);
+        graph.sync(&map);
+        let path = hpa_search(map.get_index(3, 100), map.get_index(200, 100), &map, &graph);
+        assert!(path.success);
+        assert!(path.steps.contains(&map.get_index(15, 100)));
+        assert_walkable_path(&path, &map);
+    }
+
+    #[test]
+    fn local_paths_go_around_costly_terrain() {
+        let mut map = big_map();
+        // a pond inside the first cluster, cheaper to walk round the bottom of than to swim
+        for y in 1..=12 {
+            for x in 6..=10 {
+                map.set_tile_type(x, y, TileType::DEEP_WATER);
+            }
+        }
+        let graph = ClusterGraph::new(&map);
+        let path = hpa_search(map.get_index(3, 11), map.get_index(13, 11), &map, &graph);
+        assert!(path.success);
+        assert!(path.steps.iter().all(|idx| map.get_tile_type(*idx as i32 % map.width, *idx as i32 / map.width) != TileType::DEEP_WATER));
+    }
+
+    // what walking `path` from `start` costs, step by step
+    fn path_cost(start: usize, path: &NavigationPath, map: &Map) -> f32 {
+        std::iter::once(start).chain(path.steps.iter().copied().skip_while(|idx| *idx == start))
+            .collect::<Vec<usize>>()
+            .windows(2)
+            .map(|pair| map.step_cost(pair[0], pair[1]))
+            .sum()
+    }
+
+    fn assert_matches_astar(queries: &[(usize, usize)], map: &Map, graph: &ClusterGraph) {
+        for (start, end) in queries {
+            let (hpa, astar) = (hpa_search(*start, *end, map, graph), astar_search(*start, *end, map, true));
+            assert_eq!(hpa.success, astar.success);
+            if astar.success {
+                let (hpa_cost, astar_cost) = (path_cost(*start, &hpa, map), path_cost(*start, &astar, map));
+                assert!((hpa_cost - astar_cost).abs() <= astar_cost * COST_TOLERANCE, "{} against A*'s {}", hpa_cost, astar_cost);
+            }
+        }
+    }
+
+    #[test]
+    fn hpa_finds_whatever_astar_finds_on_a_big_map() {
+        let mut map = big_map();
+        let mut graph = ClusterGraph::new(&map);
+        assert_matches_astar(&queries(&map), &map, &graph);
+
+        map.set_tile_type(100, 100, TileType::WALL);
+        map.set_tile_blocked();
+        graph.sync(&map);
+        assert_matches_astar(&queries(&map), &map, &graph);
+    }
+
+    // cargo test --release -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_hpa_vs_astar_256() {
+        let map = big_map();
+        let queries = queries(&map);
+
+        let now = Instant::now();
+        let mut graph = ClusterGraph::new(&map);
+        println!("cluster graph build: {:?} ({} nodes)", now.elapsed(), graph.nodes.len());
+
+        let now = Instant::now();
+        let found = queries.iter().filter(|(s, e)| astar_search(*s, *e, &map, true).success).count();
+        println!("astar_search x{}: {:?} ({} found)", queries.len(), now.elapsed(), found);
+
+        let now = Instant::now();
+        let found = queries.iter().filter(|(s, e)| hpa_search(*s, *e, &map, &graph).success).count();
+        println!("hpa_search   x{}: {:?} ({} found)", queries.len(), now.elapsed(), found);
+
+        let mut map = map;
+        map.set_tile_type(100, 100, TileType::WALL);
+        map.set_tile_blocked();
+        let now = Instant::now();
+        graph.sync(&map);
+        println!("incremental sync after one tile change: {:?}", now.elapsed());
+    }
+}
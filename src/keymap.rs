@@ -0,0 +1,288 @@
+use tcod::input::{Key, KeyCode};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/*
+Maps raw key presses from any frontend to semantic commands, bindings are loaded from a config file
+*/
+
+const DEFAULT_KEYMAP: &str = include_str!("../data/keymaps/default.cfg");
+const PRESET_DIR: &str = "data/keymaps";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Command {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    MoveUpLeft,
+    MoveUpRight,
+    MoveDownLeft,
+    MoveDownRight,
+    Confirm,
+    Cancel,
+    Help,
+    AutoExplore,
+    Look,
+    Close,
+    Search,
+    Disarm,
+    Read,
+    Climb,
+    Orders,
+    Rest,
+    RunUp,
+    RunDown,
+    RunLeft,
+    RunRight,
+    RunUpLeft,
+    RunUpRight,
+    RunDownLeft,
+    RunDownRight
+}
+
+// (config name, help text) in the order they are listed on the help screen
+const COMMANDS: [(Command, &str, &str); 28] = [
+    (Command::MoveUp, "move_up", "Move up"),
+    (Command::MoveDown, "move_down", "Move down"),
+    (Command::MoveLeft, "move_left", "Move left"),
+    (Command::MoveRight, "move_right", "Move right"),
+    (Command::MoveUpLeft, "move_up_left", "Move up-left"),
+    (Command::MoveUpRight, "move_up_right", "Move up-right"),
+    (Command::MoveDownLeft, "move_down_left", "Move down-left"),
+    (Command::MoveDownRight, "move_down_right", "Move down-right"),
+    (Command::RunUp, "run_up", "Run up"),
+    (Command::RunDown, "run_down", "Run down"),
+    (Command::RunLeft, "run_left", "Run left"),
+    (Command::RunRight, "run_right", "Run right"),
+    (Command::RunUpLeft, "run_up_left", "Run up-left"),
+    (Command::RunUpRight, "run_up_right", "Run up-right"),
+    (Command::RunDownLeft, "run_down_left", "Run down-left"),
+    (Command::RunDownRight, "run_down_right", "Run down-right"),
+    (Command::Rest, "rest", "Rest a turn (prefix a count, i.e. 20s)"),
+    (Command::Close, "close", "Close a door (walk into one to open it)"),
+    (Command::Search, "search", "Search nearby for hidden things (takes a count)"),
+    (Command::Disarm, "disarm", "Disarm a trap next to you"),
+    (Command::Read, "read", "Read writing here or next to you"),
+    (Command::Climb, "climb", "Climb the stairs you're standing on"),
+    (Command::Orders, "orders", "Give orders to your companions"),
+    (Command::Confirm, "confirm", "Confirm"),
+    (Command::Cancel, "cancel", "Cancel / quit"),
+    (Command::AutoExplore, "auto_explore", "Explore automatically"),
+    (Command::Look, "look", "Look around / examine"),
+    (Command::Help, "help", "Show this help"),
+];
+
+// names usable in config files for keys that don't print a character
+const KEY_NAMES: [(&str, KeyCode); 30] = [
+    ("Escape", KeyCode::Escape),
+    ("Backspace", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("Enter", KeyCode::Enter),
+    ("Space", KeyCode::Spacebar),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("End", KeyCode::End),
+    ("Home", KeyCode::Home),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("NumPad0", KeyCode::NumPad0),
+    ("NumPad1", KeyCode::NumPad1),
+    ("NumPad2", KeyCode::NumPad2),
+    ("NumPad3", KeyCode::NumPad3),
+    ("NumPad4", KeyCode::NumPad4),
+    ("NumPad5", KeyCode::NumPad5),
+    ("NumPad6", KeyCode::NumPad6),
+    ("NumPad7", KeyCode::NumPad7),
+    ("NumPad8", KeyCode::NumPad8),
+    ("NumPad9", KeyCode::NumPad9),
+    ("NumPadEnter", KeyCode::NumPadEnter),
+    ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2),
+    ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4),
+];
+
+impl Command {
+    pub fn config_name(self) -> &'static str {
+        COMMANDS.iter().find(|c| c.0 == self).unwrap().1
+    }
+
+    pub fn description(self) -> &'static str {
+        COMMANDS.iter().find(|c| c.0 == self).unwrap().2
+    }
+
+    // (dx, dy) for movement commands
+    pub fn direction(self) -> Option<(i32, i32)> {
+        match self {
+            Command::MoveUp => Some((0, -1)),
+            Command::MoveDown => Some((0, 1)),
+            Command::MoveLeft => Some((-1, 0)),
+            Command::MoveRight => Some((1, 0)),
+            Command::MoveUpLeft => Some((-1, -1)),
+            Command::MoveUpRight => Some((1, -1)),
+            Command::MoveDownLeft => Some((-1, 1)),
+            Command::MoveDownRight => Some((1, 1)),
+            _ => None
+        }
+    }
+
+    // (dx, dy) for run commands
+    pub fn run_direction(self) -> Option<(i32, i32)> {
+        match self {
+            Command::RunUp => Some((0, -1)),
+            Command::RunDown => Some((0, 1)),
+            Command::RunLeft => Some((-1, 0)),
+            Command::RunRight => Some((1, 0)),
+            Command::RunUpLeft => Some((-1, -1)),
+            Command::RunUpRight => Some((1, -1)),
+            Command::RunDownLeft => Some((-1, 1)),
+            Command::RunDownRight => Some((1, 1)),
+            _ => None
+        }
+    }
+}
+
+/// A key independent of the frontend it came from: either a printable character or a named key,
+/// optionally with shift held (i.e. `Shift+Up`). Shifted characters are just their own character.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RawKey {
+    Char(char),
+    Named(&'static str, bool)
+}
+
+impl RawKey {
+    pub fn from_key(key: &Key) -> Option<RawKey> {
+        if let Some((name, _)) = KEY_NAMES.iter().find(|(_, code)| *code == key.code) {
+            return Some(RawKey::Named(name, key.shift));
+        }
+        // letters, symbols and the number row all carry their character
+        if key.printable != '\0' && !key.printable.is_control() {
+            return Some(RawKey::Char(key.printable));
+        }
+        None
+    }
+
+    fn parse(text: &str) -> Result<RawKey, String> {
+        let mut chars = text.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(RawKey::Char(c));
+        }
+        let (name, shift) = match text.strip_prefix("Shift+") {
+            Some(name) => (name, true),
+            None => (text, false)
+        };
+        KEY_NAMES.iter().find(|(known, _)| *known == name)
+            .map(|(known, _)| RawKey::Named(known, shift))
+            .ok_or(format!("unknown key '{}'", text))
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            RawKey::Char(c) => c.to_string(),
+            RawKey::Named(name, true) => format!("Shift+{}", name),
+            RawKey::Named(name, false) => name.to_string()
+        }
+    }
+
+    pub fn digit(&self) -> Option<u32> {
+        match self {
+            RawKey::Char(c) => c.to_digit(10),
+            _ => None
+        }
+    }
+}
+
+pub struct Keymap {
+    bindings: HashMap<RawKey, Command>
+}
+
+impl Keymap {
+    pub fn default_bindings() -> Keymap {
+        Keymap::parse(DEFAULT_KEYMAP).expect("built-in keymap is invalid")
+    }
+
+    /// Loads a keymap from a file, or from one of the shipped presets by name (i.e. "vi-keys").
+    pub fn load(name_or_path: &str) -> Result<Keymap, String> {
+        let path = if Path::new(name_or_path).is_file() {
+            name_or_path.to_string()
+        } else {
+            format!("{}/{}.cfg", PRESET_DIR, name_or_path)
+        };
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+        Keymap::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Parses `command = key, key` lines. A key bound to more than one command is an error.
+    pub fn parse(text: &str) -> Result<Keymap, String> {
+        let mut bindings = HashMap::new();
+        let mut conflicts = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, keys) = line.split_once('=').ok_or(format!("line {}: expected 'command = keys'", line_no + 1))?;
+            let command = COMMANDS.iter().find(|c| c.1 == name.trim())
+                .map(|c| c.0)
+                .ok_or(format!("line {}: unknown command '{}'", line_no + 1, name.trim()))?;
+            for key in keys.split(',').map(|k| k.trim()).filter(|k| !k.is_empty()) {
+                let key = RawKey::parse(key).map_err(|e| format!("line {}: {}", line_no + 1, e))?;
+                if let Some(existing) = bindings.insert(key, command) {
+                    if existing != command {
+                        conflicts.push(format!("'{}' is bound to both {} and {}", key.label(), existing.config_name(), command.config_name()));
+                    }
+                }
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(conflicts.join("; "));
+        }
+        Ok(Keymap{bindings})
+    }
+
+    pub fn command_for(&self, key: &Key) -> Option<Command> {
+        match RawKey::from_key(key)? {
+            // shift+key falls back to the plain binding when it has none of its own
+            RawKey::Named(name, true) => self.bindings.get(&RawKey::Named(name, true))
+                .or_else(|| self.bindings.get(&RawKey::Named(name, false)))
+                .copied(),
+            raw => self.bindings.get(&raw).copied()
+        }
+    }
+
+    /// Every command with the keys bound to it, in help screen order.
+    pub fn binding_table(&self) -> Vec<(Command, Vec<RawKey>)> {
+        COMMANDS.iter().map(|(command, _, _)| {
+            let mut keys: Vec<RawKey> = self.bindings.iter().filter(|(_, c)| *c == command).map(|(k, _)| *k).collect();
+            keys.sort_by_key(|k| k.label());
+            (*command, keys)
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_presets_load_without_conflicts() {
+        for preset in ["default", "vi-keys", "laptop"].iter() {
+            let keymap = Keymap::load(preset).unwrap();
+            assert!(keymap.binding_table().iter().all(|(_, keys)| !keys.is_empty()), "{} leaves a command unbound", preset);
+        }
+    }
+
+    #[test]
+    fn conflicting_bindings_are_rejected() {
+        let err = Keymap::parse("move_up = k\nmove_down = k, j").err().unwrap();
+        assert!(err.contains("'k' is bound to both move_up and move_down"));
+        assert!(Keymap::parse("jump = k").is_err());
+        assert!(Keymap::parse("move_up = Upp").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use crate::components::{Position, Name, Player, Languages, Writing, Teaches, Hidden, Submerged, Item, FloorPlan};
+use crate::map::{Map, MemoryLoss};
+use crate::gamelog::GameLog;
+use crate::conlang::{Conlang, hash, capitalise};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use specs::{Entity, World, WorldExt, Join};
+
+/*
+The tower's many tongues: garbling whatever the player doesn't understand yet, and learning them
+*/
+
+// fluency at which every word of a tongue is understood
+pub const FLUENT: u32 = 100;
+// fluency gained each time the player hears or reads a tongue
+const EXPOSURE: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Tongue {
+    // the player's own, always understood
+    Common,
+    Orcish,
+    Infernal,
+    // the builders' script, carved all over the tower
+    Babelic
+}
+
+impl Tongue {
+    pub fn name(self) -> &'static str {
+        match self {
+            Tongue::Common => "Common",
+            Tongue::Orcish => "Orcish",
+            Tongue::Infernal => "Infernal",
+            Tongue::Babelic => "Babelic",
+        }
+    }
+
+    pub fn named(name: &str) -> Option<Tongue> {
+        [Tongue::Common, Tongue::Orcish, Tongue::Infernal, Tongue::Babelic].iter().copied()
+            .find(|tongue| tongue.name().eq_ignore_ascii_case(name))
+    }
+
+    // every tongue is generated from its own seed, so it garbles the same way every game
+    fn seed(self) -> u64 {
+        match self {
+            Tongue::Common => 0,
+            Tongue::Orcish => 0x6f72_6373,
+            Tongue::Infernal => 0x696d_7073,
+            Tongue::Babelic => 0x00ba_6e1c,
+        }
+    }
+
+    pub fn conlang(self) -> &'static Conlang {
+        static CONLANGS: OnceLock<HashMap<Tongue, Conlang>> = OnceLock::new();
+        let conlangs = CONLANGS.get_or_init(|| {
+            [Tongue::Common, Tongue::Orcish, Tongue::Infernal, Tongue::Babelic].iter()
+                .map(|tongue| (*tongue, Conlang::generate(tongue.seed())))
+                .collect()
+        });
+        &conlangs[&self]
+    }
+}
+
+// how fluent the player has to be to understand a word, from 1 to FLUENT
+fn difficulty(tongue: Tongue, word: &str) -> u32 {
+    1 + (hash(word, tongue.seed()) % FLUENT as u64) as u32
+}
+
+// the word in the tongue itself, keeping the capital if it had one
+fn garble_word(tongue: Tongue, word: &str) -> String {
+    let translated = tongue.conlang().translate(word);
+    if word.starts_with(char::is_uppercase) {
+        capitalise(&translated)
+    } else {
+        translated
+    }
+}
+
+/// Text in `tongue` as it reads to someone with the given fluency: words they know are left alone,
+/// the rest are garbled. Punctuation is kept so sentences still look like sentences.
+pub fn render(text: &str, tongue: Tongue, fluency: u32) -> String {
+    if tongue == Tongue::Common {
+        return text.to_string();
+    }
+    text.split(' ').map(|token| {
+        let start = token.find(|c: char| c.is_alphanumeric()).unwrap_or(token.len());
+        let end = token.char_indices().rev().find(|(_, c)| c.is_alphanumeric()).map_or(start, |(i, c)| i + c.len_utf8());
+        let word = &token[start..end.max(start)];
+        if word.is_empty() || fluency >= difficulty(tongue, &word.to_lowercase()) {
+            token.to_string()
+        } else {
+            format!("{}{}{}", &token[..start], garble_word(tongue, word), &token[end..])
+        }
+    }).collect::<Vec<String>>().join(" ")
+}
+
+/// How well `id` knows `tongue`, everyone knows Common.
+pub fn fluency(ecs: &World, id: Entity, tongue: Tongue) -> u32 {
+    if tongue == Tongue::Common {
+        return FLUENT;
+    }
+    ecs.read_storage::<Languages>().get(id).and_then(|l| l.fluency.get(&tongue).copied()).unwrap_or(0)
+}
+
+/// Text in `tongue` as the player would read it right now.
+pub fn render_for_player(ecs: &World, text: &str, tongue: Tongue) -> String {
+    match ecs.try_fetch::<Entity>() {
+        Some(player) => render(text, tongue, fluency(ecs, *player, tongue)),
+        None => render(text, tongue, 0)
+    }
+}
+
+/// Improves `id`'s fluency in `tongue`, telling the player if it was them.
+pub fn learn(ecs: &World, id: Entity, tongue: Tongue, amount: u32) {
+    let before = fluency(ecs, id, tongue);
+    if tongue == Tongue::Common || before >= FLUENT {
+        return;
+    }
+    let after = (before + amount).min(FLUENT);
+    if let Some(languages) = ecs.write_storage::<Languages>().get_mut(id) {
+        languages.fluency.insert(tongue, after);
+    }
+    if ecs.read_storage::<Player>().contains(id) && after / 25 > before / 25 {
+        let message = if after >= FLUENT {
+            format!("You are now fluent in {}.", tongue.name())
+        } else {
+            format!("You understand a little more {}.", tongue.name())
+        };
+        ecs.write_resource::<GameLog>().log(message);
+    }
+}
+
+/// Logs something said aloud in `tongue`, which the player picks up a little of by hearing it.
+pub fn say(ecs: &World, speaker: &str, tongue: Tongue, text: &str) {
+    ecs.write_resource::<GameLog>().log_speech(format!("The {} says", speaker), tongue, text);
+    let player = *ecs.fetch::<Entity>();
+    learn(ecs, player, tongue, EXPOSURE);
+}
+
+/// Reads the first writing under or next to `id`. Writing that teaches its tongue only does so
+/// the first time. Returns whether there was anything to read, which takes the turn.
+pub fn read(ecs: &World, id: Entity) -> bool {
+    let (x, y) = match ecs.read_storage::<Position>().get(id) {
+        Some(pos) => (pos.x, pos.y),
+        None => return false
+    };
+    let found = {
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let writings = ecs.read_storage::<Writing>();
+        let hidden = ecs.read_storage::<Hidden>();
+        let submerged = ecs.read_storage::<Submerged>();
+        // something at your feet before anything around you
+        let mut found: Vec<(Entity, i32, Writing)> = (&entities, &positions, &writings, !&hidden, !&submerged).join()
+            .filter(|(_, pos, _, _, _)| (pos.x - x).abs() <= 1 && (pos.y - y).abs() <= 1)
+            .map(|(ent, pos, writing, _, _)| (ent, (pos.x - x).abs() + (pos.y - y).abs(), writing.clone()))
+            .collect();
+        found.sort_by_key(|(_, distance, _)| *distance);
+        found.into_iter().next()
+    };
+    let (writing_id, _, writing) = match found {
+        Some(found) => found,
+        None => {
+            ecs.write_resource::<GameLog>().log("There is nothing here to read.");
+            return false;
+        }
+    };
+    let name = ecs.read_storage::<Name>().get(writing_id).map_or("writing".to_string(), |n| n.name.clone());
+    ecs.write_resource::<GameLog>().log_speech(format!("The {} reads", name), writing.tongue, &writing.text);
+    learn(ecs, id, writing.tongue, EXPOSURE);
+    let teaches = ecs.write_storage::<Teaches>().remove(writing_id);
+    if let Some(teaches) = teaches {
+        if ecs.read_storage::<Item>().contains(writing_id) {
+            ecs.write_resource::<GameLog>().log(format!("You study the {} until its shapes start to make sense.", name));
+        }
+        learn(ecs, id, teaches.tongue, teaches.amount);
+    }
+    // a plan is drawn rather than written, it needs no fluency to follow
+    if ecs.read_storage::<FloorPlan>().contains(writing_id) {
+        ecs.write_resource::<Map>().forget(MemoryLoss::MagicMapping);
+        ecs.write_resource::<GameLog>().log("The plan fixes the layout of this floor in your mind, though not who is on it now.");
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "Whoever climbs the tower must first learn to listen.";
+
+    #[test]
+    fn garbling_is_deterministic_and_keeps_punctuation() {
+        let garbled = render(TEXT, Tongue::Babelic, 0);
+        assert_eq!(garbled, render(TEXT, Tongue::Babelic, 0));
+        assert_ne!(garbled, TEXT);
+        assert_ne!(garbled, render(TEXT, Tongue::Orcish, 0));
+        assert!(garbled.ends_with('.'));
+        assert!(garbled.starts_with(|c: char| c.is_uppercase()));
+        assert_eq!(render(TEXT, Tongue::Common, 0), TEXT);
+    }
+
+    #[test]
+    fn more_fluency_reveals_more_words() {
+        let known = |fluency| render(TEXT, Tongue::Orcish, fluency).split(' ').zip(TEXT.split(' ')).filter(|(a, b)| a == b).count();
+        let words = TEXT.split(' ').count();
+        assert_eq!(known(0), 0);
+        assert!(known(0) < known(60) && known(60) < words);
+        assert!(known(60) <= known(90));
+        assert_eq!(known(FLUENT), words);
+    }
+
+    #[test]
+    fn words_ending_in_accented_letters_are_garbled_whole() {
+        let text = "«Déjà vu», naïve café über straße!";
+        let garbled = render(text, Tongue::Orcish, 0);
+        assert_eq!(garbled.split(' ').count(), text.split(' ').count());
+        assert!(garbled.starts_with('«') && garbled.ends_with('!'));
+        assert!(!garbled.contains("café") && !garbled.contains("straße"));
+        assert_eq!(render(text, Tongue::Orcish, FLUENT), text);
+    }
+
+    #[test]
+    fn reading_a_floor_plan_maps_the_floor() {
+        use specs::Builder;
+        let mut ecs = World::new();
+        ecs.register::<Position>();
+        ecs.register::<Player>();
+        ecs.register::<Name>();
+        ecs.register::<Languages>();
+        ecs.register::<Writing>();
+        ecs.register::<Teaches>();
+        ecs.register::<Hidden>();
+        ecs.register::<Submerged>();
+        ecs.register::<Item>();
+        ecs.register::<FloorPlan>();
+        ecs.insert(Map::new(10, 5));
+        ecs.insert(GameLog::default());
+        let player = ecs.create_entity().with(Position{x: 2, y: 2}).with(Player{}).build();
+        ecs.create_entity().with(Position{x: 2, y: 2})
+                           .with(Writing{tongue: Tongue::Babelic, text: TEXT.to_string()})
+                           .with(FloorPlan{}).build();
+        assert!(!ecs.fetch::<Map>().tile_visited(8, 3));
+        assert!(read(&ecs, player));
+        assert!(ecs.fetch::<Map>().tile_visited(8, 3));
+    }
+}
@@ -1,23 +1,64 @@
 mod player;
 mod gui;
+use gui::{InputEvent, MouseCursor};
+#[cfg(unix)]
+mod terminal;
+#[cfg(test)]
+mod headless;
 mod map;
-use map::{Map};
+use map::{Map, Tile, TileType};
+mod tiles;
 mod components;
 mod actions;
+mod gamelog;
+use gamelog::GameLog;
+mod doors;
+mod traps;
+mod water;
+mod conlang;
+mod language;
+mod dialogue;
+mod factions;
+mod combat;
+mod allies;
+mod floors;
+mod chargen;
+mod cutscene;
+use chargen::Progress;
+mod progression;
+use progression::Tower;
+mod stats;
+use stats::RunStats;
+mod morgue;
+mod scores;
+mod keymap;
+use keymap::{Command, Keymap, RawKey};
 mod pathfinding;
-use pathfinding::astar_search;
+mod hpastar;
+use hpastar::ClusterGraph;
 #[macro_use]
 mod lib;
 
 mod turnsystem;
-use turnsystem::TurnSystem;
+use turnsystem::{TurnSystem, Clock};
 mod visionsystem;
 use visionsystem::VisionSystem;
+mod memorysystem;
+use memorysystem::MemorySystem;
 mod mapblockingsystem;
 use mapblockingsystem::MapBlockingSystem;
+mod environmentsystem;
+use environmentsystem::EnvironmentSystem;
+mod rng;
+use rng::GameRng;
 
 use specs::{World, WorldExt, Builder, Entity, RunNow};
-use components::{Position, Sprite, Actor, Speed, Vision, Player, Blocking};
+use components::{Position, Sprite, Actor, Speed, Vision, Player, Blocking, PathFollower, Name, Item, Health, Description, StatusEffects, OpensDoors, Unlocks, Carried,
+                 Hidden, Trap, TrapKind, SecretDoor, Perception, Flammable, Confused,
+                 Burning, Submerged, Swimmer, Aquatic, Floats, Heavy, Languages, Speaks, Writing, Teaches, FloorPlan,
+                 Peaceful, Dialogue, Faction, Melee, Companion, Experience, Character};
+use factions::{Factions, Stance};
+use language::Tongue;
 
 use tcod::colors::*;
 use rand::prelude::*;
@@ -27,19 +68,40 @@ use std::collections::HashSet;
 // actual size of the window
 const SCREEN_WIDTH: i32 = 80;
 const SCREEN_HEIGHT: i32 = 50;
+// rows left for the map once the message log panel is taken off the bottom
+const MAP_HEIGHT: i32 = 43;
 
 pub enum RunState {
     Tick,
     ActiveTurn,
     PlayerTurn,
     AITurn{id: Entity},
+    Help,
+    // examine cursor over the map
+    Look{x: i32, y: i32},
+    // pick which adjacent door to close, or trap to disarm
+    CloseDoor,
+    Disarm,
+    // talking with a peaceful NPC, `node` is where the conversation is up to
+    Dialogue{speaker: Entity, conversation: String, node: String, menu: gui::Menu},
+    // picking an order for the player's companions
+    Orders{orders: Vec<allies::Order>, menu: gui::Menu},
+    // picking a perk for a level the player has just reached
+    LevelUp{perks: Vec<progression::Perk>, menu: gui::Menu},
     Inventory,
     MainMenu,
-    NewGame,
+    // showing frame `frame` of a cutscene, `then` is where the game goes once it's over
+    Cutscene{name: String, frame: usize, then: Box<RunState>},
+    // making a character, step by step
+    NewGame{creation: chargen::Creation},
+    // looking over past runs, one of which can be picked to replay its seed
+    Scores{history: scores::History, order: scores::Order, menu: gui::Menu},
     LoadGame,
     SaveGame,
     Options,
-    GameOver
+    // the run is over, `morgue` is where the morgue file went and `rank` where the run placed in the
+    // high scores, or why either couldn't be written
+    GameOver{won: bool, score: u32, morgue: Result<std::path::PathBuf, String>, rank: Result<usize, String>}
 }
 
 fn run_systems(ecs: &mut World) {
@@ -50,45 +112,110 @@ fn run_systems(ecs: &mut World) {
     let mut vision_system = VisionSystem{};
     vision_system.run_now(ecs);
 
+    let mut memory_system = MemorySystem{};
+    memory_system.run_now(ecs);
+
+    // before blocking, so a lurker next to the player is in the way as soon as it surfaces
+    water::surface_lurkers(ecs);
+
     let mut map_blocking_system = MapBlockingSystem{};
     map_blocking_system.run_now(ecs);
 
+    let mut environment_system = EnvironmentSystem{};
+    environment_system.run_now(ecs);
+
+    // keep the pathfinding abstraction in step with any terrain changes
+    ecs.write_resource::<ClusterGraph>().sync(&ecs.fetch::<Map>());
+
+    // roll to notice anything hidden on tiles that just came into view
+    traps::passive_perception(ecs);
+
+    // anything killed by fire, gas or water since last tick
+    combat::reap(ecs);
+
     ecs.maintain();
 
 }
 
-fn game_loop(state: &mut RunState, tcod: &mut gui::Tcod, ecs: &mut World){
+fn game_loop(state: &mut RunState, frontend: &mut dyn gui::Frontend, ecs: &mut World){
     match state {
         RunState::MainMenu  => {
-                        tcod.render_main_menu(ecs);
-                        *state = player::handle_main_menu_events(tcod, ecs);
+                        gui::render_main_menu(frontend, ecs);
+                        *state = player::handle_main_menu_events(frontend, ecs);
                         
                      },
-        RunState::NewGame => {
-                        // opening cinematic
-                        // character select
-                        // start game
-                        *state = RunState::Tick;
+        RunState::NewGame{creation} => {
+                        gui::render_creation(frontend, creation);
+                        let key = frontend.wait_for_keypress();
+                        let command = ecs.fetch::<Keymap>().command_for(&key);
+                        match creation.handle(&key, command) {
+                            Progress::Editing => {},
+                            Progress::Abandoned => *state = RunState::MainMenu,
+                            Progress::Finished => {
+                                chargen::build(ecs, creation, SCREEN_WIDTH/2, SCREEN_HEIGHT/2);
+                                *state = RunState::Tick;
+                            }
+                        }
+        },
+
+        // left and right switch between best and latest, picking a run starts a new character on its seed
+        RunState::Scores{history, order, menu} => {
+            gui::render_scores(frontend, history, *order, menu);
+            let command = player::read_command(frontend, &ecs.fetch::<Keymap>());
+            match command {
+                Some(Command::MoveUp) if !history.runs.is_empty() => player::decrement_cursor(&mut menu.cursor_index, menu.menu_size),
+                Some(Command::MoveDown) if !history.runs.is_empty() => player::increment_cursor(&mut menu.cursor_index, menu.menu_size),
+                Some(Command::MoveLeft) | Some(Command::MoveRight) => *state = scores::table(std::mem::take(history), order.toggled()),
+                Some(Command::Confirm) => if let Some(run) = history.runs.get(menu.cursor_index as usize) {
+                    *state = RunState::NewGame{creation: chargen::Creation::replay(run.seed)};
+                },
+                Some(Command::Cancel) => *state = RunState::MainMenu,
+                _ => {}
+            }
+        },
+
+        // a frame at a time, skipping ends the cutscene early
+        RunState::Cutscene{name, frame, then} => {
+            let shown = cutscene::cutscenes().get(name).and_then(|frames| frames.get(*frame))
+                .map(|current| cutscene::play(frontend, ecs, current));
+            if shown == Some(cutscene::Shown::Next) {
+                *frame += 1;
+            } else {
+                *state = std::mem::replace(then.as_mut(), RunState::MainMenu);
+            }
         },
 
         // run all once per frame systems, etc
         RunState::Tick => {
             run_systems(ecs);
-            tcod.render_game(ecs);
+            gui::render_game(frontend, ecs);
             *state = RunState::ActiveTurn;
 
         },
         // work through all the turns that occur on a single tick
         RunState::ActiveTurn  => {
-            tcod.render_game(ecs);
+            if stats::player_dead(ecs) {
+                *state = game_over(ecs, false);
+                return;
+            }
+            // a new level is dealt with before anyone else moves
+            if progression::perk_pending(ecs) {
+                let (perks, menu) = progression::perks(ecs);
+                *state = RunState::LevelUp{perks, menu};
+                return;
+            }
+            gui::render_game(frontend, ecs);
             let player_id = ecs.fetch::<Entity>();
             let mut turn_queue = ecs.write_resource::<Vec<Entity>>();
 
             // branch to whatever entity type's turn it is or return to tick state
             let ent = (*turn_queue).pop();
             match ent {
+                // killed earlier in the tick, it's only cleaned up once the tick is over
+                Some(ent) if ent != *player_id && combat::is_dead(ecs, ent) => {},
                 Some(ent) => {
                     if ent == (*player_id) {
+                    ecs.write_resource::<RunStats>().turns += 1;
                     *state = RunState::PlayerTurn;
                     }
                     else {
@@ -101,63 +228,481 @@ fn game_loop(state: &mut RunState, tcod: &mut gui::Tcod, ecs: &mut World){
         },
         // handle player input, and change states
         RunState::PlayerTurn => {
-            let player_id = ecs.fetch::<Entity>();
-            let map = ecs.fetch::<Map>();
-            let act = player::read_keys(tcod, *player_id);
+            let player_id = *ecs.fetch::<Entity>();
+            if player::keypress_interrupts(ecs, frontend) {
+                gui::render_game(frontend, ecs);
+            }
+            // keep walking a clicked route without waiting for input
+            let act = if player::is_travelling(ecs) {
+                player::travel_step(ecs, player_id)
+            } else if player::is_exploring(ecs) {
+                player::explore_step(ecs, player_id)
+            } else if player::is_repeating(ecs) {
+                player::repeat_step(ecs, player_id)
+            } else {
+                match frontend.wait_for_event() {
+                    InputEvent::Key(key) => {
+                        let command = ecs.fetch::<Keymap>().command_for(&key);
+                        if command == Some(Command::Help) {
+                            *state = RunState::Help;
+                        }
+                        if command == Some(Command::Close) {
+                            *state = RunState::CloseDoor;
+                        }
+                        if command == Some(Command::Disarm) {
+                            *state = RunState::Disarm;
+                        }
+                        if command == Some(Command::Orders) {
+                            let (orders, menu) = allies::orders(ecs);
+                            *state = RunState::Orders{orders, menu};
+                        }
+                        if command == Some(Command::Look) {
+                            if let Some(pos) = ecs.read_storage::<Position>().get(player_id) {
+                                *state = RunState::Look{x: pos.x, y: pos.y};
+                            }
+                        }
+                        if command == Some(Command::AutoExplore) {
+                            player::start_explore(ecs, player_id);
+                            // show why it refused, if it did
+                            gui::render_game(frontend, ecs);
+                        }
+                        // unbound digits build up a count for the next command, i.e. 20s
+                        let digit = RawKey::from_key(&key).and_then(|raw| raw.digit());
+                        if let (None, Some(digit)) = (command, digit) {
+                            player::push_count_digit(ecs, digit);
+                            gui::render_game(frontend, ecs);
+                            actions::Action::NoAction
+                        } else {
+                            let act = player::handle_command(ecs, player_id, command);
+                            // show a refused run or a dropped count
+                            gui::render_game(frontend, ecs);
+                            act
+                        }
+                    },
+                    InputEvent::MouseMove{x, y} => {
+                        ecs.write_resource::<MouseCursor>().position = Some((x, y));
+                        gui::render_game(frontend, ecs);
+                        actions::Action::NoAction
+                    },
+                    InputEvent::MouseClick{x, y} => {
+                        player::start_travel(ecs, player_id, x, y);
+                        actions::Action::NoAction
+                    }
+                }
+            };
+            if let actions::Action::RestAction{..} = act {
+                *state = RunState::ActiveTurn;
+            }
+            if let actions::Action::SearchAction{id} = act {
+                if traps::search(ecs, id) {
+                    player::interrupt_activity(ecs);
+                }
+                *state = RunState::ActiveTurn;
+            }
+            if let actions::Action::ClimbAction{..} = act {
+                if climb(ecs) {
+                    *state = if ecs.fetch::<Tower>().depth >= progression::TOP_FLOOR {
+                        let over = game_over(ecs, true);
+                        cutscene::start("ending", over)
+                    } else {
+                        cutscene::start("floor2", RunState::Tick)
+                    };
+                } else {
+                    gui::render_game(frontend, ecs);
+                }
+            }
+            if let actions::Action::ReadAction{id} = act {
+                if language::read(ecs, id) {
+                    *state = RunState::ActiveTurn;
+                } else {
+                    gui::render_game(frontend, ecs);
+                }
+            }
             // TODO: this should be handled by the event queue most likely
             if let actions::Action::MoveAction { id: _, x, y } = act {
-                let mut pos_store = ecs.write_storage::<Position>();
-                let player_pos = pos_store.get_mut(*player_id);
-                if let Some(player_pos) = player_pos{
-                    if map.is_exit_valid(player_pos.x + x, player_pos.y + y){
-                        player_pos.x += x;
-                        player_pos.y += y;
-                        // return to turn queue
+                let (x, y) = stagger(ecs, player_id, x, y);
+                let target = ecs.read_storage::<Position>().get(player_id).map(|pos| (pos.x + x, pos.y + y));
+                let mut moved = false;
+                if let Some((tx, ty)) = target {
+                    let map = ecs.fetch::<Map>();
+                    if map.is_exit_valid(tx, ty) {
+                        let mut pos_store = ecs.write_storage::<Position>();
+                        if let Some(player_pos) = pos_store.get_mut(player_id) {
+                            player_pos.x = tx;
+                            player_pos.y = ty;
+                            moved = true;
+                        }
+                    } else if let Some(companion) = allies::companion_at(ecs, tx, ty) {
+                        // companions step aside by swapping places
+                        let mut pos_store = ecs.write_storage::<Position>();
+                        if let Some((px, py)) = pos_store.get(player_id).map(|pos| (pos.x, pos.y)) {
+                            pos_store.insert(companion, Position{x: px, y: py}).expect("swapped with a dead companion");
+                            pos_store.insert(player_id, Position{x: tx, y: ty}).expect("the player is dead");
+                            moved = true;
+                        }
+                    } else if let Some(victim) = combat::hostile_at(ecs, player_id, tx, ty) {
+                        drop(map);
+                        combat::attack(ecs, player_id, victim);
+                        player::interrupt_activity(ecs);
                         *state = RunState::ActiveTurn;
+                    } else if let Some(speaker) = dialogue::speaker_at(ecs, tx, ty) {
+                        // bumping into someone peaceful strikes up a conversation
+                        drop(map);
+                        let conversation = ecs.read_storage::<Dialogue>().get(speaker).map(|d| d.conversation.clone()).unwrap();
+                        *state = talk(ecs, speaker, &conversation, dialogue::START);
+                    } else if map.in_bounds(tx, ty) && map.get_tile_type(tx, ty) == TileType::DOOR_CLOSED {
+                        // walking into a closed door opens it, which takes the turn
+                        drop(map);
+                        if doors::open_door(ecs, player_id, tx, ty) {
+                            *state = RunState::ActiveTurn;
+                        } else {
+                            // a locked door ends any travel or run into it
+                            ecs.write_resource::<player::Travel>().steps.clear();
+                            gui::render_game(frontend, ecs);
+                        }
                     }
                 }
-
+                if moved {
+                    doors::pick_up_keys(ecs, player_id);
+                    water::enter_water(ecs, player_id);
+                    pay_for_terrain(ecs, player_id);
+                    if traps::trigger_at(ecs, player_id) {
+                        player::interrupt_activity(ecs);
+                    }
+                    // return to turn queue
+                    *state = RunState::ActiveTurn;
+                }
             }
         },
         // handle any ai entities turns
         RunState::AITurn{id} => {
-            let player = ecs.fetch::<Entity>();
-            let map = ecs.write_resource::<Map>();
-            let mut pos_store = ecs.write_storage::<Position>();
-            let player_pos = pos_store.get(*player);
-            if let Some(player_pos) = player_pos{
-                // reassign variable to drop borrow of pos_store
-                let player_pos = (player_pos.x, player_pos.y);
+            let companion = ecs.read_storage::<Companion>().contains(*id);
+            let target = if companion { allies::pick_target(ecs, *id) } else { combat::pick_target(ecs, *id) };
+            // where it's headed, which is only attacked if it's also the target
+            let goal = target.or_else(|| allies::heel(ecs, *id));
+            let hunts_player = factions::stance(ecs, *id, *ecs.fetch::<Entity>()) == Stance::Hostile;
+            let (moved, shout, strike) = {
+                let player = ecs.fetch::<Entity>();
+                let mut map = ecs.write_resource::<Map>();
+                let graph = ecs.fetch::<ClusterGraph>();
+                let opens_doors = ecs.read_storage::<OpensDoors>().contains(*id);
+                let mut pos_store = ecs.write_storage::<Position>();
+                let mut follower_store = ecs.write_storage::<PathFollower>();
+                // whether the monster stays in the water
+                let aquatic = ecs.read_storage::<Aquatic>().contains(*id);
+                let mut moved = false;
+                let mut shout = None;
+                let mut strike = None;
+                if let Some(player_pos) = pos_store.get(*player) {
+                    // anything that talks calls out the first time it spots the player
+                    let player_tile = Tile{x: player_pos.x, y: player_pos.y, tile_type: map.get_tile_type(player_pos.x, player_pos.y)};
+                    if hunts_player && ecs.read_storage::<Vision>().get(*id).is_some_and(|v| v.field_of_vision.contains(&player_tile)) {
+                        if let Some(speaks) = ecs.write_storage::<Speaks>().get_mut(*id) {
+                            shout = speaks.shout.take().map(|text| (speaks.tongue, text));
+                        }
+                    }
+                }
+                let target_pos = goal.and_then(|goal| pos_store.get(goal)).map(|pos| (pos.x, pos.y));
                 let mons_pos = pos_store.get_mut(*id);
-                if let Some(mons_pos) = mons_pos {
-                    if map.get_distance_sq(mons_pos.x, mons_pos.y, player_pos.0, player_pos.1) > 2.0 {
-                        let path = astar_search(map.get_index(mons_pos.x, mons_pos.y), map.get_index(player_pos.0, player_pos.1), &map);
-                        if path.success && path.steps.len()>1 {
-                            mons_pos.x = path.steps[1] as i32 % map.width;
-                            mons_pos.y = path.steps[1] as i32 / map.width;
+                let follower = follower_store.get_mut(*id);
+                if let (Some(quarry), Some(target_pos), Some(mons_pos), Some(follower)) = (goal, target_pos, mons_pos, follower) {
+                    follower.chase(quarry);
+                    if map.get_distance_sq(mons_pos.x, mons_pos.y, target_pos.0, target_pos.1) > 2.0 {
+                        let next = follower.next_step(map.get_index(mons_pos.x, mons_pos.y), map.get_index(target_pos.0, target_pos.1), &map, &graph, opens_doors);
+                        if let Some(next) = next {
+                            let (dx, dy) = stagger(ecs, *id, next as i32 % map.width - mons_pos.x, next as i32 / map.width - mons_pos.y);
+                            let (x, y) = (mons_pos.x + dx, mons_pos.y + dy);
+                            // opening a door on the way costs the move
+                            if opens_doors && map.is_openable_door(map.get_index(x, y)) {
+                                map.set_tile_type(x, y, TileType::DOOR_OPEN);
+                            } else if map.is_exit_valid(x, y) && (!aquatic || map.get_tile_type(x, y).def().depth > 0) {
+                                mons_pos.x = x;
+                                mons_pos.y = y;
+                                moved = true;
+                            }
                         }
+                    } else {
+                        strike = target;
                     }
                 }
+                (moved, shout, strike)
+            };
+            if let Some((tongue, text)) = shout {
+                let name = ecs.read_storage::<Name>().get(*id).map_or("something".to_string(), |n| n.name.clone());
+                language::say(ecs, &name, tongue, &text);
+            }
+            if let Some(victim) = strike {
+                combat::attack(ecs, *id, victim);
+            }
+            if moved {
+                water::enter_water(ecs, *id);
+                pay_for_terrain(ecs, *id);
             }
             *state = RunState::ActiveTurn;
         },
+        // list the key bindings until any key is pressed
+        RunState::Help => {
+            gui::render_help(frontend, &ecs.fetch::<Keymap>());
+            frontend.wait_for_keypress();
+            *state = RunState::PlayerTurn;
+        },
+        // close the only open door in reach, or ask which one
+        RunState::CloseDoor => {
+            let player_id = *ecs.fetch::<Entity>();
+            let target = choose_adjacent(frontend, ecs, |ecs, x, y| doors::adjacent_open_doors(&ecs.fetch::<Map>(), x, y),
+                                         "Close which door?", "There is no open door next to you.");
+            *state = match target {
+                Some((x, y)) if doors::close_door(ecs, player_id, x, y) => RunState::ActiveTurn,
+                _ => RunState::PlayerTurn
+            };
+            gui::render_game(frontend, ecs);
+        },
+        // same again for a trap to disarm
+        RunState::Disarm => {
+            let player_id = *ecs.fetch::<Entity>();
+            let target = choose_adjacent(frontend, ecs, traps::adjacent_known_traps,
+                                         "Disarm which trap?", "There is no trap you know of next to you.");
+            *state = match target {
+                Some((x, y)) if traps::disarm(ecs, player_id, x, y) => RunState::ActiveTurn,
+                _ => RunState::PlayerTurn
+            };
+            gui::render_game(frontend, ecs);
+        },
+        // move the examine cursor until the player backs out
+        RunState::Look{x, y} => {
+            gui::render_look(frontend, ecs, *x, *y);
+            let (mut dx, mut dy) = (0, 0);
+            match frontend.wait_for_event() {
+                InputEvent::Key(key) => {
+                    let command = ecs.fetch::<Keymap>().command_for(&key);
+                    match command {
+                        Some(Command::Cancel) | Some(Command::Confirm) | Some(Command::Look) => {
+                            *state = RunState::PlayerTurn;
+                            return;
+                        },
+                        Some(command) => {
+                            // run keys jump the cursor further
+                            if let Some((cx, cy)) = command.direction() {
+                                dx = cx;
+                                dy = cy;
+                            } else if let Some((cx, cy)) = command.run_direction() {
+                                dx = cx * 5;
+                                dy = cy * 5;
+                            }
+                        },
+                        None => {}
+                    }
+                },
+                InputEvent::MouseMove{x: mx, y: my} | InputEvent::MouseClick{x: mx, y: my} => {
+                    dx = mx - *x;
+                    dy = my - *y;
+                }
+            }
+            let map = ecs.fetch::<Map>();
+            *x = (*x + dx).clamp(0, map.width - 1);
+            *y = (*y + dy).clamp(0, map.height - 1);
+        },
+        // pick a reply, the conversation ends when a reply leads nowhere
+        RunState::Dialogue{speaker, conversation, node, menu} => {
+            let current = match dialogue::conversations().node(conversation, node) {
+                Some(current) => current,
+                None => {
+                    *state = RunState::ActiveTurn;
+                    return;
+                }
+            };
+            gui::render_dialogue(frontend, ecs, *speaker, &current.text, menu);
+            let command = player::read_command(frontend, &ecs.fetch::<Keymap>());
+            match command {
+                Some(Command::MoveUp) => player::decrement_cursor(&mut menu.cursor_index, menu.menu_size),
+                Some(Command::MoveDown) => player::increment_cursor(&mut menu.cursor_index, menu.menu_size),
+                Some(Command::Confirm) => {
+                    let next = dialogue::next(ecs, current, menu.cursor_index as usize);
+                    let (speaker, conversation) = (*speaker, conversation.clone());
+                    *state = talk(ecs, speaker, &conversation, &next);
+                },
+                // walking away takes the turn all the same
+                Some(Command::Cancel) => *state = RunState::ActiveTurn,
+                _ => {}
+            }
+        },
+        // same for orders, which apply to every companion at once
+        RunState::Orders{orders, menu} => {
+            gui::render_orders(frontend, ecs, menu);
+            let command = player::read_command(frontend, &ecs.fetch::<Keymap>());
+            match command {
+                Some(Command::MoveUp) => player::decrement_cursor(&mut menu.cursor_index, menu.menu_size),
+                Some(Command::MoveDown) => player::increment_cursor(&mut menu.cursor_index, menu.menu_size),
+                Some(Command::Confirm) => {
+                    let order = orders[menu.cursor_index as usize];
+                    *state = if allies::give_order(ecs, order) { RunState::ActiveTurn } else { RunState::PlayerTurn };
+                    gui::render_game(frontend, ecs);
+                },
+                Some(Command::Cancel) => *state = RunState::PlayerTurn,
+                _ => {}
+            }
+        },
+        // there's no backing out of a perk, one has to be picked
+        RunState::LevelUp{perks, menu} => {
+            gui::render_level_up(frontend, ecs, menu);
+            let command = player::read_command(frontend, &ecs.fetch::<Keymap>());
+            match command {
+                Some(Command::MoveUp) => player::decrement_cursor(&mut menu.cursor_index, menu.menu_size),
+                Some(Command::MoveDown) => player::increment_cursor(&mut menu.cursor_index, menu.menu_size),
+                Some(Command::Confirm) => {
+                    progression::choose(ecs, perks[menu.cursor_index as usize]);
+                    *state = RunState::ActiveTurn;
+                },
+                _ => {}
+            }
+        },
         RunState::Inventory  => {},
         RunState::LoadGame => {},
         RunState::SaveGame  => {},
         RunState::Options => {},
-        RunState::GameOver => {}
+        // read how it went, then back to the main menu with a fresh tower for the next run
+        RunState::GameOver{won, score, morgue, rank} => {
+            gui::render_game_over(frontend, ecs, *won, *score, morgue, rank);
+            frontend.wait_for_keypress();
+            *ecs = new_world();
+            *state = RunState::MainMenu;
+        }
     }
 }
 
+// scores the run and writes its morgue file, for the game over screen
+fn game_over(ecs: &World, won: bool) -> RunState {
+    let run = scores::Run::finished(ecs, won);
+    RunState::GameOver{won, score: run.score, morgue: morgue::write(ecs, won), rank: scores::record(&run)}
+}
 
-fn main() {
+// moves a conversation on to `node`, talking takes the player's turn once it's over
+fn talk(ecs: &World, speaker: Entity, conversation: &str, node: &str) -> RunState {
+    match dialogue::enter(ecs, speaker, conversation, node) {
+        Some(entered) => RunState::Dialogue{speaker, conversation: conversation.to_string(), node: node.to_string(), menu: dialogue::menu(ecs, entered)},
+        None => RunState::ActiveTurn
+    }
+}
+
+// slow going terrain like rubble or water holds up the mover's next turn
+fn pay_for_terrain(ecs: &World, id: Entity) {
+    let cost = match ecs.read_storage::<Position>().get(id) {
+        Some(pos) => ecs.fetch::<Map>().get_tile_type(pos.x, pos.y).def().cost,
+        None => return
+    };
+    if let Some(actor) = ecs.write_storage::<Actor>().get_mut(id) {
+        actor.action_points -= ((cost - 1.0) * actor.threshold as f32) as i32;
+    }
+}
+
+// confused movers lurch off in a random direction half the time
+fn stagger(ecs: &World, id: Entity, dx: i32, dy: i32) -> (i32, i32) {
+    let mut rng = ecs.write_resource::<GameRng>();
+    if !ecs.read_storage::<Confused>().contains(id) || rng.0.gen_bool(0.5) {
+        return (dx, dy);
+    }
+    let directions = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+    let lurch = directions[rng.0.gen_range(0..directions.len())];
+    drop(rng);
+    if lurch != (dx, dy) && ecs.read_storage::<Player>().contains(id) {
+        ecs.write_resource::<GameLog>().log("You stumble.");
+    }
+    lurch
+}
+
+// picks the only candidate next to the player, or asks for a direction when there are several
+fn choose_adjacent<F>(frontend: &mut dyn gui::Frontend, ecs: &World, candidates: F, ask: &str, none: &str) -> Option<(i32, i32)>
+where
+    F: Fn(&World, i32, i32) -> Vec<(i32, i32)>,
+{
+    let player_id = *ecs.fetch::<Entity>();
+    let (px, py) = ecs.read_storage::<Position>().get(player_id).map(|pos| (pos.x, pos.y))?;
+    let found = candidates(ecs, px, py);
+    match found.len() {
+        0 => {
+            ecs.write_resource::<GameLog>().log(none);
+            None
+        },
+        1 => Some(found[0]),
+        _ => {
+            ecs.write_resource::<GameLog>().log(ask);
+            gui::render_game(frontend, ecs);
+            let command = player::read_command(frontend, &ecs.fetch::<Keymap>());
+            command.and_then(|c| c.direction()).map(|(dx, dy)| (px + dx, py + dy))
+        }
+    }
+}
+
+// up the stairs to a fresh floor, with tougher monsters on it and experience for reaching it the first time
+fn climb(ecs: &mut World) -> bool {
+    if !floors::climb(ecs, SCREEN_WIDTH/2, SCREEN_HEIGHT/2) {
+        return false;
+    }
+    let depth = ecs.fetch::<Tower>().depth + 1;
+    progression::arrive(ecs, depth);
+    spawn_monsters(ecs);
+    true
+}
+
+// the monsters every floor starts out with, tougher the higher up the tower it is
+fn spawn_monsters(ecs: &mut World) {
+    let tower = *ecs.fetch::<Tower>();
+
+    // create test monstar
+    ecs.create_entity().with(Actor{action_points: 0, threshold: 10})
+                       .with(Position{x: SCREEN_WIDTH/2, y: SCREEN_HEIGHT/2 + 5})
+                       .with(Sprite{sprite: 'o', color: RED})
+                       .with(Name{name: "orc".to_string()})
+                       .with(Description{text: "A hulking brute in scavenged armour. It grunts in no language you know.".to_string()})
+                       .with(StatusEffects::default())
+                       .with(Health{current: tower.health(10), max: tower.health(10)})
+                       .with(Vision{field_of_vision: HashSet::new()})
+                       .with(Speed{speed: 1})
+                       .with(PathFollower::default())
+                       .with(OpensDoors{})
+                       .with(Faction{name: "orcs".to_string()})
+                       .with(Melee{damage: tower.damage(4)})
+                       .with(Speaks{tongue: Tongue::Orcish, shout: Some("Meat! Fresh meat for the pot!".to_string())})
+                       .with(Blocking{}).build();
+
+    // something lurking in the pond
+    ecs.create_entity().with(Actor{action_points: 0, threshold: 8})
+                       .with(Position{x: 18, y: 23})
+                       .with(Sprite{sprite: 'e', color: LIGHT_SEA})
+                       .with(Name{name: "eel".to_string()})
+                       .with(Description{text: "A long, pale eel with far too many teeth.".to_string()})
+                       .with(StatusEffects::default())
+                       .with(Health{current: tower.health(6), max: tower.health(6)})
+                       .with(Vision{field_of_vision: HashSet::new()})
+                       .with(Speed{speed: 1})
+                       .with(PathFollower::default())
+                       .with(Faction{name: "beasts".to_string()})
+                       .with(Melee{damage: tower.damage(3)})
+                       .with(Aquatic{})
+                       .with(Swimmer{})
+                       .with(Submerged{})
+                       .with(Blocking{}).build();
+}
+
+// `--keymap <file or preset>` picks the key bindings, i.e. `--keymap vi-keys`
+fn load_keymap() -> Keymap {
+    let args: Vec<String> = std::env::args().collect();
+    let requested = args.iter().position(|arg| arg == "--keymap").and_then(|i| args.get(i + 1));
+    match requested {
+        Some(name) => Keymap::load(name).unwrap_or_else(|err| {
+            eprintln!("could not load keymap, using defaults: {}", err);
+            Keymap::default_bindings()
+        }),
+        None => Keymap::default_bindings()
+    }
+}
+
+// a fresh tower with nobody in it yet, the player is added once their character is made
+fn new_world() -> World {
 
     // init ecs
     let mut ecs = World::new();
 
-    // init rng
-    let mut rng = thread_rng();
-
     // register component types with ECS
     ecs.register::<Player>();
     ecs.register::<Position>();
@@ -166,38 +711,297 @@ fn main() {
     ecs.register::<Speed>();
     ecs.register::<Vision>();
     ecs.register::<Blocking>();
+    ecs.register::<PathFollower>();
+    ecs.register::<Name>();
+    ecs.register::<Item>();
+    ecs.register::<Health>();
+    ecs.register::<Description>();
+    ecs.register::<StatusEffects>();
+    ecs.register::<OpensDoors>();
+    ecs.register::<Unlocks>();
+    ecs.register::<Carried>();
+    ecs.register::<Hidden>();
+    ecs.register::<Trap>();
+    ecs.register::<SecretDoor>();
+    ecs.register::<Perception>();
+    ecs.register::<Flammable>();
+    ecs.register::<Confused>();
+    ecs.register::<Burning>();
+    ecs.register::<Submerged>();
+    ecs.register::<Swimmer>();
+    ecs.register::<Aquatic>();
+    ecs.register::<Floats>();
+    ecs.register::<Heavy>();
+    ecs.register::<Languages>();
+    ecs.register::<Speaks>();
+    ecs.register::<Writing>();
+    ecs.register::<Teaches>();
+    ecs.register::<FloorPlan>();
+    ecs.register::<Faction>();
+    ecs.register::<Melee>();
+    ecs.register::<Companion>();
+    ecs.register::<Peaceful>();
+    ecs.register::<Dialogue>();
+    ecs.register::<Experience>();
+    ecs.register::<Character>();
 
-    //create player entity
-    let player_entity = ecs.create_entity().with(Actor{action_points:0, threshold: 5})
-                                            .with(Position{x: SCREEN_WIDTH/2, y: SCREEN_HEIGHT/2})
-                                            .with(Sprite{sprite: '@', color: WHITE })
-                                            .with(Speed{speed: 1})
-                                            .with(Vision{field_of_vision: HashSet::new()})
-                                            .with(Player{}).build();
-    
-    // create test monstar
-    ecs.create_entity().with(Actor{action_points: 0, threshold: 10})
-                       .with(Position{x: SCREEN_WIDTH/2, y: SCREEN_HEIGHT/2 + 5})
-                       .with(Sprite{sprite: 'o', color: RED})
+    // monsters get tougher the higher up the tower they're met
+    ecs.insert(Tower::default());
+    spawn_monsters(&mut ecs);
+
+    // create test item
+    ecs.create_entity().with(Position{x: 60, y: 10})
+                       .with(Sprite{sprite: '?', color: YELLOW})
+                       .with(Name{name: "scroll".to_string()})
+                       .with(Description{text: "A brittle roll of parchment covered in unfamiliar script.".to_string()})
+                       .with(Flammable{})
+                       .with(Floats{})
+                       .with(Writing{tongue: Tongue::Babelic, text: "Whoever climbs the tower must first learn to listen. The stair remembers every voice.".to_string()})
+                       .with(Teaches{tongue: Tongue::Babelic, amount: 30})
+                       .with(Item{}).build();
+
+    // a builder's plan of the floor, reading it maps everything
+    ecs.create_entity().with(Position{x: 48, y: 14})
+                       .with(Sprite{sprite: '?', color: LIGHT_BLUE})
+                       .with(Name{name: "floor plan".to_string()})
+                       .with(Description{text: "A builder's drawing of this floor, every wall and door inked in.".to_string()})
+                       .with(Flammable{})
+                       .with(Floats{})
+                       .with(Writing{tongue: Tongue::Babelic, text: "The second course, as laid.".to_string()})
+                       .with(FloorPlan{})
+                       .with(Item{}).build();
+
+    // words carved into the base of one of the statues, the statue itself is the sprite
+    ecs.create_entity().with(Position{x: 36, y: 21})
+                       .with(Name{name: "inscription".to_string()})
+                       .with(Description{text: "Deep letters cut into the statue's plinth, worn smooth by many hands.".to_string()})
+                       .with(Writing{tongue: Tongue::Babelic, text: "Here stood the first of the builders, who spoke and was understood by all.".to_string()})
+                       .build();
+
+    // key to the storeroom the scroll is locked in
+    ecs.create_entity().with(Position{x: 15, y: 36})
+                       .with(Sprite{sprite: '-', color: GOLD})
+                       .with(Name{name: "brass key".to_string()})
+                       .with(Description{text: "A heavy brass key, green with age.".to_string()})
+                       .with(Unlocks{lock: 1})
+                       .with(Heavy{})
+                       .with(Item{}).build();
+
+    // an old scribe who'd rather talk than fight
+    let hermit = ecs.create_entity().with(Actor{action_points: 0, threshold: 10})
+                       .with(Position{x: 36, y: 23})
+                       .with(Sprite{sprite: 'h', color: LIGHT_AMBER})
+                       .with(Name{name: "hermit".to_string()})
+                       .with(Description{text: "A stooped old man in ink-stained robes, muttering to the statues.".to_string()})
+                       .with(StatusEffects::default())
+                       .with(Health{current: 8, max: 8})
+                       .with(Vision{field_of_vision: HashSet::new()})
+                       .with(Speed{speed: 1})
+                       .with(PathFollower::default())
+                       .with(Faction{name: "scribes".to_string()})
+                       .with(Melee{damage: 2})
+                       .with(Peaceful{})
+                       .with(Dialogue{conversation: "hermit".to_string()})
+                       .with(Blocking{}).build();
+    ecs.create_entity().with(Sprite{sprite: '"', color: LIGHTEST_GREY})
+                       .with(Name{name: "bone charm".to_string()})
+                       .with(Description{text: "A knuckle bone on a leather cord, carved with tiny, careful letters.".to_string()})
+                       .with(Carried{owner: hermit})
+                       .with(Item{}).build();
+
+    // the player's dog, which came up the tower with them
+    ecs.create_entity().with(Actor{action_points: 0, threshold: 4})
+                       .with(Position{x: SCREEN_WIDTH/2 + 1, y: SCREEN_HEIGHT/2 + 1})
+                       .with(Sprite{sprite: 'd', color: LIGHT_ORANGE})
+                       .with(Name{name: "hound".to_string()})
+                       .with(Description{text: "Your loyal hound, scarred muzzle and all. It goes where you go.".to_string()})
+                       .with(StatusEffects::default())
+                       .with(Health{current: 12, max: 12})
                        .with(Vision{field_of_vision: HashSet::new()})
                        .with(Speed{speed: 1})
+                       .with(PathFollower::default())
+                       .with(Melee{damage: 3})
+                       .with(Swimmer{})
+                       .with(Companion{order: allies::Order::Follow})
                        .with(Blocking{}).build();
 
     // create gamestate resources
-    let main_menu = gui::Menu::new(3, vec_of_strings!["New Game", "Load Game", "Options", "Quit"]);
-    let current_level = Map::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let main_menu = gui::Menu::new(4, vec_of_strings!["New Game", "Load Game", "High Scores", "Options", "Quit"]);
+    tiles::load();
+    let factions = Factions::load();
+    dialogue::load(&factions);
+    cutscene::load();
+    let current_level = Map::new(SCREEN_WIDTH, MAP_HEIGHT);
+    let cluster_graph = ClusterGraph::new(&current_level);
     let turn_queue : Vec<Entity> = Vec::new();
+    let keymap = load_keymap();
+    let travel = player::Travel::default();
+    let mouse_cursor = gui::MouseCursor::default();
+    let auto_explore = player::AutoExplore::default();
+    let repeat = player::Repeat::default();
+    let mut game_log = GameLog::default();
+    game_log.log("Welcome to the tower of BABEL.");
 
     ecs.insert(main_menu);
     ecs.insert(current_level);
+    ecs.insert(cluster_graph);
     ecs.insert(turn_queue);
-    ecs.insert(player_entity);
+    ecs.insert(keymap);
+    ecs.insert(travel);
+    ecs.insert(mouse_cursor);
+    ecs.insert(auto_explore);
+    ecs.insert(repeat);
+    ecs.insert(game_log);
+    ecs.insert(Clock::default());
+    ecs.insert(dialogue::StoryFlags::default());
+    ecs.insert(factions);
+    ecs.insert(combat::LastTarget::default());
+    ecs.insert(RunStats::default());
 
-    // gui init code
-    let mut tcod = gui::Tcod::new();
+    // test traps around the start, and a second way into the storeroom
+    traps::spawn_trap(&mut ecs, TrapKind::Pit, 44, 22, 12);
+    traps::spawn_trap(&mut ecs, TrapKind::Dart, 34, 26, 14);
+    traps::spawn_trap(&mut ecs, TrapKind::Alarm, 48, 28, 10);
+    traps::spawn_trap(&mut ecs, TrapKind::Teleport, 52, 16, 15);
+    traps::spawn_trap(&mut ecs, TrapKind::Summoning, 28, 36, 16);
+    traps::spawn_trap(&mut ecs, TrapKind::Fire, 66, 30, 12);
+    traps::spawn_trap(&mut ecs, TrapKind::PoisonGas, 60, 24, 12);
+    traps::spawn_trap(&mut ecs, TrapKind::ConfusionGas, 30, 30, 12);
+    traps::spawn_trap(&mut ecs, TrapKind::Amnesia, 50, 36, 14);
+    traps::spawn_secret_door(&mut ecs, 56, 9, 15);
+    ecs
+}
+
+fn main() {
+    let mut ecs = new_world();
+
+    // gui init code, `--terminal` plays in the terminal (i.e. over ssh) instead of a libtcod window
+    let mut frontend: Box<dyn gui::Frontend> = match std::env::args().any(|arg| arg == "--terminal") {
+        #[cfg(unix)]
+        true => Box::new(terminal::Terminal::new()),
+        #[cfg(not(unix))]
+        true => {
+            eprintln!("the terminal frontend needs a unix tty, opening a window instead");
+            Box::new(gui::Tcod::new())
+        },
+        false => Box::new(gui::Tcod::new())
+    };
 
     let mut state = RunState::MainMenu;
-    while !tcod.root.window_closed() {
-        game_loop(&mut state, &mut tcod, &mut ecs);
+    while !frontend.window_closed() {
+        game_loop(&mut state, frontend.as_mut(), &mut ecs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use headless::HeadlessRenderer;
+    use gui::{Renderer, Input};
+    use tcod::console::TextAlignment;
+    use tcod::input::{Key, KeyCode};
+    use specs::Join;
+
+    fn orc_health(ecs: &World) -> Option<i32> {
+        let names = ecs.read_storage::<Name>();
+        let health = ecs.read_storage::<Health>();
+        (&names, &health).join().find(|(name, _)| name.name == "orc").map(|(_, health)| health.max)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn a_seed_names_a_nameless_character_the_same_every_time() {
+        let name = |seed| {
+            let mut ecs = new_world();
+            let player = chargen::build(&mut ecs, &chargen::Creation::replay(seed), SCREEN_WIDTH/2, SCREEN_HEIGHT/2);
+            let name = ecs.read_storage::<Character>().get(player).map(|c| c.name.clone());
+            name
+        };
+        assert_eq!(name(3), name(3));
+    }
+
+    #[test]
+    fn climbing_the_stairs_is_worth_experience_and_toughens_the_monsters() {
+        let mut ecs = new_world();
+        let player = chargen::build(&mut ecs, &chargen::Creation::replay(7), SCREEN_WIDTH/2, SCREEN_HEIGHT/2);
+        let first_orc = orc_health(&ecs).unwrap();
+        assert!(!climb(&mut ecs));
+
+        ecs.write_storage::<Position>().insert(player, Position{x: 74, y: 4}).unwrap();
+        assert!(climb(&mut ecs));
+        assert_eq!(ecs.fetch::<Tower>().depth, 2);
+        assert!(orc_health(&ecs).unwrap() > first_orc);
+        let xp = ecs.read_storage::<Experience>().get(player).map(|exp| (exp.level, exp.xp));
+        assert_ne!(xp, Some((1, 0)));
+    }
+
+    // draws into memory and answers every wait for input with the same key
+    struct Pressing {
+        screen: HeadlessRenderer,
+        key: Key
+    }
+
+    impl Renderer for Pressing {
+        fn put_char_ex(&mut self, x: i32, y: i32, glyph: char, foreground: Color, background: Color) {
+            self.screen.put_char_ex(x, y, glyph, foreground, background);
+        }
+        fn set_char_background(&mut self, x: i32, y: i32, background: Color) {
+            self.screen.set_char_background(x, y, background);
+        }
+        fn print(&mut self, x: i32, y: i32, text: &str, alignment: TextAlignment) {
+            self.screen.print(x, y, text, alignment);
+        }
+        fn clear(&mut self) {
+            self.screen.clear();
+        }
+        fn flush(&mut self) {
+            self.screen.flush();
+        }
+    }
+
+    impl Input for Pressing {
+        fn wait_for_event(&mut self) -> InputEvent {
+            InputEvent::Key(self.key)
+        }
+        fn wait_for_event_timeout(&mut self, _millis: u64) -> Option<InputEvent> {
+            None
+        }
+        fn window_closed(&self) -> bool {
+            false
+        }
+    }
+
+    // a new character on `seed` rests for `turns` turns, then everything said and everyone left standing
+    fn rest_out(seed: u64, turns: u32) -> Vec<String> {
+        let mut ecs = new_world();
+        let player = chargen::build(&mut ecs, &chargen::Creation::replay(seed), SCREEN_WIDTH/2, SCREEN_HEIGHT/2);
+        // tough enough to sit through anything on the floor
+        ecs.write_storage::<Health>().insert(player, Health{current: 500, max: 500}).unwrap();
+        let mut key = Key::default();
+        key.code = KeyCode::Char;
+        key.printable = 's';
+        let mut frontend = Pressing{screen: HeadlessRenderer::new(SCREEN_WIDTH, SCREEN_HEIGHT), key};
+        let mut state = RunState::Tick;
+        for _ in 0..10_000 {
+            if ecs.fetch::<RunStats>().turns >= turns {
+                break;
+            }
+            game_loop(&mut state, &mut frontend, &mut ecs);
+        }
+        assert!(ecs.fetch::<RunStats>().turns >= turns, "the game stopped taking turns");
+
+        let mut summary: Vec<String> = ecs.fetch::<GameLog>().entries.iter().map(|entry| entry.text.clone()).collect();
+        let names = ecs.read_storage::<Name>();
+        let positions = ecs.read_storage::<Position>();
+        let health = ecs.read_storage::<Health>();
+        summary.extend((&names, &positions, &health).join()
+            .map(|(name, pos, health)| format!("{} at {}, {} with {} health", name.name, pos.x, pos.y, health.current)));
+        summary
+    }
+
+    #[test]
+    fn a_seed_plays_out_the_same_every_time() {
+        let first = rest_out(11, 40);
+        assert!(first.iter().any(|line| line.contains(" hits ")), "nothing came to blows");
+        assert_eq!(first, rest_out(11, 40));
+    }
+}
@@ -1,8 +1,12 @@
 use std::cmp;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use tcod::colors::Color;
+use crate::tiles::{self, TileDef};
 
 const VIEW_DIST: i32 = 9;
 const VIEW_DIST_SQ: i32 = VIEW_DIST * VIEW_DIST;
+// how much further a diagonal step counts for when pathing
+pub const DIAGONAL_COST: f32 = 1.45;
 
 pub struct Map {
     pub width: i32,
@@ -10,36 +14,166 @@ pub struct Map {
     terrain: Vec<TileType>,
     pub visited: HashSet<Tile>,
     pub visible: HashSet<Tile>,
-    pub blocked: Vec<bool>
+    // tiles that came into the player's view on the last vision update
+    pub newly_visible: Vec<Tile>,
+    pub blocked: Vec<bool>,
+    // bumped whenever terrain changes so cached paths know to replan
+    pub revision: u32,
+    // entities the player last saw on each tile, kept with the floor so every level has its own memory
+    pub remembered: HashMap<usize, Vec<RememberedEntity>>,
+    // lock id of each locked door, opened by a key with the same id
+    pub locks: HashMap<usize, u32>,
+    // turns each tile has left to burn, 0 where nothing is alight
+    pub fire: Vec<u8>,
+    // gas hanging over each tile, spread and thinned out by the environment system
+    pub gas: Vec<Option<Gas>>
 }
 
+// what an entity looked like when the player last saw it
+#[derive(Clone, Debug, PartialEq)]
+pub struct RememberedEntity {
+    pub glyph: char,
+    pub color: Color,
+    pub name: String,
+    pub turn_seen: u32
+}
+
+// events that make the player's picture of the floor unreliable
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryLoss {
+    // the layout is revealed, but anything seen on it before may have moved
+    MagicMapping,
+    // the player forgets the floor entirely
+    Amnesia
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GasKind {
+    Poison,
+    // thick enough smoke hides whatever is behind it
+    Smoke,
+    Confusion
+}
+
+impl GasKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            GasKind::Poison => "poison gas",
+            GasKind::Smoke => "smoke",
+            GasKind::Confusion => "confusion gas",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gas {
+    pub kind: GasKind,
+    pub density: u8
+}
+
+// smoke at least this dense blocks vision
+pub const OPAQUE_SMOKE: u8 = 4;
+
 // TODO: handle maps that are bigger than the screen width (i.e. handle wrapping)
 impl Map {
   pub fn new(width: i32, height: i32) -> Map {
-      let mut terrain = vec![TileType::Ground; (width * height) as usize];
+      // terrain that isn't built into the game falls back to plain ground if tiles.cfg leaves it out
+      let tile = |id: &str| TileType::named(id).unwrap_or(TileType::GROUND);
+      let mut terrain = vec![TileType::GROUND; (width * height) as usize];
       for j in 0..height {
           for i in 0..width {
               if i == 0 || j == 0 || i == width - 1 || j == height - 1 {
-                terrain[(i + j*width) as usize] = TileType::Wall;
+                terrain[(i + j*width) as usize] = TileType::WALL;
               } else if j == 18 && (i == 38 || i == 39 || i == 40 || i == 41 || i == 42) {
-                terrain[(i + j*width) as usize] = TileType::Wall;
+                terrain[(i + j*width) as usize] = TileType::WALL;
               } else if j == 32 && (i == 38 || i == 39 || i == 41 || i == 42) {
-                terrain[(i + j*width) as usize] = TileType::Wall;
+                terrain[(i + j*width) as usize] = TileType::WALL;
+              } else if j == 32 && i == 40 {
+                terrain[(i + j*width) as usize] = TileType::DOOR_CLOSED;
+              } else if ((i == 56 || i == 64) && (6..=12).contains(&j)) || ((j == 6 || j == 12) && (56..=64).contains(&i)) {
+                // locked storeroom
+                terrain[(i + j*width) as usize] = if j == 12 && i == 60 { TileType::DOOR_CLOSED } else { TileType::WALL };
+              } else if i == 74 && j == 4 {
+                terrain[(i + j*width) as usize] = tile("stairs_up");
+              } else if j == 21 && (i == 36 || i == 44) {
+                terrain[(i + j*width) as usize] = tile("statue");
+              } else if (i - 20).pow(2) + (j - 20).pow(2) < 49 {
+                // pond, deep in the middle with a bridge across
+                terrain[(i + j*width) as usize] = if j == 20 { tile("bridge") } else { TileType::DEEP_WATER };
               } else if (i - 20).pow(2) + (j - 20).pow(2) < 100 {
-                terrain[(i + j*width) as usize] = TileType::Water;
+                terrain[(i + j*width) as usize] = tile("shallow_water");
+              } else if (6..=14).contains(&i) && (30..=33).contains(&j) {
+                terrain[(i + j*width) as usize] = if i == 10 { tile("bridge") } else { tile("chasm") };
+              } else if (66..=72).contains(&i) && (38..=40).contains(&j) {
+                terrain[(i + j*width) as usize] = tile("lava");
+              } else if (i - 68).pow(2) + (j - 30).pow(2) < 30 {
+                terrain[(i + j*width) as usize] = tile("grass");
+              } else if (30..=34).contains(&i) && (10..=11).contains(&j) {
+                terrain[(i + j*width) as usize] = tile("rubble");
               }
           }
       }
+      let mut locks = HashMap::new();
+      if width > 64 && height > 12 {
+        locks.insert((60 + 12*width) as usize, 1);
+      }
       return Map{width: width,
                  height: height,
                  terrain: terrain,
                  visited: HashSet::new(),
                  visible: HashSet::new(),
-                 blocked: vec![false; (width * height) as usize]};
+                 newly_visible: Vec::new(),
+                 blocked: vec![false; (width * height) as usize],
+                 revision: 0,
+                 remembered: HashMap::new(),
+                 locks,
+                 fire: vec![0; (width * height) as usize],
+                 gas: vec![None; (width * height) as usize]};
   }
 
   pub fn tile_in_view(&self, x: i32, y: i32) -> bool{
-    return self.visible.contains(&Tile{x: x, y: y, tile_type: TileType::Ground})
+    if !self.in_bounds(x, y) { return false; }
+    return self.visible.contains(&Tile{x: x, y: y, tile_type: self.get_tile_type(x, y)})
+  }
+
+  pub fn tile_visited(&self, x: i32, y: i32) -> bool{
+    if !self.in_bounds(x, y) { return false; }
+    self.visited.contains(&Tile{x, y, tile_type: self.get_tile_type(x, y)})
+  }
+
+  // records the tiles the player can see now, dropping anything remembered about them before
+  pub fn update_memory(&mut self, seen: &HashSet<Tile>) {
+    let stale: HashSet<(i32, i32)> = seen.iter().map(|t| (t.x, t.y)).collect();
+    self.visited.retain(|t| !stale.contains(&(t.x, t.y)) || seen.contains(t));
+    self.visited.extend(seen.iter().copied());
+    for tile in seen.iter() {
+      let idx = self.get_index(tile.x, tile.y);
+      self.remembered.remove(&idx);
+    }
+  }
+
+  pub fn remember_entity(&mut self, x: i32, y: i32, entity: RememberedEntity) {
+    let idx = self.get_index(x, y);
+    self.remembered.entry(idx).or_default().push(entity);
+  }
+
+  // what the player last saw on a tile that is out of view
+  pub fn remembered_at(&self, x: i32, y: i32) -> &[RememberedEntity] {
+    if !self.in_bounds(x, y) { return &[]; }
+    self.remembered.get(&self.get_index(x, y)).map_or(&[], |entries| entries.as_slice())
+  }
+
+  pub fn forget(&mut self, loss: MemoryLoss) {
+    self.remembered.clear();
+    match loss {
+      // the whole floor is remembered as it is right now
+      MemoryLoss::MagicMapping => {
+        self.visited = (0..self.height).flat_map(|y| (0..self.width).map(move |x| (x, y)))
+          .map(|(x, y)| Tile{x, y, tile_type: self.get_tile_type(x, y)})
+          .collect();
+      },
+      MemoryLoss::Amnesia => self.visited.clear()
+    }
   }
 
   pub fn get_tiles_in_view(&mut self, x: i32, y: i32) -> HashSet<Tile> {
@@ -75,9 +209,9 @@ impl Map {
     let mut e2;
 
     while self.get_distance_sq(start_x, start_y, x0, y0) < length_sq as f32 {
-        let tile_type = self.terrain[self.get_index(x0, y0)];
-        visible.insert(Tile{x: x0, y: y0, tile_type: tile_type});
-        if self.tile_blocks_vision(tile_type) || (x0 == x1 && y0 == y1) {
+        let idx = self.get_index(x0, y0);
+        visible.insert(Tile{x: x0, y: y0, tile_type: self.terrain[idx]});
+        if self.blocks_vision(idx) || (x0 == x1 && y0 == y1) {
             break;
         }
         e2 = 2*err;
@@ -103,14 +237,79 @@ impl Map {
       return (y0 as usize * self.width as usize) + x0 as usize;
   }
 
-  fn tile_blocks_vision(&self, tile_type: TileType) -> bool {
-      return tile_type == TileType::Wall;
+  pub fn get_tile_type(&self, x: i32, y: i32) -> TileType {
+      self.terrain[self.get_index(x, y)]
+  }
+
+  pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+      x >= 0 && y >= 0 && x < self.width && y < self.height
+  }
+
+  pub fn set_tile_type(&mut self, x: i32, y: i32, tile_type: TileType) {
+      let idx = self.get_index(x, y);
+      if self.terrain[idx] != tile_type {
+          self.terrain[idx] = tile_type;
+          self.revision = self.revision.wrapping_add(1);
+      }
+  }
+
+  fn blocks_vision(&self, idx: usize) -> bool {
+      !self.terrain[idx].def().transparent
+          || self.gas[idx].is_some_and(|gas| gas.kind == GasKind::Smoke && gas.density >= OPAQUE_SMOKE)
+  }
+
+  pub fn is_burning(&self, x: i32, y: i32) -> bool {
+      self.in_bounds(x, y) && self.fire[self.get_index(x, y)] > 0
+  }
+
+  pub fn gas_at(&self, x: i32, y: i32) -> Option<Gas> {
+      if !self.in_bounds(x, y) { return None; }
+      self.gas[self.get_index(x, y)]
+  }
+
+  // sets a tile alight for the given number of turns, water puts it straight out
+  pub fn ignite(&mut self, x: i32, y: i32, turns: u8) {
+      let idx = self.get_index(x, y);
+      if self.terrain[idx].def().depth == 0 {
+          self.fire[idx] = self.fire[idx].max(turns);
+      }
+  }
+
+  // adds gas to a tile, a different kind of gas is pushed out if the new cloud is denser
+  pub fn release_gas(&mut self, x: i32, y: i32, kind: GasKind, density: u8) {
+      let idx = self.get_index(x, y);
+      add_gas(&mut self.gas[idx], kind, density);
+  }
+
+  pub fn is_locked(&self, idx: usize) -> bool {
+      self.locks.contains_key(&idx)
+  }
+
+  pub fn unlock(&mut self, idx: usize) {
+      if self.locks.remove(&idx).is_some() {
+          self.revision = self.revision.wrapping_add(1);
+      }
+  }
+
+  // a closed door that anything able to work a handle could open
+  pub fn is_openable_door(&self, idx: usize) -> bool {
+      self.terrain[idx] == TileType::DOOR_CLOSED && !self.is_locked(idx)
+  }
+
+  // walkable now, or only behind an unlocked door
+  pub fn is_passable(&self, idx: usize) -> bool {
+      self.is_walkable(idx) || self.is_openable_door(idx)
+  }
+
+  // whether a mover can step onto (or open its way onto) a tile this turn
+  pub fn can_enter(&self, idx: usize, opens_doors: bool) -> bool {
+      !self.blocked[idx] || (opens_doors && self.is_openable_door(idx))
   }
 
   // sets blocked vec based on wall locations, entity locations are set in the mapblocking system
   pub fn set_tile_blocked(&mut self) {
-    for (i, tile) in self.terrain.iter().enumerate() {
-        if tile == &TileType::Wall || tile == &TileType::Water {
+    for i in 0..self.terrain.len() {
+        if !self.is_walkable(i) {
             self.blocked[i] = true;
         }
         else {
@@ -119,6 +318,11 @@ impl Map {
     }
   }
 
+  // terrain-only walkability, ignores entities standing on the tile
+  pub fn is_walkable(&self, idx: usize) -> bool {
+      self.terrain[idx].def().walkable
+  }
+
 pub fn is_exit_valid(&self, x:i32, y:i32) -> bool {
     if x < 1 || x > self.width-1 || y < 1 || y > self.height-1 { return false; }
     let idx = self.get_index(x, y);
@@ -132,27 +336,46 @@ pub fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
     self.get_distance_sq(p1.0 as i32, p1.1 as i32, p2.0 as i32, p2.1 as i32)
 }
 
-pub fn get_available_exits(&self, idx:usize) -> Vec<(usize, f32)> {
+// movers that can open doors also get exits through closed, unlocked doors
+// step costs are scaled by the destination tile's movement cost
+pub fn get_available_exits(&self, idx:usize, opens_doors: bool) -> Vec<(usize, f32)> {
     let mut exits = Vec::new();
     let x = idx as i32 % self.width;
     let y = idx as i32 / self.width;
     let w = self.width as usize;
+    let open = |x: i32, y: i32| self.is_exit_valid(x, y) || (opens_doors && self.in_bounds(x, y) && self.is_openable_door(self.get_index(x, y)));
+    let cost = |to: usize| self.step_cost(idx, to);
 
     // Cardinal directions
-    if self.is_exit_valid(x-1, y) { exits.push((idx-1, 1.0)) };
-    if self.is_exit_valid(x+1, y) { exits.push((idx+1, 1.0)) };
-    if self.is_exit_valid(x, y-1) { exits.push((idx-w, 1.0)) };
-    if self.is_exit_valid(x, y+1) { exits.push((idx+w, 1.0)) };
+    if open(x-1, y) { exits.push((idx-1, cost(idx-1))) };
+    if open(x+1, y) { exits.push((idx+1, cost(idx+1))) };
+    if open(x, y-1) { exits.push((idx-w, cost(idx-w))) };
+    if open(x, y+1) { exits.push((idx+w, cost(idx+w))) };
 
     // Diagonals
-    if self.is_exit_valid(x-1, y-1) { exits.push(((idx-w)-1, 1.45)); }
-    if self.is_exit_valid(x+1, y-1) { exits.push(((idx-w)+1, 1.45)); }
-    if self.is_exit_valid(x-1, y+1) { exits.push(((idx+w)-1, 1.45)); }
-    if self.is_exit_valid(x+1, y+1) { exits.push(((idx+w)+1, 1.45)); }
+    if open(x-1, y-1) { exits.push(((idx-w)-1, cost((idx-w)-1))); }
+    if open(x+1, y-1) { exits.push(((idx-w)+1, cost((idx-w)+1))); }
+    if open(x-1, y+1) { exits.push(((idx+w)-1, cost((idx+w)-1))); }
+    if open(x+1, y+1) { exits.push(((idx+w)+1, cost((idx+w)+1))); }
 
     exits
 }
 
+// cost of stepping between two neighbouring tiles, the terrain's cost stretched for diagonals
+pub fn step_cost(&self, from: usize, to: usize) -> f32 {
+    let w = self.width as usize;
+    let diagonal = from % w != to % w && from / w != to / w;
+    self.terrain[to].def().cost * if diagonal { DIAGONAL_COST } else { 1.0 }
+}
+
+}
+
+pub fn add_gas(cell: &mut Option<Gas>, kind: GasKind, density: u8) {
+    match cell {
+        Some(gas) if gas.kind == kind => gas.density = gas.density.saturating_add(density),
+        Some(gas) if gas.density >= density => {},
+        _ => *cell = Some(Gas{kind, density})
+    }
 }
 
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
@@ -163,9 +386,32 @@ pub struct Tile {
 }
 
 
+// handle to a terrain definition loaded from data/tiles.cfg
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
-pub enum TileType {
-    Ground,
-    Water,
-    Wall
-}
\ No newline at end of file
+pub struct TileType(u16);
+
+impl TileType {
+    // same order as tiles::CORE_TILES
+    pub const GROUND: TileType = TileType(0);
+    pub const WALL: TileType = TileType(1);
+    pub const DEEP_WATER: TileType = TileType(2);
+    pub const DOOR_CLOSED: TileType = TileType(3);
+    pub const DOOR_OPEN: TileType = TileType(4);
+
+    // looks a tile up by its section name in tiles.cfg
+    pub fn named(id: &str) -> Option<TileType> {
+        tiles::tile_set().id(id).map(|index| TileType(index as u16))
+    }
+
+    pub fn def(&self) -> &'static TileDef {
+        &tiles::tile_set().defs[self.0 as usize]
+    }
+
+    pub fn name(&self) -> &'static str {
+        &self.def().name
+    }
+
+    pub fn description(&self) -> &'static str {
+        &self.def().description
+    }
+}
@@ -1,4 +1,4 @@
-use crate::components::{Position, Blocking};
+use crate::components::{Position, Blocking, Submerged};
 use crate::map::Map;
 use specs::{System, ReadStorage, WriteExpect};
 
@@ -12,16 +12,17 @@ pub struct MapBlockingSystem;
 impl<'a> System<'a> for MapBlockingSystem {
     type SystemData = (ReadStorage<'a, Position>,
                        ReadStorage<'a, Blocking>,
+                       ReadStorage<'a, Submerged>,
                        WriteExpect<'a, Map>);
 
-    fn run(&mut self, (pos, blocking, mut map): Self::SystemData) {
+    fn run(&mut self, (pos, blocking, submerged, mut map): Self::SystemData) {
         use specs::Join;
 
         let width = map.width;
         // set blocking for each wall
         map.set_tile_blocked();
-        // set blocking for each entity
-        for (pos, _) in (&pos, &blocking).join() {
+        // set blocking for each entity, except lurkers under the water which nobody can see to go around
+        for (pos, _, _) in (&pos, &blocking, !&submerged).join() {
             map.blocked[(pos.x + pos.y*width) as usize] = true;
         }
 
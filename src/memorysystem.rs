@@ -0,0 +1,36 @@
+use crate::components::{Position, Sprite, Name, Player, Hidden, Submerged};
+use crate::map::{Map, RememberedEntity};
+use crate::turnsystem::Clock;
+use specs::{System, ReadStorage, Read, WriteExpect};
+
+/*
+Remember what the player saw on each visible tile, so it can still be drawn once it's out of view
+*/
+
+pub struct MemorySystem;
+
+impl<'a> System<'a> for MemorySystem {
+    type SystemData = (ReadStorage<'a, Position>,
+                       ReadStorage<'a, Sprite>,
+                       ReadStorage<'a, Name>,
+                       ReadStorage<'a, Player>,
+                       ReadStorage<'a, Hidden>,
+                       ReadStorage<'a, Submerged>,
+                       Read<'a, Clock>,
+                       WriteExpect<'a, Map>);
+
+    fn run(&mut self, (pos, sprite, name, player, hidden, submerged, clock, mut map): Self::SystemData) {
+        use specs::Join;
+        // the vision system already cleared memories for every tile in view
+        for (pos, sprite, name, _, _, _) in (&pos, &sprite, name.maybe(), !&player, !&hidden, !&submerged).join() {
+            if map.tile_in_view(pos.x, pos.y) {
+                map.remember_entity(pos.x, pos.y, RememberedEntity{
+                    glyph: sprite.sprite,
+                    color: sprite.color,
+                    name: name.map_or("something".to_string(), |n| n.name.clone()),
+                    turn_seen: clock.tick
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use crate::components::{Position, Sprite, Name, Health, Melee, Perception, Languages, Experience, Character, Carried, Hidden, Submerged};
+use crate::gamelog::GameLog;
+use crate::language::{self, FLUENT};
+use crate::map::Map;
+use crate::progression::Tower;
+use crate::stats::RunStats;
+use specs::{Entity, World, WorldExt, Join};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+The end of a run: a summary for the game over screen, and a plain text morgue file of the whole thing
+*/
+
+const MORGUE_DIR: &str = "morgue";
+// how many of the last log messages make it into the file
+const LAST_MESSAGES: usize = 12;
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+/// `name` in the directory the game's executable is in, so morgue files and scores end up in one
+/// place whichever directory the game was started from. Falls back to the working directory.
+pub fn beside_game(name: &str) -> PathBuf {
+    std::env::current_exe().ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(name)))
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+/// Seconds since the epoch as "2024-05-01 13:45" UTC.
+pub fn date(seconds: u64) -> String {
+    // days to a civil date, after Howard Hinnant's days_from_civil in reverse
+    let days = (seconds / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, seconds % 86400 / 3600, seconds % 3600 / 60)
+}
+
+/// The player's name and background, i.e. "Kjel the soldier".
+pub fn who(ecs: &World) -> String {
+    let player = *ecs.fetch::<Entity>();
+    ecs.read_storage::<Character>().get(player)
+        .map_or("The climber".to_string(), |c| format!("{} the {}", c.name, c.background.name()))
+}
+
+/// How the run ended, in a line, i.e. "Kjel the soldier was killed by an orc on floor 1."
+pub fn epitaph(ecs: &World, won: bool) -> String {
+    if won {
+        return format!("{} reached the top of the tower.", who(ecs));
+    }
+    let cause = ecs.try_fetch::<RunStats>().and_then(|stats| stats.cause.clone()).unwrap_or("died".to_string());
+    let depth = ecs.try_fetch::<Tower>().map_or(1, |tower| tower.depth);
+    format!("{} {} on floor {}.", who(ecs), cause, depth)
+}
+
+/// The lines shown on the game over screen, which also head the morgue file.
+pub fn summary(ecs: &World, won: bool) -> Vec<String> {
+    let player = *ecs.fetch::<Entity>();
+    let stats = ecs.fetch::<RunStats>();
+    let level = ecs.read_storage::<Experience>().get(player).map_or(1, |exp| exp.level);
+    let deepest = ecs.try_fetch::<Tower>().map_or(1, |tower| tower.deepest);
+    vec![
+        epitaph(ecs, won),
+        format!("Reached level {} and floor {} in {} turns.", level, deepest, stats.turns),
+        format!("Killed {} creatures, dealt {} damage and took {}.", stats.total_kills(), stats.damage_dealt, stats.damage_taken),
+        format!("Seed: {}", stats.seed)
+    ]
+}
+
+// the final floor as the player knew it, with whatever they could see on it
+fn map_dump(ecs: &World) -> Vec<String> {
+    let map = ecs.fetch::<Map>();
+    let mut rows = vec![vec![' '; map.width as usize]; map.height as usize];
+    for tile in map.visited.iter() {
+        rows[tile.y as usize][tile.x as usize] = tile.tile_type.def().glyph;
+    }
+    let positions = ecs.read_storage::<Position>();
+    let sprites = ecs.read_storage::<Sprite>();
+    let hidden = ecs.read_storage::<Hidden>();
+    let submerged = ecs.read_storage::<Submerged>();
+    let player = *ecs.fetch::<Entity>();
+    for (ent, pos, sprite, _, _) in (&ecs.entities(), &positions, &sprites, !&hidden, !&submerged).join() {
+        if ent != player && map.tile_in_view(pos.x, pos.y) {
+            rows[pos.y as usize][pos.x as usize] = sprite.sprite;
+        }
+    }
+    if let Some(pos) = positions.get(player) {
+        rows[pos.y as usize][pos.x as usize] = '@';
+    }
+    let mut lines: Vec<String> = rows.into_iter().map(|row| row.into_iter().collect::<String>().trim_end().to_string()).collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    let first = lines.iter().position(|line| !line.is_empty()).unwrap_or(lines.len());
+    lines.split_off(first)
+}
+
+/// The whole morgue file.
+pub fn morgue(ecs: &World, won: bool) -> String {
+    let player = *ecs.fetch::<Entity>();
+    let mut out = vec!["BABEL morgue file".to_string(), date(now()), String::new()];
+    out.extend(summary(ecs, won));
+
+    out.push(String::new());
+    if let Some(health) = ecs.read_storage::<Health>().get(player) {
+        out.push(format!("Health: {}/{}", health.current.max(0), health.max));
+    }
+    let damage = ecs.read_storage::<Melee>().get(player).map_or(1, |m| m.damage);
+    let perception = ecs.read_storage::<Perception>().get(player).map_or(0, |p| p.skill);
+    out.push(format!("Damage: {}   Perception: {}", damage, perception));
+    if let Some(languages) = ecs.read_storage::<Languages>().get(player) {
+        let mut tongues: Vec<String> = languages.fluency.iter().filter(|(_, fluency)| **fluency > 0)
+            .map(|(tongue, fluency)| if *fluency >= FLUENT { format!("{} (fluent)", tongue.name()) } else { format!("{} ({})", tongue.name(), fluency) })
+            .collect();
+        tongues.sort();
+        out.push(format!("Tongues: Common{}", tongues.iter().map(|t| format!(", {}", t)).collect::<String>()));
+    }
+
+    out.push(String::new());
+    out.push("Carrying:".to_string());
+    let names = ecs.read_storage::<Name>();
+    let mut carried: Vec<String> = (&ecs.read_storage::<Carried>(), &names).join()
+        .filter(|(carried, _)| carried.owner == player)
+        .map(|(_, name)| name.name.clone())
+        .collect();
+    carried.sort();
+    if carried.is_empty() {
+        out.push("  nothing".to_string());
+    }
+    out.extend(carried.into_iter().map(|item| format!("  {}", item)));
+
+    out.push(String::new());
+    out.push("Kills:".to_string());
+    let stats = ecs.fetch::<RunStats>();
+    if stats.kills.is_empty() {
+        out.push("  none".to_string());
+    }
+    out.extend(stats.kills.iter().map(|(name, count)| format!("  {:>3} {}", count, name)));
+
+    out.push(String::new());
+    out.push("Last messages:".to_string());
+    let log = ecs.fetch::<GameLog>();
+    for entry in log.recent(LAST_MESSAGES) {
+        match &entry.speech {
+            Some((tongue, speech)) => out.push(format!("  {} \"{}\"", entry.text, language::render_for_player(ecs, speech, *tongue))),
+            None => out.push(format!("  {}", entry.text))
+        }
+    }
+
+    out.push(String::new());
+    out.push("The final floor:".to_string());
+    out.extend(map_dump(ecs));
+    out.join("\n") + "\n"
+}
+
+/// Writes the morgue file into the morgue directory beside the game, returning where it went.
+pub fn write(ecs: &World, won: bool) -> Result<PathBuf, String> {
+    let name = ecs.read_storage::<Character>().get(*ecs.fetch::<Entity>()).map_or("climber".to_string(), |c| c.name.clone());
+    let safe: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    let dir = beside_game(MORGUE_DIR);
+    let path = dir.join(format!("{}-{}.txt", safe, now()));
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    fs::write(&path, morgue(ecs, won)).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chargen::Background;
+    use specs::{Builder, World, WorldExt};
+
+    #[test]
+    fn dates_and_morgue_contents() {
+        assert_eq!(date(0), "1970-01-01 00:00");
+        assert_eq!(date(951_827_696), "2000-02-29 12:34");
+
+        let mut ecs = World::new();
+        ecs.register::<Position>();
+        ecs.register::<Sprite>();
+        ecs.register::<Name>();
+        ecs.register::<Health>();
+        ecs.register::<Melee>();
+        ecs.register::<Perception>();
+        ecs.register::<Languages>();
+        ecs.register::<Experience>();
+        ecs.register::<Character>();
+        ecs.register::<Carried>();
+        ecs.register::<Hidden>();
+        ecs.register::<Submerged>();
+        ecs.insert(Map::new(6, 4));
+        ecs.insert(GameLog::default());
+        ecs.insert(Tower::default());
+        ecs.insert(RunStats::default());
+        let player = ecs.create_entity().with(Position{x: 2, y: 1}).with(Health{current: -3, max: 20})
+            .with(Character{name: "Kjel".to_string(), background: Background::Soldier}).build();
+        ecs.insert(player);
+        ecs.create_entity().with(Name{name: "dented shield".to_string()}).with(Carried{owner: player}).build();
+        ecs.fetch_mut::<RunStats>().hurt(4, "was killed by an orc");
+        ecs.fetch_mut::<RunStats>().kills.insert("eel".to_string(), 2);
+        ecs.write_resource::<GameLog>().log("The orc hits you for 4.");
+
+        assert_eq!(epitaph(&ecs, false), "Kjel the soldier was killed by an orc on floor 1.");
+        let text = morgue(&ecs, false);
+        assert!(text.contains("Health: 0/20"));
+        assert!(text.contains("Carrying:\n  dented shield"));
+        assert!(text.contains("Kills:\n    2 eel"));
+        assert!(text.contains("  The orc hits you for 4."));
+    }
+}
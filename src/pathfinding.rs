@@ -1,26 +1,83 @@
 use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::collections::hash_map::Entry;
 use std::convert::TryInto;
 
 use crate::map::Map;
+use specs::Entity;
+use crate::components::PathFollower;
+use crate::hpastar::{ClusterGraph, hpa_search};
 
 /// Bail out if the A* search exceeds this many steps.
 const MAX_ASTAR_STEPS: usize = 65536;
 
+/// How far (squared) the target may drift from a cached path's destination before we replan.
+const TARGET_TOLERANCE_SQ: f32 = 9.0;
+
+/// Maps with more tiles than this are searched through the cluster graph instead of plain A*.
+const HPA_MIN_TILES: i32 = 128 * 128;
+
 /// Request an A-Star search. The start and end are specified as index numbers (compatible with your
 /// BaseMap implementation), and it requires access to your map so as to call distance and exit determinations.
-pub fn astar_search<T>(start: T, end: T, map: & Map) -> NavigationPath
+/// `opens_doors` lets the path go through closed (but not locked) doors.
+pub fn astar_search<T>(start: T, end: T, map: & Map, opens_doors: bool) -> NavigationPath
 where
     T: TryInto<usize>,
 {
-    AStar::new(start.try_into().ok().unwrap(), end.try_into().ok().unwrap()).search(map)
+    AStar::new(start.try_into().ok().unwrap(), end.try_into().ok().unwrap(), opens_doors, false).search(map)
+}
+
+/// Picks plain A* for small maps and the hierarchical search for large ones. The cluster graph assumes
+/// doors can be opened, so movers that can't open them always get plain A*. With `reach_adjacent` an
+/// end nobody can step onto (i.e. a creature being chased) counts as reached from any tile next to it.
+pub fn plan_path(start: usize, end: usize, map: &Map, graph: &ClusterGraph, opens_doors: bool, reach_adjacent: bool) -> NavigationPath {
+    if opens_doors && map.width * map.height > HPA_MIN_TILES {
+        hpa_search(start, end, map, graph)
+    } else {
+        AStar::new(start, end, opens_doors, reach_adjacent).search(map)
+    }
+}
+
+/// Breadth-first flood from `start` over walkable tiles, returning a path to the closest tile for
+/// which `is_goal` holds. Used for things like auto-explore where there is no single destination.
+pub fn nearest_matching<F>(start: usize, map: &Map, opens_doors: bool, is_goal: F) -> NavigationPath
+where
+    F: Fn(usize) -> bool,
+{
+    let mut parents: HashMap<usize, usize> = HashMap::new();
+    let mut open = VecDeque::new();
+    parents.insert(start, start);
+    open.push_back(start);
+
+    while let Some(idx) = open.pop_front() {
+        if idx != start && is_goal(idx) {
+            let mut result = NavigationPath::new();
+            result.success = true;
+            result.destination = idx;
+            let mut current = idx;
+            while current != start {
+                result.steps.insert(0, current);
+                current = parents[&current];
+            }
+            result.steps.insert(0, start);
+            return result;
+        }
+        for (exit, _) in map.get_available_exits(idx, opens_doors) {
+            if let Entry::Vacant(e) = parents.entry(exit) {
+                e.insert(idx);
+                open.push_back(exit);
+            }
+        }
+    }
+
+    NavigationPath::new()
 }
 
 /// Holds the result of an A-Star navigation query.
 /// `destination` is the index of the target tile.
 /// `success` is true if it reached the target, false otherwise.
 /// `steps` is a vector of each step towards the target, *including* the starting position.
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Debug)]
 pub struct NavigationPath {
     pub destination: usize,
     pub success: bool,
@@ -69,6 +126,56 @@ impl NavigationPath {
     }
 }
 
+impl PathFollower {
+    /// Returns the next tile to step onto on the way from `start` to `end`, advancing along the
+    /// cached path. The path is only recomputed when it has gone stale: the next step is blocked,
+    /// the target has moved beyond the tolerance, the map revision changed, or we've left the path.
+    /// A next step onto a closed door means the mover should open it rather than move.
+    pub fn next_step(&mut self, start: usize, end: usize, map: &Map, graph: &ClusterGraph, opens_doors: bool) -> Option<usize> {
+        if self.is_stale(start, end, map, opens_doors) {
+            // only a quarry (see `chase`) can be closed in on without a free tile under it
+            self.path = plan_path(start, end, map, graph, opens_doors, self.quarry.is_some());
+            self.step = 0;
+            self.revision = map.revision;
+        }
+
+        if !self.path.success || self.step + 1 >= self.path.steps.len() {
+            return None;
+        }
+        let next = self.path.steps[self.step + 1];
+        // the destination itself may be occupied (i.e. by the target we're chasing)
+        if !map.can_enter(next, opens_doors) && next != self.path.destination {
+            return None;
+        }
+        self.step += 1;
+        Some(next)
+    }
+
+    /// Drops the cached path if it was planned towards anyone other than `quarry`.
+    pub fn chase(&mut self, quarry: Entity) {
+        if self.quarry != Some(quarry) {
+            self.path = NavigationPath::default();
+            self.step = 0;
+            self.quarry = Some(quarry);
+        }
+    }
+
+    fn is_stale(&self, start: usize, end: usize, map: &Map, opens_doors: bool) -> bool {
+        if !self.path.success || self.revision != map.revision {
+            return true;
+        }
+        // knocked off the path, or already at the end of it
+        if self.path.steps.get(self.step) != Some(&start) || self.step + 1 >= self.path.steps.len() {
+            return true;
+        }
+        if map.get_pathing_distance(self.path.destination, end) > TARGET_TOLERANCE_SQ {
+            return true;
+        }
+        let next = self.path.steps[self.step + 1];
+        !map.can_enter(next, opens_doors) && next != self.path.destination
+    }
+}
+
 /// Private structure for calculating an A-Star navigation path.
 struct AStar {
     start: usize,
@@ -77,11 +184,13 @@ struct AStar {
     closed_list: HashMap<usize, f32>,
     parents: HashMap<usize, (usize, f32)>, // (index, cost)
     step_counter: usize,
+    opens_doors: bool,
+    reach_adjacent: bool,
 }
 
 impl AStar {
     /// Creates a new path, with specified starting and ending indices.
-    fn new(start: usize, end: usize) -> AStar {
+    fn new(start: usize, end: usize, opens_doors: bool, reach_adjacent: bool) -> AStar {
         let mut open_list: BinaryHeap<Node> = BinaryHeap::new();
         open_list.push(Node {
             idx: start,
@@ -96,6 +205,8 @@ impl AStar {
             parents: HashMap::new(),
             closed_list: HashMap::new(),
             step_counter: 0,
+            opens_doors,
+            reach_adjacent,
         }
     }
 
@@ -162,10 +273,15 @@ impl AStar {
                 let success = self.found_it();
                 return success;
             }
+            // a blocked end (i.e. a creature being chased) is never an exit, so reaching its side is enough
+            if self.reach_adjacent && !map.can_enter(self.end, self.opens_doors) && map.get_pathing_distance(q.idx, self.end) <= 2.0 {
+                self.parents.insert(self.end, (q.idx, q.f));
+                return self.found_it();
+            }
 
             // Generate successors
             map
-                .get_available_exits(q.idx)
+                .get_available_exits(q.idx, self.opens_doors)
                 .iter()
                 .for_each(|s| self.add_successor(q, s.0, s.1 + q.f, map));
 
@@ -178,3 +294,61 @@ impl AStar {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::TileType;
+    use specs::{Builder, World, WorldExt};
+
+    #[test]
+    fn follower_keeps_its_path_until_it_goes_stale() {
+        let mut map = Map::new(16, 7);
+        map.set_tile_blocked();
+        let graph = ClusterGraph::new(&map);
+        let mut follower = PathFollower::default();
+        let end = map.get_index(12, 3);
+
+        let first = follower.next_step(map.get_index(2, 3), end, &map, &graph, false).unwrap();
+        let planned = follower.path.steps.clone();
+        let second = follower.next_step(first, end, &map, &graph, false).unwrap();
+        assert_eq!(second, planned[2]);
+        assert_eq!(follower.path.steps, planned);
+
+        // a target that shuffles a tile over keeps the path, one that moves further away gets a new one
+        let third = follower.next_step(second, map.get_index(12, 4), &map, &graph, false).unwrap();
+        assert_eq!(follower.path.steps, planned);
+        let moved = map.get_index(8, 1);
+        let fourth = follower.next_step(third, moved, &map, &graph, false).unwrap();
+        assert_eq!(follower.path.destination, moved);
+
+        // something standing on the next step means a way round it
+        let ahead = follower.path.steps[follower.step + 1];
+        map.blocked[ahead] = true;
+        let detour = follower.next_step(fourth, moved, &map, &graph, false).unwrap();
+        assert_ne!(detour, ahead);
+        map.blocked[ahead] = false;
+
+        // and any change to the terrain is planned against afresh
+        map.set_tile_type(13, 5, TileType::WALL);
+        follower.next_step(detour, moved, &map, &graph, false);
+        assert_eq!(follower.revision, map.revision);
+    }
+
+    #[test]
+    fn only_a_chase_ends_next_to_a_blocked_target() {
+        let mut map = Map::new(16, 7);
+        map.set_tile_blocked();
+        let graph = ClusterGraph::new(&map);
+        let (start, end) = (map.get_index(2, 3), map.get_index(12, 3));
+        map.blocked[end] = true;
+        assert!(!astar_search(start, end, &map, false).success);
+
+        let mut follower = PathFollower::default();
+        assert_eq!(follower.next_step(start, end, &map, &graph, false), None);
+        follower.chase(World::new().create_entity().build());
+        assert!(follower.next_step(start, end, &map, &graph, false).is_some());
+        let last = follower.path.steps[follower.path.steps.len() - 2];
+        assert!(map.get_pathing_distance(last, end) <= 2.0);
+    }
+}
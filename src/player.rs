@@ -1,10 +1,27 @@
-use crate::gui::Tcod as Tcod;
+use crate::gui::{Input, InputEvent};
 use crate::RunState as RunState;
 use crate::actions::*;
 use crate::gui::Menu;
-use tcod::input::Key;
-use tcod::input::KeyCode::*;
+use crate::chargen::Creation;
+use crate::cutscene;
+use crate::scores;
+use crate::keymap::{Command, Keymap};
+use crate::map::Map;
+use crate::components::{Actor, Position, Item, Health, Name, Submerged};
+use crate::factions::{self, Stance};
+use crate::pathfinding::{astar_search, nearest_matching};
+use crate::gamelog::GameLog;
+use crate::traps::known_trap_at;
 use specs::{Entity, World, WorldExt};
+use std::collections::{HashSet, VecDeque};
+
+// path the player is walking along after clicking a tile, one step per turn
+#[derive(Default)]
+pub struct Travel {
+    pub steps: VecDeque<usize>,
+    // hostiles already in view when travel started, only new arrivals interrupt it
+    known_hostiles: HashSet<Entity>
+}
 
 pub fn increment_cursor(cursor: &mut i16, menu_size: i16){
     if *cursor == menu_size { *cursor = 0; } else { *cursor += 1; }
@@ -14,48 +31,378 @@ pub fn decrement_cursor(cursor: &mut i16, menu_size: i16) {
     if *cursor == 0 { *cursor = menu_size;} else { *cursor -= 1;}
 }
 
-pub fn read_keys(tcod: &mut Tcod, id: Entity) ->  Action{
-    let key = tcod.root.wait_for_keypress(true);
-    match key {
-        Key { code: Escape, .. } => std::process::exit(0x0), // exit game
+// block until a key arrives and translate it through the keymap, unbound keys give None
+pub fn read_command<I: Input + ?Sized>(input: &mut I, keymap: &Keymap) -> Option<Command> {
+    let key = input.wait_for_keypress();
+    keymap.command_for(&key)
+}
+
+pub fn command_to_action(command: Option<Command>, id: Entity) ->  Action{
+    match command {
+        Some(Command::Cancel) => std::process::exit(0x0), // exit game
+        Some(Command::Rest) => Action::RestAction{id},
+        Some(Command::Search) => Action::SearchAction{id},
+        Some(Command::Read) => Action::ReadAction{id},
+        Some(Command::Climb) => Action::ClimbAction{id},
         // movement keys
-        Key { code: Up, .. } => Action::MoveAction{id: id, x: 0, y: -1},
-        Key { code: Down, .. } => Action::MoveAction{id: id, x: 0, y: 1},
-        Key { code: Left, .. } => Action::MoveAction{id: id, x: -1, y: 0},
-        Key { code: Right, .. } => Action::MoveAction{id: id, x: 1, y: 0},
-        //numpad keys (handled counterclockwise)
-        Key { code: NumPad1, .. } => Action::MoveAction{id: id, x: -1, y: 1},
-        Key { code: NumPad2, .. } => Action::MoveAction{id: id, x: 0, y: 1},
-        Key { code: NumPad3, .. } => Action::MoveAction{id: id, x: 1, y: 1},
-        Key { code: NumPad6, .. } => Action::MoveAction{id: id, x: 1, y: 0},
-        Key { code: NumPad9, .. } => Action::MoveAction{id: id, x: 1, y: -1},
-        Key { code: NumPad8, .. } => Action::MoveAction{id: id, x: 0, y: -1},
-        Key { code: NumPad7, .. } => Action::MoveAction{id: id, x: -1, y: -1},
-        Key { code: NumPad4, .. } => Action::MoveAction{id: id, x: -1, y: 0},
-        _ => Action::NoAction
-    }
-}
-
-pub fn handle_main_menu_events(tcod: &mut Tcod, ecs: &mut World) -> RunState {
+        Some(command) => match command.direction() {
+            Some((x, y)) => Action::MoveAction{id, x, y},
+            None => Action::NoAction
+        },
+        None => Action::NoAction
+    }
+}
+
+// every actor in view that would attack the player
+pub fn hostiles_in_view(ecs: &World) -> HashSet<Entity> {
+    use specs::Join;
+    let player = *ecs.fetch::<Entity>();
+    let map = ecs.fetch::<Map>();
+    let entities = ecs.entities();
+    let actors = ecs.read_storage::<Actor>();
+    let positions = ecs.read_storage::<Position>();
+    let submerged = ecs.read_storage::<Submerged>();
+    (&entities, &actors, &positions, !&submerged).join()
+        .filter(|(ent, _, pos, _)| *ent != player && map.tile_in_view(pos.x, pos.y) && factions::stance(ecs, *ent, player) == Stance::Hostile)
+        .map(|(ent, _, _, _)| ent)
+        .collect()
+}
+
+pub fn is_travelling(ecs: &World) -> bool {
+    !ecs.fetch::<Travel>().steps.is_empty()
+}
+
+// plan a route to a known, walkable tile, the player then follows it through the normal turn order
+pub fn start_travel(ecs: &World, id: Entity, x: i32, y: i32) {
+    let map = ecs.fetch::<Map>();
+    if !map.tile_visited(x, y) || !map.is_passable(map.get_index(x, y)) {
+        return;
+    }
+    let pos_store = ecs.read_storage::<Position>();
+    if let Some(pos) = pos_store.get(id) {
+        let path = astar_search(map.get_index(pos.x, pos.y), map.get_index(x, y), &map, true);
+        if path.success {
+            let mut travel = ecs.write_resource::<Travel>();
+            travel.steps = path.steps.into_iter().skip(1).collect();
+            travel.known_hostiles = hostiles_in_view(ecs);
+        }
+    }
+}
+
+// next move along the travel route, or NoAction (ending travel) if it was interrupted or blocked
+pub fn travel_step(ecs: &World, id: Entity) -> Action {
+    let hostiles = hostiles_in_view(ecs);
+    let map = ecs.fetch::<Map>();
+    let pos_store = ecs.read_storage::<Position>();
+    let mut travel = ecs.write_resource::<Travel>();
+    if !hostiles.is_subset(&travel.known_hostiles) {
+        travel.steps.clear();
+        return Action::NoAction;
+    }
+    if let (Some(next), Some(pos)) = (travel.steps.front().copied(), pos_store.get(id)) {
+        let (x, y) = (next as i32 % map.width - pos.x, next as i32 / map.width - pos.y);
+        if x.abs() <= 1 && y.abs() <= 1 && map.can_enter(next, true) && !known_trap_at(ecs, pos.x + x, pos.y + y) {
+            // a closed door stays on the route until the bump has opened it
+            if !map.is_openable_door(next) {
+                travel.steps.pop_front();
+            }
+            return Action::MoveAction{id, x, y};
+        }
+    }
+    travel.steps.clear();
+    Action::NoAction
+}
+
+// a key pressed mid-route, while exploring or repeating a command takes control back, returns whether it stopped anything
+pub fn keypress_interrupts<I: Input + ?Sized>(ecs: &World, input: &mut I) -> bool {
+    if !is_travelling(ecs) && !is_exploring(ecs) && !is_repeating(ecs) {
+        return false;
+    }
+    match input.wait_for_event_timeout(0) {
+        Some(InputEvent::Key(_)) => {
+            interrupt_activity(ecs);
+            ecs.write_resource::<GameLog>().log("You stop.");
+            true
+        },
+        _ => false
+    }
+}
+
+// remembers what the player already knew about when a multi-turn action started,
+// so only changes since then interrupt it
+#[derive(Default)]
+pub struct Watch {
+    known_items: HashSet<Entity>,
+    last_hp: i32
+}
+
+impl Watch {
+    pub fn new(ecs: &World, id: Entity) -> Watch {
+        Watch {
+            known_items: items_in_view(ecs).into_iter().map(|(ent, _)| ent).collect(),
+            last_hp: player_hp(ecs, id)
+        }
+    }
+
+    // why the player should stop, if anything worth their attention happened since the last check
+    pub fn interruption(&mut self, ecs: &World, id: Entity) -> Option<String> {
+        let hostiles = hostiles_in_view(ecs);
+        if let Some(hostile) = hostiles.iter().next() {
+            let name = ecs.read_storage::<Name>().get(*hostile).map_or("something".to_string(), |n| n.name.clone());
+            return Some(format!("The {} comes into view.", name));
+        }
+        let new_item = items_in_view(ecs).into_iter().find(|(ent, _)| !self.known_items.contains(ent));
+        if let Some((_, name)) = new_item {
+            return Some(format!("You spot the {}.", name));
+        }
+        let hp = player_hp(ecs, id);
+        if hp < self.last_hp {
+            return Some("You are hurt!".to_string());
+        }
+        self.last_hp = hp;
+        None
+    }
+}
+
+// state for auto-explore, active until something worth the player's attention happens
+#[derive(Default)]
+pub struct AutoExplore {
+    pub active: bool,
+    watch: Watch
+}
+
+fn items_in_view(ecs: &World) -> Vec<(Entity, String)> {
+    use specs::Join;
+    let map = ecs.fetch::<Map>();
+    let entities = ecs.entities();
+    let items = ecs.read_storage::<Item>();
+    let positions = ecs.read_storage::<Position>();
+    let names = ecs.read_storage::<Name>();
+    let submerged = ecs.read_storage::<Submerged>();
+    (&entities, &items, &positions, !&submerged).join()
+        .filter(|(_, _, pos, _)| map.tile_in_view(pos.x, pos.y))
+        .map(|(ent, _, _, _)| (ent, names.get(ent).map_or("something".to_string(), |n| n.name.clone())))
+        .collect()
+}
+
+fn player_hp(ecs: &World, id: Entity) -> i32 {
+    ecs.read_storage::<Health>().get(id).map_or(0, |h| h.current)
+}
+
+pub fn is_exploring(ecs: &World) -> bool {
+    ecs.fetch::<AutoExplore>().active
+}
+
+pub fn start_explore(ecs: &World, id: Entity) {
+    if !hostiles_in_view(ecs).is_empty() {
+        ecs.write_resource::<GameLog>().log("Not with enemies in view!");
+        return;
+    }
+    let watch = Watch::new(ecs, id);
+    let mut explore = ecs.write_resource::<AutoExplore>();
+    explore.active = true;
+    explore.watch = watch;
+}
+
+fn stop_explore(ecs: &World, reason: String) {
+    ecs.write_resource::<AutoExplore>().active = false;
+    ecs.write_resource::<GameLog>().log(reason);
+}
+
+// one step towards the nearest unexplored tile, or NoAction (ending exploration) with a message saying why
+pub fn explore_step(ecs: &World, id: Entity) -> Action {
+    let interruption = ecs.write_resource::<AutoExplore>().watch.interruption(ecs, id);
+    if let Some(reason) = interruption {
+        stop_explore(ecs, reason);
+        return Action::NoAction;
+    }
+
+    let path = {
+        let map = ecs.fetch::<Map>();
+        let pos_store = ecs.read_storage::<Position>();
+        match pos_store.get(id) {
+            Some(pos) => nearest_matching(map.get_index(pos.x, pos.y), &map, true, |idx| {
+                !map.tile_visited(idx as i32 % map.width, idx as i32 / map.width)
+            }).steps.get(1).map(|next| ((*next as i32 % map.width) - pos.x, (*next as i32 / map.width) - pos.y)),
+            None => None
+        }
+    };
+    let pos = ecs.read_storage::<Position>().get(id).map(|pos| (pos.x, pos.y));
+    match (path, pos) {
+        (Some((x, y)), Some((px, py))) if known_trap_at(ecs, px + x, py + y) => {
+            stop_explore(ecs, "There is a trap in the way.".to_string());
+            Action::NoAction
+        },
+        (Some((x, y)), _) => Action::MoveAction{id, x, y},
+        (None, _) => {
+            stop_explore(ecs, "Nothing left to explore here.".to_string());
+            Action::NoAction
+        }
+    }
+}
+
+// stops travel, exploring and repeated commands, i.e. after something happens to the player
+pub fn interrupt_activity(ecs: &World) {
+    ecs.write_resource::<Travel>().steps.clear();
+    ecs.write_resource::<AutoExplore>().active = false;
+    ecs.write_resource::<Repeat>().kind = None;
+}
+
+#[derive(Clone, Copy)]
+pub enum RepeatKind {
+    // keep moving in a direction until the surroundings change
+    Run{dx: i32, dy: i32},
+    // do the same thing a fixed number of turns, i.e. 20s
+    Count{command: Command, remaining: u32}
+}
+
+// a command the player repeats over several turns, one step per turn so everyone else still gets to act
+#[derive(Default)]
+pub struct Repeat {
+    pub kind: Option<RepeatKind>,
+    // count typed so far with the number row, applied to the next command
+    pub pending_count: Option<u32>,
+    // which neighbouring tiles were walkable where a run started
+    surroundings: Vec<bool>,
+    watch: Watch
+}
+
+const MAX_COUNT: u32 = 999;
+
+pub fn is_repeating(ecs: &World) -> bool {
+    ecs.fetch::<Repeat>().kind.is_some()
+}
+
+pub fn push_count_digit(ecs: &World, digit: u32) {
+    let mut repeat = ecs.write_resource::<Repeat>();
+    let count = repeat.pending_count.unwrap_or(0) * 10 + digit;
+    repeat.pending_count = Some(count.min(MAX_COUNT));
+}
+
+// walkability of the eight tiles around a position, a change means a branch, opening or dead end
+fn surroundings(map: &Map, x: i32, y: i32) -> Vec<bool> {
+    let mut walkable = Vec::new();
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            if dx != 0 || dy != 0 {
+                walkable.push(map.in_bounds(x + dx, y + dy) && map.is_walkable(map.get_index(x + dx, y + dy)));
+            }
+        }
+    }
+    walkable
+}
+
+fn player_surroundings(ecs: &World, id: Entity) -> Vec<bool> {
+    let map = ecs.fetch::<Map>();
+    ecs.read_storage::<Position>().get(id).map_or(Vec::new(), |pos| surroundings(&map, pos.x, pos.y))
+}
+
+// applies any pending count to a command and starts runs, everything else goes through command_to_action
+pub fn handle_command(ecs: &World, id: Entity, command: Option<Command>) -> Action {
+    let count = ecs.write_resource::<Repeat>().pending_count.take();
+    let command = match command {
+        // cancel drops a half typed count instead of quitting
+        Some(Command::Cancel) if count.is_some() => return Action::NoAction,
+        Some(command) => command,
+        None => return Action::NoAction
+    };
+    let kind = if let Some((dx, dy)) = command.run_direction() {
+        Some(RepeatKind::Run{dx, dy})
+    } else if command == Command::Rest || command == Command::Search || command.direction().is_some() {
+        count.filter(|count| *count > 1).map(|count| RepeatKind::Count{command, remaining: count})
+    } else {
+        None
+    };
+    match kind {
+        Some(kind) => {
+            if !hostiles_in_view(ecs).is_empty() {
+                ecs.write_resource::<GameLog>().log("Not with enemies in view!");
+                return Action::NoAction;
+            }
+            let surroundings = player_surroundings(ecs, id);
+            let watch = Watch::new(ecs, id);
+            let mut repeat = ecs.write_resource::<Repeat>();
+            repeat.kind = Some(kind);
+            repeat.surroundings = surroundings;
+            repeat.watch = watch;
+            drop(repeat);
+            repeat_step(ecs, id)
+        },
+        None => command_to_action(Some(command), id)
+    }
+}
+
+fn stop_repeat(ecs: &World, reason: Option<String>) {
+    ecs.write_resource::<Repeat>().kind = None;
+    if let Some(reason) = reason {
+        ecs.write_resource::<GameLog>().log(reason);
+    }
+}
+
+// the next step of a run or counted command, or NoAction (ending it) once it's done or interrupted
+pub fn repeat_step(ecs: &World, id: Entity) -> Action {
+    let interruption = ecs.write_resource::<Repeat>().watch.interruption(ecs, id);
+    if interruption.is_some() {
+        stop_repeat(ecs, interruption);
+        return Action::NoAction;
+    }
+    let kind = ecs.fetch::<Repeat>().kind;
+    let (dx, dy) = match kind {
+        Some(RepeatKind::Run{dx, dy}) => {
+            let surroundings = player_surroundings(ecs, id);
+            if surroundings != ecs.fetch::<Repeat>().surroundings {
+                stop_repeat(ecs, None);
+                return Action::NoAction;
+            }
+            (dx, dy)
+        },
+        Some(RepeatKind::Count{command, remaining}) => {
+            let remaining = remaining - 1;
+            ecs.write_resource::<Repeat>().kind = match remaining {
+                0 => None,
+                _ => Some(RepeatKind::Count{command, remaining})
+            };
+            match command.direction() {
+                Some(direction) => direction,
+                None => return command_to_action(Some(command), id)
+            }
+        },
+        None => return Action::NoAction
+    };
+    let map = ecs.fetch::<Map>();
+    let pos_store = ecs.read_storage::<Position>();
+    match pos_store.get(id) {
+        Some(pos) if map.is_exit_valid(pos.x + dx, pos.y + dy) && !known_trap_at(ecs, pos.x + dx, pos.y + dy) => {
+            Action::MoveAction{id, x: dx, y: dy}
+        },
+        _ => {
+            stop_repeat(ecs, None);
+            Action::NoAction
+        }
+    }
+}
+
+pub fn handle_main_menu_events<I: Input + ?Sized>(input: &mut I, ecs: &mut World) -> RunState {
     let mut menu = ecs.write_resource::<Menu>();
+    let keymap = ecs.fetch::<Keymap>();
     let menu_size = menu.menu_size;
 
-    let key = tcod.root.wait_for_keypress(true);
-    match key {
-        Key {code: Up, ..} => {
+    match read_command(input, &keymap) {
+        Some(Command::MoveUp) => {
                                decrement_cursor(&mut (*menu).cursor_index, menu_size);
                                RunState::MainMenu
                             },
-        Key {code: Down, ..} => {
+        Some(Command::MoveDown) => {
                                  increment_cursor(&mut (*menu).cursor_index, menu_size);
                                  RunState::MainMenu
                                 },
-        Key {code: Enter, ..} => {// return new RunState of chosen menu item
+        Some(Command::Confirm) => {// return new RunState of chosen menu item
                                   match (*menu).cursor_index {
-                                      0 => RunState::NewGame,
+                                      0 => cutscene::start("opening", RunState::NewGame{creation: Creation::default()}),
                                       1 => RunState::LoadGame,
-                                      2 => RunState::Options,
-                                      3 => std::process::exit(0x0),
+                                      2 => scores::view(),
+                                      3 => RunState::Options,
+                                      4 => std::process::exit(0x0),
                                       _ => RunState::MainMenu
                                   }
 
@@ -66,4 +413,148 @@ pub fn handle_main_menu_events(tcod: &mut Tcod, ecs: &mut World) -> RunState {
 
 
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Player, Companion, Faction, Hidden, Trap, Vision, Peaceful};
+    use crate::factions::Factions;
+    use crate::map::TileType;
+    use crate::visionsystem::VisionSystem;
+    use specs::{Builder, RunNow};
+    use tcod::input::Key;
+
+    // replays queued events, then reports nothing pressed
+    struct Script(VecDeque<InputEvent>);
+
+    impl Input for Script {
+        fn wait_for_event(&mut self) -> InputEvent {
+            self.0.pop_front().expect("ran out of scripted input")
+        }
+        fn wait_for_event_timeout(&mut self, _millis: u64) -> Option<InputEvent> {
+            self.0.pop_front()
+        }
+        fn window_closed(&self) -> bool {
+            false
+        }
+    }
+
+    fn world() -> (World, Entity) {
+        let mut ecs = World::new();
+        ecs.register::<Player>();
+        ecs.register::<Companion>();
+        ecs.register::<Peaceful>();
+        ecs.register::<Position>();
+        ecs.register::<Vision>();
+        ecs.register::<Actor>();
+        ecs.register::<Faction>();
+        ecs.register::<Submerged>();
+        ecs.register::<Trap>();
+        ecs.register::<Hidden>();
+        ecs.register::<Item>();
+        ecs.register::<Name>();
+        ecs.register::<Health>();
+        let mut map = Map::new(10, 5);
+        map.set_tile_blocked();
+        ecs.insert(map);
+        ecs.insert(Factions::parse("[orcs]\nplayer = -50").unwrap());
+        ecs.insert(GameLog::default());
+        ecs.insert(Travel::default());
+        ecs.insert(AutoExplore::default());
+        ecs.insert(Repeat::default());
+        let player = ecs.create_entity().with(Position{x: 1, y: 2})
+                                        .with(Vision{field_of_vision: HashSet::new()})
+                                        .with(Player{}).build();
+        ecs.insert(player);
+        VisionSystem{}.run_now(&ecs);
+        (ecs, player)
+    }
+
+    #[test]
+    fn travel_walks_the_route_until_a_new_hostile_shows_up() {
+        let (mut ecs, player) = world();
+        start_travel(&ecs, player, 8, 2);
+        assert!(is_travelling(&ecs));
+        assert!(matches!(travel_step(&ecs, player), Action::MoveAction{x: 1, y: 0, ..}));
+
+        ecs.create_entity().with(Position{x: 6, y: 2})
+                           .with(Actor{action_points: 0, threshold: 100})
+                           .with(Faction{name: "orcs".to_string()}).build();
+        assert!(matches!(travel_step(&ecs, player), Action::NoAction));
+        assert!(!is_travelling(&ecs));
+
+        // walls and unexplored tiles can't be travelled to
+        start_travel(&ecs, player, 0, 2);
+        assert!(!is_travelling(&ecs));
+    }
+
+    #[test]
+    fn a_keypress_stops_travel() {
+        let (ecs, player) = world();
+        // nothing to stop, the key is left for the turn to read
+        let mut input = Script(VecDeque::from(vec![InputEvent::Key(Key::default())]));
+        assert!(!keypress_interrupts(&ecs, &mut input));
+        assert_eq!(input.0.len(), 1);
+
+        // moving the mouse along the way doesn't count
+        start_travel(&ecs, player, 8, 2);
+        input.0.push_front(InputEvent::MouseMove{x: 4, y: 3});
+        assert!(!keypress_interrupts(&ecs, &mut input));
+        assert!(is_travelling(&ecs));
+        assert!(keypress_interrupts(&ecs, &mut input));
+        assert!(!is_travelling(&ecs));
+        assert_eq!(ecs.fetch::<GameLog>().recent(1)[0].text, "You stop.");
+    }
+
+    #[test]
+    fn explore_heads_for_unseen_tiles_until_a_key_is_pressed() {
+        let (ecs, player) = world();
+        // the east end of the room hasn't been seen yet
+        ecs.write_resource::<Map>().visited.retain(|tile| tile.x < 6);
+        start_explore(&ecs, player);
+        assert!(is_exploring(&ecs));
+        assert!(matches!(explore_step(&ecs, player), Action::MoveAction{x: 1, ..}));
+
+        let mut input = Script(VecDeque::from(vec![InputEvent::Key(Key::default())]));
+        assert!(keypress_interrupts(&ecs, &mut input));
+        assert!(!is_exploring(&ecs));
+    }
+
+    #[test]
+    fn a_run_stops_where_a_side_passage_opens() {
+        let (ecs, player) = world();
+        // a corridor along the middle row with a gap in its north wall at x = 6
+        {
+            let mut map = ecs.write_resource::<Map>();
+            for x in 1..9 {
+                map.set_tile_type(x, 1, TileType::WALL);
+                map.set_tile_type(x, 3, TileType::WALL);
+            }
+            map.set_tile_type(6, 1, TileType::GROUND);
+            map.set_tile_blocked();
+        }
+        ecs.write_storage::<Position>().get_mut(player).unwrap().x = 2;
+
+        let mut act = handle_command(&ecs, player, Some(Command::RunRight));
+        let mut steps = 0;
+        while let Action::MoveAction{x, y, ..} = act {
+            let mut pos_store = ecs.write_storage::<Position>();
+            let pos = pos_store.get_mut(player).unwrap();
+            pos.x += x;
+            pos.y += y;
+            drop(pos_store);
+            steps += 1;
+            act = repeat_step(&ecs, player);
+        }
+        // the opening is diagonally ahead, so the run ends next to it rather than carrying on down the corridor
+        assert_eq!(steps, 3);
+        assert!(!is_repeating(&ecs));
+
+        // a count is stopped by a key like anything else
+        ecs.write_resource::<Repeat>().kind = Some(RepeatKind::Count{command: Command::Rest, remaining: 5});
+        let mut input = Script(VecDeque::from(vec![InputEvent::Key(Key::default())]));
+        assert!(keypress_interrupts(&ecs, &mut input));
+        assert!(!is_repeating(&ecs));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::components::{Experience, Health, Melee, Perception, Actor, Swimmer, Companion, Player};
+use crate::gamelog::GameLog;
+use crate::gui::Menu;
+use specs::{Entity, World, WorldExt};
+
+/*
+Experience and levels: what the player earns by fighting and climbing, and what they get for it
+*/
+
+// experience for reaching a new floor, times how high up it is
+const FLOOR_XP: i32 = 10;
+// max health gained with every level
+const LEVEL_HEALTH: i32 = 4;
+// the quickest a turn threshold can get with the Fleet perk
+const FASTEST_THRESHOLD: i32 = 3;
+// the roof of the tower, coming out onto it wins the game
+pub const TOP_FLOOR: i32 = 5;
+
+/// How far up the tower the player is, and the highest floor they've reached so far.
+#[derive(Clone, Copy)]
+pub struct Tower {
+    pub depth: i32,
+    pub deepest: i32
+}
+
+impl Default for Tower {
+    fn default() -> Tower {
+        Tower{depth: 1, deepest: 1}
+    }
+}
+
+impl Tower {
+    /// A monster's health, a quarter again for every floor above the first.
+    pub fn health(&self, base: i32) -> i32 {
+        base + base * (self.depth - 1) / 4
+    }
+
+    /// A monster's melee damage, one more for every two floors above the first.
+    pub fn damage(&self, base: i32) -> i32 {
+        base + (self.depth - 1) / 2
+    }
+}
+
+/// Experience needed to get from `level` to the next one.
+pub fn needed(level: i32) -> i32 {
+    20 * level
+}
+
+/// What the player can pick from each time they go up a level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Perk {
+    Tough,
+    Strong,
+    Alert,
+    Fleet,
+    Swimmer
+}
+
+impl Perk {
+    pub fn label(self) -> &'static str {
+        match self {
+            Perk::Tough => "Tough (+5 max HP)",
+            Perk::Strong => "Strong (+1 damage)",
+            Perk::Alert => "Alert (+1 perception)",
+            Perk::Fleet => "Fleet (faster turns)",
+            Perk::Swimmer => "Swimmer (learn to swim)"
+        }
+    }
+}
+
+/// Gives the player `amount` experience, going up as many levels as it pays for.
+pub fn gain(ecs: &World, amount: i32) {
+    let player = *ecs.fetch::<Entity>();
+    let mut levels = Vec::new();
+    if let Some(exp) = ecs.write_storage::<Experience>().get_mut(player) {
+        exp.xp += amount;
+        while exp.xp >= needed(exp.level) {
+            exp.xp -= needed(exp.level);
+            exp.level += 1;
+            exp.pending += 1;
+            levels.push(exp.level);
+        }
+    }
+    for level in levels {
+        if let Some(health) = ecs.write_storage::<Health>().get_mut(player) {
+            health.max += LEVEL_HEALTH;
+            health.current += LEVEL_HEALTH;
+        }
+        ecs.write_resource::<GameLog>().log(format!("You reach level {}.", level));
+    }
+}
+
+/// `victim` was killed by `killer`, which is worth its health and bite if that was the player or one of their companions.
+pub fn killed(ecs: &World, victim: Entity, killer: Entity) {
+    let on_players_side = ecs.read_storage::<Player>().contains(killer) || ecs.read_storage::<Companion>().contains(killer);
+    if !on_players_side {
+        return;
+    }
+    let health = ecs.read_storage::<Health>().get(victim).map_or(0, |h| h.max);
+    let damage = ecs.read_storage::<Melee>().get(victim).map_or(0, |m| m.damage);
+    gain(ecs, health + damage);
+}
+
+/// The player has come out onto floor `depth`, the first time they reach a new height is worth some experience.
+pub fn arrive(ecs: &World, depth: i32) {
+    let discovered = {
+        let mut tower = ecs.write_resource::<Tower>();
+        tower.depth = depth;
+        let discovered = depth > tower.deepest;
+        tower.deepest = tower.deepest.max(depth);
+        discovered
+    };
+    if discovered {
+        ecs.write_resource::<GameLog>().log("You have never climbed this high before.");
+        gain(ecs, FLOOR_XP * depth);
+    }
+}
+
+/// Whether the player has gone up a level and not yet picked a perk for it.
+pub fn perk_pending(ecs: &World) -> bool {
+    let player = *ecs.fetch::<Entity>();
+    ecs.read_storage::<Experience>().get(player).is_some_and(|exp| exp.pending > 0)
+}
+
+/// The perks the player can pick from right now, and the menu to pick one from.
+pub fn perks(ecs: &World) -> (Vec<Perk>, Menu) {
+    let player = *ecs.fetch::<Entity>();
+    let mut perks = vec![Perk::Tough, Perk::Strong, Perk::Alert];
+    if ecs.read_storage::<Actor>().get(player).is_some_and(|actor| actor.threshold > FASTEST_THRESHOLD) {
+        perks.push(Perk::Fleet);
+    }
+    if !ecs.read_storage::<Swimmer>().contains(player) {
+        perks.push(Perk::Swimmer);
+    }
+    let options: Vec<String> = perks.iter().map(|perk| perk.label().to_string()).collect();
+    (perks, Menu::new(options.len() as i16 - 1, options))
+}
+
+/// Applies the perk the player picked for one of their pending levels.
+pub fn choose(ecs: &World, perk: Perk) {
+    let player = *ecs.fetch::<Entity>();
+    match perk {
+        Perk::Tough => if let Some(health) = ecs.write_storage::<Health>().get_mut(player) {
+            health.max += 5;
+            health.current += 5;
+        },
+        Perk::Strong => if let Some(melee) = ecs.write_storage::<Melee>().get_mut(player) {
+            melee.damage += 1;
+        },
+        Perk::Alert => if let Some(perception) = ecs.write_storage::<Perception>().get_mut(player) {
+            perception.skill += 1;
+        },
+        Perk::Fleet => if let Some(actor) = ecs.write_storage::<Actor>().get_mut(player) {
+            actor.threshold = (actor.threshold - 1).max(FASTEST_THRESHOLD);
+        },
+        Perk::Swimmer => {
+            ecs.write_storage::<Swimmer>().insert(player, Swimmer{}).expect("the player is dead");
+        }
+    }
+    if let Some(exp) = ecs.write_storage::<Experience>().get_mut(player) {
+        exp.pending = (exp.pending - 1).max(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Peaceful;
+    use specs::{Builder, World, WorldExt};
+
+    #[test]
+    fn kills_and_new_floors_level_the_player_up() {
+        let mut ecs = World::new();
+        ecs.register::<Experience>();
+        ecs.register::<Health>();
+        ecs.register::<Melee>();
+        ecs.register::<Perception>();
+        ecs.register::<Actor>();
+        ecs.register::<Swimmer>();
+        ecs.register::<Companion>();
+        ecs.register::<Peaceful>();
+        ecs.register::<Player>();
+        ecs.insert(GameLog::default());
+        ecs.insert(Tower::default());
+        let player = ecs.create_entity().with(Player{}).with(Experience::default()).with(Health{current: 20, max: 20})
+            .with(Melee{damage: 4}).with(Actor{action_points: 0, threshold: 5}).build();
+        ecs.insert(player);
+        let orc = ecs.create_entity().with(Health{current: 0, max: 16}).with(Melee{damage: 4}).build();
+
+        // someone else's kill is worth nothing
+        killed(&ecs, orc, orc);
+        assert!(!perk_pending(&ecs));
+        killed(&ecs, orc, player);
+        assert_eq!(ecs.read_storage::<Experience>().get(player).map(|e| (e.level, e.xp)), Some((2, 0)));
+        assert_eq!(ecs.read_storage::<Health>().get(player).unwrap().max, 24);
+
+        // going back down a floor is worth nothing, going higher than ever is
+        arrive(&ecs, 3);
+        arrive(&ecs, 2);
+        assert_eq!(ecs.fetch::<Tower>().deepest, 3);
+        assert_eq!(ecs.read_storage::<Experience>().get(player).map(|e| (e.level, e.xp, e.pending)), Some((2, 30, 1)));
+        assert_eq!(ecs.fetch::<Tower>().health(8), 10);
+
+        let (perks, _) = perks(&ecs);
+        assert!(perks.contains(&Perk::Swimmer));
+        choose(&ecs, Perk::Strong);
+        assert!(!perk_pending(&ecs));
+        assert_eq!(ecs.read_storage::<Melee>().get(player).unwrap().damage, 5);
+    }
+}
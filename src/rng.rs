@@ -0,0 +1,14 @@
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+/*
+The game's one source of randomness, everything left to chance draws from it so a seed always plays out the same way
+*/
+
+pub struct GameRng(pub StdRng);
+
+impl GameRng {
+    pub fn new(seed: u64) -> GameRng {
+        GameRng(StdRng::seed_from_u64(seed))
+    }
+}
@@ -0,0 +1,204 @@
+use crate::components::{Character, Experience};
+use crate::gui::Menu;
+use crate::morgue;
+use crate::RunState;
+use crate::progression::Tower;
+use crate::stats::RunStats;
+use specs::{Entity, World, WorldExt};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+/*
+High scores: every finished run, kept in scores.cfg next to the game so the best and the latest can be looked back on and replayed
+*/
+
+const SCORES_FILE: &str = "scores.cfg";
+
+// points for each floor reached, level, kill and for winning outright
+const FLOOR_POINTS: u32 = 100;
+const LEVEL_POINTS: u32 = 50;
+const KILL_POINTS: u32 = 10;
+const VICTORY_POINTS: u32 = 1000;
+
+/// One finished run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Run {
+    pub name: String,
+    pub background: String,
+    // deepest floor reached
+    pub depth: i32,
+    pub score: u32,
+    pub cause: String,
+    pub seed: u64,
+    // seconds since the epoch
+    pub date: u64,
+    pub version: String
+}
+
+// which way the table is sorted, best first or newest first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Score,
+    Date
+}
+
+impl Order {
+    pub fn label(self) -> &'static str {
+        match self {
+            Order::Score => "score",
+            Order::Date => "date"
+        }
+    }
+
+    pub fn toggled(self) -> Order {
+        match self {
+            Order::Score => Order::Date,
+            Order::Date => Order::Score
+        }
+    }
+}
+
+impl Run {
+    /// The run that has just ended in `ecs`.
+    pub fn finished(ecs: &World, won: bool) -> Run {
+        let player = *ecs.fetch::<Entity>();
+        let (name, background) = ecs.read_storage::<Character>().get(player)
+            .map_or(("climber".to_string(), "climber".to_string()), |c| (c.name.clone(), c.background.name().to_string()));
+        let level = ecs.read_storage::<Experience>().get(player).map_or(1, |exp| exp.level);
+        let depth = ecs.try_fetch::<Tower>().map_or(1, |tower| tower.deepest);
+        let stats = ecs.fetch::<RunStats>();
+        let score = depth.max(1) as u32 * FLOOR_POINTS + level.max(1) as u32 * LEVEL_POINTS + stats.total_kills() * KILL_POINTS
+            + if won { VICTORY_POINTS } else { 0 };
+        let cause = if won { "reached the top of the tower".to_string() } else { stats.cause.clone().unwrap_or("died".to_string()) };
+        Run{name, background, depth, score, cause, seed: stats.seed, date: morgue::now(), version: env!("CARGO_PKG_VERSION").to_string()}
+    }
+
+    // a line of the high score table, under gui::SCORES_HEADER
+    pub fn row(&self, rank: usize) -> String {
+        format!("{:>3} {:<20} {:<9} {:>5} {:>6}  {}", rank, self.name, self.background, self.depth, self.score, morgue::date(self.date))
+    }
+
+    fn to_text(&self) -> String {
+        format!("[run]\nname = {}\nbackground = {}\ndepth = {}\nscore = {}\ncause = {}\nseed = {}\ndate = {}\nversion = {}\n\n",
+                self.name, self.background, self.depth, self.score, self.cause, self.seed, self.date, self.version)
+    }
+}
+
+/// Every run recorded so far, in the order they were played.
+#[derive(Debug, Default)]
+pub struct History {
+    pub runs: Vec<Run>
+}
+
+impl History {
+    /// Parses `[run]` sections, one per finished run.
+    pub fn parse(text: &str) -> Result<History, String> {
+        let mut runs = Vec::new();
+        let mut current: Option<Run> = None;
+        for (line_no, raw) in text.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "[run]" {
+                runs.extend(current.take());
+                current = Some(Run{name: String::new(), background: String::new(), depth: 1, score: 0, cause: String::new(),
+                                   seed: 0, date: 0, version: String::new()});
+                continue;
+            }
+            let (field, value) = line.split_once('=').ok_or(format!("line {}: expected 'field = value'", line_no + 1))?;
+            let run = current.as_mut().ok_or(format!("line {}: field outside of a [run] section", line_no + 1))?;
+            let value = value.trim();
+            let number = |what: &str| format!("line {}: '{}' should be a number", line_no + 1, what);
+            match field.trim() {
+                "name" => run.name = value.to_string(),
+                "background" => run.background = value.to_string(),
+                "depth" => run.depth = value.parse().map_err(|_| number("depth"))?,
+                "score" => run.score = value.parse().map_err(|_| number("score"))?,
+                "cause" => run.cause = value.to_string(),
+                "seed" => run.seed = value.parse().map_err(|_| number("seed"))?,
+                "date" => run.date = value.parse().map_err(|_| number("date"))?,
+                "version" => run.version = value.to_string(),
+                other => return Err(format!("line {}: unknown field '{}'", line_no + 1, other))
+            }
+        }
+        runs.extend(current);
+        Ok(History{runs})
+    }
+
+    /// The runs in scores.cfg, or none if there isn't one yet.
+    pub fn load() -> Result<History, String> {
+        match fs::read_to_string(morgue::beside_game(SCORES_FILE)) {
+            Ok(text) => History::parse(&text),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(History::default()),
+            Err(err) => Err(err.to_string())
+        }
+    }
+
+    /// Ties on score go to whoever got there first.
+    pub fn sort(&mut self, order: Order) {
+        match order {
+            Order::Score => self.runs.sort_by(|a, b| b.score.cmp(&a.score).then(a.date.cmp(&b.date))),
+            Order::Date => self.runs.sort_by_key(|run| std::cmp::Reverse(run.date))
+        }
+    }
+
+    // where a score would place on the table, from 1
+    pub fn rank(&self, score: u32) -> usize {
+        self.runs.iter().filter(|run| run.score > score).count() + 1
+    }
+}
+
+/// The high score table, sorted by `order`, with the cursor on the top run.
+pub fn table(mut history: History, order: Order) -> RunState {
+    history.sort(order);
+    let rows: Vec<String> = history.runs.iter().enumerate().map(|(index, run)| run.row(index + 1)).collect();
+    let menu = Menu::new(rows.len() as i16 - 1, rows);
+    RunState::Scores{history, order, menu}
+}
+
+/// Opens the high score table from the main menu, best runs first.
+pub fn view() -> RunState {
+    let history = History::load().unwrap_or_else(|err| {
+        eprintln!("could not load {}: {}", morgue::beside_game(SCORES_FILE).display(), err);
+        History::default()
+    });
+    table(history, Order::Score)
+}
+
+/// Adds `run` to scores.cfg, returning where it placed among every run so far. A scores.cfg that
+/// can't be read is left alone and the error returned, rather than piling more runs on top of it.
+pub fn record(run: &Run) -> Result<usize, String> {
+    let history = History::load()?;
+    let mut file = OpenOptions::new().create(true).append(true).open(morgue::beside_game(SCORES_FILE)).map_err(|e| e.to_string())?;
+    file.write_all(run.to_text().as_bytes()).map_err(|e| e.to_string())?;
+    Ok(history.rank(run.score))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(name: &str, score: u32, date: u64) -> Run {
+        Run{name: name.to_string(), background: "sailor".to_string(), depth: 2, score, cause: "drowned".to_string(),
+            seed: 42, date, version: "0.1.0".to_string()}
+    }
+
+    #[test]
+    fn runs_round_trip_and_sort() {
+        let text: String = [run("Ana", 300, 10), run("Bel", 500, 20), run("Cai", 300, 30)].iter().map(|r| r.to_text()).collect();
+        let mut history = History::parse(&text).unwrap();
+        assert_eq!(history.runs[1], run("Bel", 500, 20));
+
+        history.sort(Order::Score);
+        let names: Vec<&str> = history.runs.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["Bel", "Ana", "Cai"]);
+        history.sort(Order::Date);
+        assert_eq!(history.runs[0].name, "Cai");
+        assert_eq!(history.rank(400), 2);
+
+        assert!(History::parse("name = Ana").unwrap_err().contains("line 1"));
+        assert!(History::parse("[run]\nscore = lots").unwrap_err().contains("'score'"));
+    }
+}
@@ -0,0 +1,51 @@
+use crate::components::{Health, Player};
+use specs::{Entity, World, WorldExt};
+use std::collections::BTreeMap;
+
+/*
+Run statistics: what the player killed, dealt and took over the run, and what finally did them in
+*/
+
+#[derive(Default, Debug)]
+pub struct RunStats {
+    // by the victim's name, sorted for the morgue file
+    pub kills: BTreeMap<String, u32>,
+    pub damage_dealt: i32,
+    pub damage_taken: i32,
+    pub turns: u32,
+    // the seed the run was played with
+    pub seed: u64,
+    // whatever last hurt the player, i.e. "was killed by an orc", which is what killed them if they died
+    pub cause: Option<String>
+}
+
+impl RunStats {
+    pub fn hurt(&mut self, amount: i32, cause: &str) {
+        self.damage_taken += amount;
+        self.cause = Some(cause.to_string());
+    }
+
+    pub fn total_kills(&self) -> u32 {
+        self.kills.values().sum()
+    }
+}
+
+/// "a" or "an" to go in front of `name`.
+pub fn article(name: &str) -> &'static str {
+    if name.starts_with(['a', 'e', 'i', 'o', 'u']) { "an" } else { "a" }
+}
+
+/// Records `amount` of damage to `victim`, if that's the player, along with what did it.
+pub fn player_hurt(ecs: &World, victim: Entity, amount: i32, cause: &str) {
+    if !ecs.read_storage::<Player>().contains(victim) {
+        return;
+    }
+    if let Some(mut stats) = ecs.try_fetch_mut::<RunStats>() {
+        stats.hurt(amount, cause);
+    }
+}
+
+pub fn player_dead(ecs: &World) -> bool {
+    let player = *ecs.fetch::<Entity>();
+    ecs.read_storage::<Health>().get(player).is_some_and(|h| h.current <= 0)
+}
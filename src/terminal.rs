@@ -0,0 +1,532 @@
+use tcod::colors::*;
+use tcod::console::TextAlignment;
+use tcod::input::{Key, KeyCode};
+use std::io::Write;
+use std::sync::Mutex;
+use super::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::gui::{Input, InputEvent, Renderer};
+
+/*
+ANSI terminal frontend, draws with escape codes and reads keys from raw stdin so the game can be
+played over ssh
+*/
+
+// terminal settings from before we switched to raw mode, restored on exit and on panic
+static ORIGINAL_TERMIOS: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+// how long to wait after an escape byte for the rest of an escape sequence
+const ESCAPE_TIMEOUT_MS: i32 = 25;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorMode {
+    TrueColor,
+    Ansi256,
+    Ansi16
+}
+
+impl ColorMode {
+    // pick the richest mode the terminal advertises
+    pub fn detect() -> ColorMode {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorMode::TrueColor
+        } else if term.contains("256color") {
+            ColorMode::Ansi256
+        } else {
+            ColorMode::Ansi16
+        }
+    }
+
+    // sgr parameters selecting `color` as foreground (or background)
+    fn sgr(self, color: Color, background: bool) -> String {
+        match self {
+            ColorMode::TrueColor => format!("{};2;{};{};{}", if background { 48 } else { 38 }, color.r, color.g, color.b),
+            ColorMode::Ansi256 => format!("{};5;{}", if background { 48 } else { 38 }, ansi256(color)),
+            ColorMode::Ansi16 => {
+                let index = ansi16(color);
+                let base = if background { 40 } else { 30 };
+                // bright colors live at 90-97/100-107
+                if index >= 8 { format!("{}", base + 60 + index - 8) } else { format!("{}", base + index) }
+            }
+        }
+    }
+}
+
+// nearest entry in the xterm 6x6x6 color cube or greyscale ramp
+fn ansi256(color: Color) -> u8 {
+    let to_cube = |c: u8| if c < 48 { 0 } else if c < 115 { 1 } else { (c - 35) / 40 };
+    let (r, g, b) = (to_cube(color.r), to_cube(color.g), to_cube(color.b));
+    if r == g && g == b {
+        let average = (color.r as u32 + color.g as u32 + color.b as u32) / 3;
+        if average < 8 { return 16; }
+        if average > 238 { return 231; }
+        return 232 + ((average - 8) / 10) as u8;
+    }
+    16 + 36 * r + 6 * g + b
+}
+
+// nearest of the 16 standard terminal colors
+fn ansi16(color: Color) -> u8 {
+    const PALETTE: [(u8, u8, u8); 16] = [
+        (0, 0, 0), (128, 0, 0), (0, 128, 0), (128, 128, 0), (0, 0, 128), (128, 0, 128), (0, 128, 128), (192, 192, 192),
+        (128, 128, 128), (255, 0, 0), (0, 255, 0), (255, 255, 0), (0, 0, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255)
+    ];
+    let distance = |p: &(u8, u8, u8)| {
+        let dr = p.0 as i32 - color.r as i32;
+        let dg = p.1 as i32 - color.g as i32;
+        let db = p.2 as i32 - color.b as i32;
+        dr * dr + dg * dg + db * db
+    };
+    (0..16).min_by_key(|i| distance(&PALETTE[*i as usize])).unwrap()
+}
+
+fn restore_terminal() {
+    if let Ok(mut original) = ORIGINAL_TERMIOS.lock() {
+        if let Some(termios) = original.take() {
+            // stop mouse reporting, reset colors, show the cursor and leave the alternate screen
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(b"\x1b[?1003l\x1b[?1006l\x1b[0m\x1b[?25h\x1b[?1049l");
+            let _ = stdout.flush();
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &termios); }
+        }
+    }
+}
+
+extern "C" fn restore_terminal_at_exit() {
+    restore_terminal();
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Cell {
+    glyph: char,
+    foreground: Color,
+    background: Color
+}
+
+const BLANK: Cell = Cell{glyph: ' ', foreground: WHITE, background: BLACK};
+
+pub struct Terminal {
+    color_mode: ColorMode,
+    back: Vec<Cell>,
+    // what is currently on screen, None until the first full draw
+    front: Vec<Option<Cell>>,
+    input: Stdin
+}
+
+impl Terminal {
+    pub fn new() -> Self {
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) == 0 {
+                *ORIGINAL_TERMIOS.lock().unwrap() = Some(termios);
+                let mut raw = termios;
+                libc::cfmakeraw(&mut raw);
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw);
+                // the game quits through process::exit, so Drop alone isn't enough
+                libc::atexit(restore_terminal_at_exit);
+            }
+        }
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            previous_hook(info);
+        }));
+
+        let mut stdout = std::io::stdout();
+        // alternate screen, hidden cursor, sgr mouse reporting including motion
+        let _ = stdout.write_all(b"\x1b[?1049h\x1b[?25l\x1b[2J\x1b[?1003h\x1b[?1006h");
+        let _ = stdout.flush();
+
+        let size = (SCREEN_WIDTH * SCREEN_HEIGHT) as usize;
+        Terminal{
+            color_mode: ColorMode::detect(),
+            back: vec![BLANK; size],
+            front: vec![None; size],
+            input: Stdin{closed: false}
+        }
+    }
+
+    fn set(&mut self, x: i32, y: i32, cell: Cell) {
+        if x >= 0 && y >= 0 && x < SCREEN_WIDTH && y < SCREEN_HEIGHT {
+            self.back[(x + y * SCREEN_WIDTH) as usize] = cell;
+        }
+    }
+}
+
+// a source of raw keyboard and mouse bytes, which the provided methods decode into events
+trait RawInput {
+    fn read_byte(&mut self) -> Option<u8>;
+
+    // true if more input arrives within `millis`
+    fn input_within(&self, millis: i32) -> bool;
+
+    fn read_escape_sequence(&mut self) -> Option<InputEvent> {
+        if !self.input_within(ESCAPE_TIMEOUT_MS) {
+            return Some(key_event(KeyCode::Escape));
+        }
+        match self.read_byte() {
+            Some(b'[') | Some(b'O') => {},
+            _ => return None
+        }
+        let first = self.read_byte()?;
+        if first == b'<' {
+            return self.read_mouse_report();
+        }
+        // optional numeric parameters, i.e. ESC [ 5 ~ or ESC [ 1 ; 2 A for shift+up
+        let mut params: Vec<u32> = Vec::new();
+        let mut last = first;
+        if first.is_ascii_digit() {
+            params.push((first - b'0') as u32);
+            loop {
+                match self.read_byte()? {
+                    d @ b'0'..=b'9' => {
+                        let param = params.last_mut().unwrap();
+                        *param = *param * 10 + (d - b'0') as u32;
+                    },
+                    b';' => params.push(0),
+                    byte => { last = byte; break; }
+                }
+            }
+        }
+        let code = match last {
+            b'A' => KeyCode::Up,
+            b'B' => KeyCode::Down,
+            b'C' => KeyCode::Right,
+            b'D' => KeyCode::Left,
+            b'H' => KeyCode::Home,
+            b'F' => KeyCode::End,
+            b'~' => match params.first() {
+                Some(1) | Some(7) => KeyCode::Home,
+                Some(2) => KeyCode::Insert,
+                Some(3) => KeyCode::Delete,
+                Some(4) | Some(8) => KeyCode::End,
+                Some(5) => KeyCode::PageUp,
+                Some(6) => KeyCode::PageDown,
+                _ => return None
+            },
+            _ => return None
+        };
+        let mut event = key_event(code);
+        // the modifier parameter is 1 + a bitmask where bit 0 is shift
+        if let (InputEvent::Key(key), Some(modifier)) = (&mut event, params.get(1)) {
+            key.shift = modifier.saturating_sub(1) & 1 != 0;
+        }
+        Some(event)
+    }
+
+    // sgr mouse report: ESC [ < button ; column ; row (M for press/motion, m for release)
+    fn read_mouse_report(&mut self) -> Option<InputEvent> {
+        let mut fields = [0u32; 3];
+        let mut field = 0;
+        let last = loop {
+            match self.read_byte()? {
+                d @ b'0'..=b'9' => fields[field] = fields[field] * 10 + (d - b'0') as u32,
+                b';' if field < 2 => field += 1,
+                byte => break byte
+            }
+        };
+        let (button, x, y) = (fields[0], fields[1] as i32 - 1, fields[2] as i32 - 1);
+        if last != b'M' {
+            return None;
+        }
+        // bit 5 marks motion, the low bits are the button (3 = none held)
+        if button & 32 != 0 {
+            Some(InputEvent::MouseMove{x, y})
+        } else if button == 0 {
+            Some(InputEvent::MouseClick{x, y})
+        } else {
+            None
+        }
+    }
+
+    // the rest of a multibyte utf-8 character starting with `lead`, None if it isn't valid
+    fn read_utf8(&mut self, lead: u8) -> Option<char> {
+        let len = match lead {
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => return None
+        };
+        let mut bytes = vec![lead];
+        for _ in 1..len {
+            bytes.push(self.read_byte()?);
+        }
+        std::str::from_utf8(&bytes).ok()?.chars().next()
+    }
+
+    fn read_event(&mut self) -> Option<InputEvent> {
+        let code = match self.read_byte()? {
+            0x1b => return self.read_escape_sequence(),
+            // ctrl-c arrives as a byte in raw mode, treat it like escape
+            0x03 => KeyCode::Escape,
+            b'\r' | b'\n' => KeyCode::Enter,
+            0x7f | 0x08 => KeyCode::Backspace,
+            b'\t' => KeyCode::Tab,
+            // terminals can't tell the numpad from the number row, numlock-off keypads send
+            // home/end/page keys instead which the keymap binds to diagonals
+            digit @ b'0'..=b'9' => {
+                let mut key = Key::default();
+                key.code = number_row(digit - b'0');
+                key.printable = digit as char;
+                key.pressed = true;
+                return Some(InputEvent::Key(key));
+            },
+            byte => {
+                let printable = if byte.is_ascii() { byte as char } else { self.read_utf8(byte)? };
+                let mut key = Key::default();
+                key.code = KeyCode::Char;
+                key.printable = printable;
+                key.pressed = true;
+                key.shift = printable.is_uppercase();
+                return Some(InputEvent::Key(key));
+            }
+        };
+        Some(key_event(code))
+    }
+}
+
+struct Stdin {
+    // set at eof, i.e. when the ssh session goes away
+    closed: bool
+}
+
+impl RawInput for Stdin {
+    // reads straight from the fd, std's buffered stdin would hide pending bytes from poll()
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = 0u8;
+        loop {
+            let read = unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            if read == 1 {
+                return Some(byte);
+            }
+            // signals such as a window resize interrupt the read, just try again
+            if read < 0 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            // eof, i.e. the ssh session went away
+            self.closed = true;
+            return None;
+        }
+    }
+
+    fn input_within(&self, millis: i32) -> bool {
+        let mut fd = libc::pollfd{fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0};
+        unsafe { libc::poll(&mut fd, 1, millis) > 0 }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+impl Renderer for Terminal {
+    fn put_char_ex(&mut self, x: i32, y: i32, glyph: char, foreground: Color, background: Color) {
+        self.set(x, y, Cell{glyph, foreground, background});
+    }
+
+    fn set_char_background(&mut self, x: i32, y: i32, background: Color) {
+        if x >= 0 && y >= 0 && x < SCREEN_WIDTH && y < SCREEN_HEIGHT {
+            self.back[(x + y * SCREEN_WIDTH) as usize].background = background;
+        }
+    }
+
+    fn print(&mut self, x: i32, y: i32, text: &str, alignment: TextAlignment) {
+        let len = text.chars().count() as i32;
+        let start = match alignment {
+            TextAlignment::Left => x,
+            TextAlignment::Center => x - len / 2,
+            TextAlignment::Right => x - len + 1,
+        };
+        for (i, glyph) in text.chars().enumerate() {
+            self.set(start + i as i32, y, Cell{glyph, foreground: WHITE, background: BLACK});
+        }
+    }
+
+    fn clear(&mut self) {
+        for cell in self.back.iter_mut() {
+            *cell = BLANK;
+        }
+    }
+
+    fn flush(&mut self) {
+        // only send cells that changed since the last frame, keeps ssh traffic down
+        let mut out = String::new();
+        let mut last_colors: Option<(Color, Color)> = None;
+        let mut cursor: Option<usize> = None;
+        for (i, cell) in self.back.iter().enumerate() {
+            if self.front[i] == Some(*cell) {
+                continue;
+            }
+            let x = i as i32 % SCREEN_WIDTH;
+            let y = i as i32 / SCREEN_WIDTH;
+            // the cursor already sits here after writing the previous cell on this row
+            if cursor != Some(i) || x == 0 {
+                out.push_str(&format!("\x1b[{};{}H", y + 1, x + 1));
+            }
+            cursor = Some(i + 1);
+            if last_colors != Some((cell.foreground, cell.background)) {
+                out.push_str(&format!("\x1b[{};{}m", self.color_mode.sgr(cell.foreground, false), self.color_mode.sgr(cell.background, true)));
+                last_colors = Some((cell.foreground, cell.background));
+            }
+            out.push(cell.glyph);
+            self.front[i] = Some(*cell);
+        }
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(out.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+impl Input for Terminal {
+    fn wait_for_event(&mut self) -> InputEvent {
+        loop {
+            if let Some(event) = self.input.read_event() {
+                return event;
+            }
+            if self.input.closed {
+                return key_event(KeyCode::NoKey);
+            }
+        }
+    }
+
+    fn wait_for_event_timeout(&mut self, millis: u64) -> Option<InputEvent> {
+        if self.input.closed || !self.input.input_within(millis.min(i32::MAX as u64) as i32) {
+            return None;
+        }
+        self.input.read_event()
+    }
+
+    fn window_closed(&self) -> bool {
+        self.input.closed
+    }
+}
+
+fn key_event(code: KeyCode) -> InputEvent {
+    let mut key = Key::default();
+    key.code = code;
+    key.pressed = code != KeyCode::NoKey;
+    InputEvent::Key(key)
+}
+
+fn number_row(digit: u8) -> KeyCode {
+    match digit {
+        0 => KeyCode::Number0,
+        1 => KeyCode::Number1,
+        2 => KeyCode::Number2,
+        3 => KeyCode::Number3,
+        4 => KeyCode::Number4,
+        5 => KeyCode::Number5,
+        6 => KeyCode::Number6,
+        7 => KeyCode::Number7,
+        8 => KeyCode::Number8,
+        _ => KeyCode::Number9
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // bytes typed ahead of time, all of them already waiting
+    impl RawInput for VecDeque<u8> {
+        fn read_byte(&mut self) -> Option<u8> {
+            self.pop_front()
+        }
+
+        fn input_within(&self, _millis: i32) -> bool {
+            !self.is_empty()
+        }
+    }
+
+    fn event(bytes: &[u8]) -> Option<InputEvent> {
+        let mut input: VecDeque<u8> = bytes.iter().copied().collect();
+        let event = input.read_event();
+        assert!(input.is_empty(), "{:?} left over", input);
+        event
+    }
+
+    fn key(bytes: &[u8]) -> Key {
+        match event(bytes) {
+            Some(InputEvent::Key(key)) => key,
+            other => panic!("{:?} isn't a key", other)
+        }
+    }
+
+    #[test]
+    fn colors_are_quantised_to_the_nearest_terminal_color() {
+        assert_eq!(ansi256(Color{r: 0, g: 0, b: 0}), 16);
+        assert_eq!(ansi256(Color{r: 255, g: 255, b: 255}), 231);
+        assert_eq!(ansi256(Color{r: 128, g: 128, b: 128}), 244);
+        assert_eq!(ansi256(Color{r: 255, g: 0, b: 0}), 196);
+        assert_eq!(ansi256(Color{r: 0, g: 128, b: 255}), 33);
+
+        assert_eq!(ansi16(Color{r: 0, g: 0, b: 0}), 0);
+        assert_eq!(ansi16(Color{r: 120, g: 10, b: 0}), 1);
+        assert_eq!(ansi16(Color{r: 200, g: 200, b: 200}), 7);
+        assert_eq!(ansi16(Color{r: 250, g: 0, b: 0}), 9);
+        assert_eq!(ansi16(Color{r: 255, g: 255, b: 240}), 15);
+    }
+
+    #[test]
+    fn each_color_mode_writes_its_own_sgr_parameters() {
+        let color = Color{r: 255, g: 0, b: 0};
+        assert_eq!(ColorMode::TrueColor.sgr(color, false), "38;2;255;0;0");
+        assert_eq!(ColorMode::TrueColor.sgr(color, true), "48;2;255;0;0");
+        assert_eq!(ColorMode::Ansi256.sgr(color, false), "38;5;196");
+        assert_eq!(ColorMode::Ansi256.sgr(color, true), "48;5;196");
+        // bright red is past the first eight
+        assert_eq!(ColorMode::Ansi16.sgr(color, false), "91");
+        assert_eq!(ColorMode::Ansi16.sgr(color, true), "101");
+        assert_eq!(ColorMode::Ansi16.sgr(Color{r: 128, g: 0, b: 0}, false), "31");
+        assert_eq!(ColorMode::Ansi16.sgr(Color{r: 128, g: 0, b: 0}, true), "41");
+    }
+
+    #[test]
+    fn escape_sequences_become_keys() {
+        assert_eq!(key(b"\x1b[A").code, KeyCode::Up);
+        assert_eq!(key(b"\x1b[D").code, KeyCode::Left);
+        assert_eq!(key(b"\x1bOB").code, KeyCode::Down);
+        assert!(!key(b"\x1b[C").shift);
+        let shifted = key(b"\x1b[1;2C");
+        assert_eq!(shifted.code, KeyCode::Right);
+        assert!(shifted.shift);
+        assert_eq!(key(b"\x1b[H").code, KeyCode::Home);
+        assert_eq!(key(b"\x1b[1~").code, KeyCode::Home);
+        assert_eq!(key(b"\x1b[F").code, KeyCode::End);
+        assert_eq!(key(b"\x1b[4~").code, KeyCode::End);
+        assert_eq!(key(b"\x1b[5~").code, KeyCode::PageUp);
+        assert_eq!(key(b"\x1b[6~").code, KeyCode::PageDown);
+        // nothing follows, so it's the escape key itself
+        assert_eq!(key(b"\x1b").code, KeyCode::Escape);
+        assert!(event(b"\x1b[9~").is_none());
+    }
+
+    #[test]
+    fn sgr_mouse_reports_become_clicks_and_moves() {
+        assert!(matches!(event(b"\x1b[<0;10;5M"), Some(InputEvent::MouseClick{x: 9, y: 4})));
+        assert!(matches!(event(b"\x1b[<35;3;7M"), Some(InputEvent::MouseMove{x: 2, y: 6})));
+        // releases and other buttons are ignored
+        assert!(event(b"\x1b[<0;10;5m").is_none());
+        assert!(event(b"\x1b[<2;10;5M").is_none());
+    }
+
+    #[test]
+    fn typed_characters_are_decoded_as_utf8() {
+        let a = key(b"a");
+        assert_eq!((a.code, a.printable, a.shift), (KeyCode::Char, 'a', false));
+        assert!(key(b"Q").shift);
+        assert_eq!(key(b"5").code, KeyCode::Number5);
+        let e = key("é".as_bytes());
+        assert_eq!((e.code, e.printable, e.shift), (KeyCode::Char, 'é', false));
+        assert!(key("É".as_bytes()).shift);
+        assert_eq!(key("語".as_bytes()).printable, '語');
+        // stray continuation bytes and broken sequences are dropped rather than misread
+        assert!(event(&[0x80]).is_none());
+        assert!(event(&[0xff]).is_none());
+        assert!(event(&[0xc3, 0x28]).is_none());
+    }
+}
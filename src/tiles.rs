@@ -0,0 +1,181 @@
+use tcod::colors::Color;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::OnceLock;
+
+/*
+Terrain definitions loaded from data/tiles.cfg, so new kinds of terrain don't need code changes
+*/
+
+const DEFAULT_TILES: &str = include_str!("../data/tiles.cfg");
+const TILES_PATH: &str = "data/tiles.cfg";
+
+// tiles the game logic refers to directly, they always get these ids whatever order the file is in
+pub const CORE_TILES: [&str; 5] = ["ground", "wall", "deep_water", "door_closed", "door_open"];
+
+static TILE_SET: OnceLock<TileSet> = OnceLock::new();
+
+#[derive(Clone, Debug)]
+pub struct TileDef {
+    pub name: String,
+    pub glyph: char,
+    pub lit: Color,
+    pub remembered: Color,
+    pub walkable: bool,
+    pub transparent: bool,
+    pub cost: f32,
+    pub flammable: bool,
+    pub depth: u8,
+    pub description: String
+}
+
+#[derive(Debug)]
+pub struct TileSet {
+    pub defs: Vec<TileDef>,
+    ids: HashMap<String, usize>
+}
+
+impl TileSet {
+    /// Parses `[id]` sections of `field = value` lines. Every field is required for every tile.
+    pub fn parse(text: &str) -> Result<TileSet, String> {
+        let mut sections: Vec<(String, usize, HashMap<String, String>)> = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(id) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                let id = id.trim();
+                if sections.iter().any(|(existing, _, _)| existing == id) {
+                    return Err(format!("line {}: tile '{}' is defined twice", line_no + 1, id));
+                }
+                sections.push((id.to_string(), line_no + 1, HashMap::new()));
+                continue;
+            }
+            let (field, value) = line.split_once('=').ok_or(format!("line {}: expected 'field = value'", line_no + 1))?;
+            let (_, _, fields) = sections.last_mut().ok_or(format!("line {}: field outside of a [tile] section", line_no + 1))?;
+            fields.insert(field.trim().to_string(), value.trim().to_string());
+        }
+
+        // core tiles first so their ids are fixed
+        let mut order: Vec<usize> = Vec::new();
+        for core in CORE_TILES.iter() {
+            let index = sections.iter().position(|(id, _, _)| id == core).ok_or(format!("missing required tile '{}'", core))?;
+            order.push(index);
+        }
+        let rest: Vec<usize> = (0..sections.len()).filter(|i| !order.contains(i)).collect();
+        order.extend(rest);
+
+        let mut defs = Vec::new();
+        let mut ids = HashMap::new();
+        for index in order {
+            let (id, line_no, fields) = &sections[index];
+            let def = parse_def(fields).map_err(|e| format!("tile '{}' (line {}): {}", id, line_no, e))?;
+            ids.insert(id.clone(), defs.len());
+            defs.push(def);
+        }
+        Ok(TileSet{defs, ids})
+    }
+
+    pub fn id(&self, name: &str) -> Option<usize> {
+        self.ids.get(name).copied()
+    }
+}
+
+fn field<'a>(fields: &'a HashMap<String, String>, name: &str) -> Result<&'a str, String> {
+    fields.get(name).map(|v| v.as_str()).ok_or(format!("missing '{}'", name))
+}
+
+fn parse_bool(fields: &HashMap<String, String>, name: &str) -> Result<bool, String> {
+    field(fields, name)?.parse().map_err(|_| format!("'{}' should be true or false", name))
+}
+
+fn parse_color(fields: &HashMap<String, String>, name: &str) -> Result<Color, String> {
+    let parts: Vec<Result<u8, _>> = field(fields, name)?.split(',').map(|c| c.trim().parse::<u8>()).collect();
+    match parts.as_slice() {
+        [Ok(r), Ok(g), Ok(b)] => Ok(Color{r: *r, g: *g, b: *b}),
+        _ => Err(format!("'{}' should be three numbers from 0 to 255, i.e. 127, 101, 63", name))
+    }
+}
+
+fn parse_def(fields: &HashMap<String, String>) -> Result<TileDef, String> {
+    let known = ["name", "glyph", "lit", "remembered", "walkable", "transparent", "cost", "flammable", "depth", "description"];
+    if let Some(unknown) = fields.keys().find(|f| !known.contains(&f.as_str())) {
+        return Err(format!("unknown field '{}'", unknown));
+    }
+    let mut glyph = field(fields, "glyph")?.chars();
+    let glyph = match (glyph.next(), glyph.next()) {
+        (Some(c), None) => c,
+        _ => return Err("'glyph' should be a single character".to_string())
+    };
+    let cost: f32 = field(fields, "cost")?.parse().map_err(|_| "'cost' should be a number".to_string())?;
+    // anything cheaper would make the pathfinding heuristics overestimate
+    if cost < 1.0 {
+        return Err("'cost' can't be less than 1".to_string());
+    }
+    let depth: u8 = field(fields, "depth")?.parse().map_err(|_| "'depth' should be 0, 1 or 2".to_string())?;
+    if depth > 2 {
+        return Err("'depth' should be 0, 1 or 2".to_string());
+    }
+    Ok(TileDef {
+        name: field(fields, "name")?.to_string(),
+        glyph,
+        lit: parse_color(fields, "lit")?,
+        remembered: parse_color(fields, "remembered")?,
+        walkable: parse_bool(fields, "walkable")?,
+        transparent: parse_bool(fields, "transparent")?,
+        cost,
+        flammable: parse_bool(fields, "flammable")?,
+        depth,
+        description: field(fields, "description")?.to_string()
+    })
+}
+
+/// Loads data/tiles.cfg if it's there and valid, otherwise the copy built into the game.
+/// Has no effect once the tiles are in use.
+pub fn load() {
+    TILE_SET.get_or_init(|| {
+        match fs::read_to_string(TILES_PATH).map_err(|e| e.to_string()).and_then(|text| TileSet::parse(&text)) {
+            Ok(tiles) => tiles,
+            Err(err) => {
+                eprintln!("could not load {}, using built-in tiles: {}", TILES_PATH, err);
+                TileSet::parse(DEFAULT_TILES).expect("built-in tiles are invalid")
+            }
+        }
+    });
+}
+
+pub fn tile_set() -> &'static TileSet {
+    TILE_SET.get_or_init(|| TileSet::parse(DEFAULT_TILES).expect("built-in tiles are invalid"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_tiles_parse_with_core_tiles_first() {
+        let tiles = TileSet::parse(DEFAULT_TILES).unwrap();
+        for (index, core) in CORE_TILES.iter().enumerate() {
+            assert_eq!(tiles.id(core), Some(index));
+        }
+        let lava = &tiles.defs[tiles.id("lava").unwrap()];
+        assert!(!lava.walkable && lava.transparent);
+        assert_eq!(tiles.defs[tiles.id("shallow_water").unwrap()].depth, 1);
+    }
+
+    #[test]
+    fn bad_definitions_are_rejected() {
+        let missing_core = "[ground]\nname = ground";
+        assert!(TileSet::parse(missing_core).unwrap_err().contains("missing required tile 'wall'"));
+
+        let mut text = DEFAULT_TILES.to_string();
+        text.push_str("\n[moss]\nname = moss\nglyph = ,\nlit = 0, 255\nremembered = 0, 0, 0\n\
+            walkable = true\ntransparent = true\ncost = 1\nflammable = true\ndepth = 0\ndescription = Moss.\n");
+        assert!(TileSet::parse(&text).unwrap_err().contains("'lit' should be three numbers"));
+
+        let mut text = DEFAULT_TILES.to_string();
+        text.push_str("\n[ground]\n");
+        assert!(TileSet::parse(&text).unwrap_err().contains("defined twice"));
+    }
+}
@@ -0,0 +1,415 @@
+use crate::map::{Map, TileType, GasKind, MemoryLoss};
+use crate::environmentsystem::BURN_TURNS;
+use crate::components::{Position, Sprite, Name, Description, Health, Hidden, Trap, TrapKind, SecretDoor, Perception,
+                        Actor, Player, PathFollower, Vision, Speed, Blocking, StatusEffects, Speaks,
+                        Faction, Melee};
+use crate::gamelog::GameLog;
+use crate::language::Tongue;
+use crate::conlang::NameKind;
+use crate::progression::Tower;
+use crate::stats;
+use crate::turnsystem;
+use crate::rng::GameRng;
+use specs::{Builder, Entity, World, WorldExt, Join, LazyUpdate};
+use tcod::colors::*;
+use rand::Rng;
+use std::collections::HashSet;
+
+/*
+Traps and other hidden features: noticing them, searching for them, setting them off and disarming them
+*/
+
+// bonus for deliberately searching rather than just walking past
+const SEARCH_BONUS: i32 = 5;
+// failing a disarm roll by this much sets the trap off
+const FUMBLE_MARGIN: i32 = 5;
+// density of the cloud a gas trap lets out
+const TRAP_GAS: u8 = 40;
+
+impl TrapKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            TrapKind::Pit => "pit trap",
+            TrapKind::Dart => "dart trap",
+            TrapKind::Teleport => "teleport trap",
+            TrapKind::Alarm => "alarm trap",
+            TrapKind::Summoning => "summoning trap",
+            TrapKind::Fire => "fire trap",
+            TrapKind::PoisonGas => "poison gas trap",
+            TrapKind::ConfusionGas => "confusion gas trap",
+            TrapKind::Amnesia => "amnesia trap",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            TrapKind::Pit => "A covered pit, deep enough to hurt.",
+            TrapKind::Dart => "A pressure plate wired to a hidden dart launcher.",
+            TrapKind::Teleport => "A faintly glowing sigil that flings whoever treads on it elsewhere.",
+            TrapKind::Alarm => "A tripwire strung to a cluster of bells.",
+            TrapKind::Summoning => "A circle of runes, humming with something waiting to be called.",
+            TrapKind::Fire => "A flint striker set over a reservoir of lamp oil.",
+            TrapKind::PoisonGas => "A vent in the floor, crusted with something green.",
+            TrapKind::ConfusionGas => "A vent in the floor that smells sweetly of flowers.",
+            TrapKind::Amnesia => "A spiral of runes that is somehow hard to keep looking at.",
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            TrapKind::Pit => DARK_SEPIA,
+            TrapKind::Dart => LIGHT_GREY,
+            TrapKind::Teleport => LIGHT_MAGENTA,
+            TrapKind::Alarm => YELLOW,
+            TrapKind::Summoning => LIGHT_RED,
+            TrapKind::Fire => FLAME,
+            TrapKind::PoisonGas => LIME,
+            TrapKind::ConfusionGas => LIGHT_PURPLE,
+            TrapKind::Amnesia => LIGHT_BLUE,
+        }
+    }
+}
+
+pub fn spawn_trap(ecs: &mut World, kind: TrapKind, x: i32, y: i32, difficulty: i32) {
+    ecs.create_entity().with(Position{x, y})
+                       .with(Sprite{sprite: '^', color: kind.color()})
+                       .with(Name{name: kind.name().to_string()})
+                       .with(Description{text: kind.description().to_string()})
+                       .with(Trap{kind, difficulty})
+                       .with(Hidden{difficulty}).build();
+}
+
+// a door in a wall that stays wall until someone finds it
+pub fn spawn_secret_door(ecs: &mut World, x: i32, y: i32, difficulty: i32) {
+    ecs.write_resource::<Map>().set_tile_type(x, y, TileType::WALL);
+    ecs.create_entity().with(Position{x, y})
+                       .with(SecretDoor{})
+                       .with(Hidden{difficulty}).build();
+}
+
+// d20 plus the entity's perception skill
+fn roll(ecs: &World, id: Entity, bonus: i32) -> i32 {
+    let skill = ecs.read_storage::<Perception>().get(id).map_or(0, |p| p.skill);
+    ecs.write_resource::<GameRng>().0.gen_range(1..=20) + skill + bonus
+}
+
+fn name_of(ecs: &World, ent: Entity) -> String {
+    ecs.read_storage::<Name>().get(ent).map_or("something".to_string(), |n| n.name.clone())
+}
+
+// turns a found feature into what it really is
+fn reveal(ecs: &World, feature: Entity) {
+    if ecs.read_storage::<SecretDoor>().contains(feature) {
+        if let Some(pos) = ecs.read_storage::<Position>().get(feature) {
+            ecs.write_resource::<Map>().set_tile_type(pos.x, pos.y, TileType::DOOR_CLOSED);
+        }
+        ecs.entities().delete(feature).expect("revealed a dead entity");
+        ecs.write_resource::<GameLog>().log("You find a secret door!");
+    } else {
+        ecs.write_storage::<Hidden>().remove(feature);
+        let name = name_of(ecs, feature);
+        ecs.write_resource::<GameLog>().log(format!("You notice {} {}.", stats::article(&name), name));
+    }
+}
+
+// hidden features on any of the given tiles, with how hard each is to find
+fn hidden_features_at(ecs: &World, tiles: &HashSet<(i32, i32)>) -> Vec<(Entity, i32)> {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let hidden = ecs.read_storage::<Hidden>();
+    (&entities, &positions, &hidden).join()
+        .filter(|(_, pos, _)| tiles.contains(&(pos.x, pos.y)))
+        .map(|(ent, _, hidden)| (ent, hidden.difficulty))
+        .collect()
+}
+
+/// One perception check for every hidden feature on a tile that just came into the player's view.
+pub fn passive_perception(ecs: &World) {
+    let player = *ecs.fetch::<Entity>();
+    let tiles: HashSet<(i32, i32)> = ecs.fetch::<Map>().newly_visible.iter().map(|t| (t.x, t.y)).collect();
+    if tiles.is_empty() {
+        return;
+    }
+    for (feature, difficulty) in hidden_features_at(ecs, &tiles) {
+        if roll(ecs, player, 0) >= difficulty {
+            reveal(ecs, feature);
+        }
+    }
+}
+
+/// Searches the tiles around `id` with a bonus over passive perception. Returns whether anything was found.
+pub fn search(ecs: &World, id: Entity) -> bool {
+    let (x, y) = match ecs.read_storage::<Position>().get(id) {
+        Some(pos) => (pos.x, pos.y),
+        None => return false
+    };
+    let tiles: HashSet<(i32, i32)> = (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy))).collect();
+    let mut found = false;
+    for (feature, difficulty) in hidden_features_at(ecs, &tiles) {
+        if roll(ecs, id, SEARCH_BONUS) >= difficulty {
+            reveal(ecs, feature);
+            found = true;
+        }
+    }
+    found
+}
+
+fn trap_at(ecs: &World, x: i32, y: i32, known_only: bool) -> Option<Entity> {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let traps = ecs.read_storage::<Trap>();
+    let hidden = ecs.read_storage::<Hidden>();
+    (&entities, &positions, &traps).join()
+        .find(|(ent, pos, _)| pos.x == x && pos.y == y && !(known_only && hidden.contains(*ent)))
+        .map(|(ent, _, _)| ent)
+}
+
+// a trap the player knows about, so moving onto it on purpose can be avoided
+pub fn known_trap_at(ecs: &World, x: i32, y: i32) -> bool {
+    trap_at(ecs, x, y, true).is_some()
+}
+
+pub fn adjacent_known_traps(ecs: &World, x: i32, y: i32) -> Vec<(i32, i32)> {
+    let mut traps = Vec::new();
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            if (dx != 0 || dy != 0) && known_trap_at(ecs, x + dx, y + dy) {
+                traps.push((x + dx, y + dy));
+            }
+        }
+    }
+    traps
+}
+
+fn hurt(ecs: &World, id: Entity, amount: i32, cause: &str) {
+    if let Some(health) = ecs.write_storage::<Health>().get_mut(id) {
+        health.current -= amount;
+    }
+    stats::player_hurt(ecs, id, amount, cause);
+}
+
+// a random free tile on the map, for teleports
+fn random_free_tile(map: &Map, rng: &mut GameRng) -> Option<(i32, i32)> {
+    let free: Vec<usize> = (0..map.blocked.len()).filter(|idx| !map.blocked[*idx]).collect();
+    if free.is_empty() {
+        return None;
+    }
+    let idx = free[rng.0.gen_range(0..free.len())];
+    Some((idx as i32 % map.width, idx as i32 / map.width))
+}
+
+// monsters come from the summoning, spawned at the end of the tick
+fn summon_imp(ecs: &World, x: i32, y: i32) {
+    let map = ecs.fetch::<Map>();
+    let spot = (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+        .find(|(sx, sy)| (*sx, *sy) != (x, y) && map.is_exit_valid(*sx, *sy));
+    if let Some((sx, sy)) = spot {
+        let true_name = Tongue::Infernal.conlang().name(NameKind::Monster, &mut ecs.write_resource::<GameRng>().0);
+        let (health, damage) = ecs.try_fetch::<Tower>().map_or((6, 3), |tower| (tower.health(6), tower.damage(3)));
+        ecs.fetch::<LazyUpdate>().create_entity(&ecs.entities())
+            .with(Actor{action_points: 0, threshold: 8})
+            .with(Position{x: sx, y: sy})
+            .with(Sprite{sprite: 'i', color: LIGHT_RED})
+            .with(Name{name: "imp".to_string()})
+            .with(Description{text: format!("A spiteful little fiend, summoned by the trap and none too pleased about it. Its true name is {}.", true_name)})
+            .with(Health{current: health, max: health})
+            .with(StatusEffects::default())
+            .with(Vision{field_of_vision: HashSet::new()})
+            .with(Speed{speed: 1})
+            .with(PathFollower::default())
+            .with(Faction{name: "demons".to_string()})
+            .with(Melee{damage})
+            .with(Speaks{tongue: Tongue::Infernal, shout: Some("You called, and I have come to collect.".to_string())})
+            .with(Blocking{}).build();
+    }
+}
+
+fn set_off(ecs: &World, trap: Entity, victim: Entity) {
+    let kind = match ecs.read_storage::<Trap>().get(trap) {
+        Some(trap) => trap.kind,
+        None => return
+    };
+    ecs.write_storage::<Hidden>().remove(trap);
+    let mut log = Vec::new();
+    match kind {
+        TrapKind::Pit => {
+            log.push("You fall into a pit!".to_string());
+            hurt(ecs, victim, 3, "fell to their death in a pit");
+        },
+        TrapKind::Dart => {
+            log.push("A dart shoots out of the wall and hits you!".to_string());
+            hurt(ecs, victim, 2, "was killed by a dart trap");
+        },
+        TrapKind::Teleport => {
+            log.push("The sigil flares and the world lurches around you!".to_string());
+            let destination = random_free_tile(&ecs.fetch::<Map>(), &mut ecs.write_resource::<GameRng>());
+            if let (Some((x, y)), Some(pos)) = (destination, ecs.write_storage::<Position>().get_mut(victim)) {
+                pos.x = x;
+                pos.y = y;
+            }
+        },
+        TrapKind::Alarm => {
+            log.push("Bells clang loudly! Everything on this floor comes running.".to_string());
+            let entities = ecs.entities();
+            let monsters: Vec<Entity> = (&entities, &ecs.read_storage::<PathFollower>()).join().map(|(ent, _)| ent).collect();
+            turnsystem::hurry(ecs, &monsters);
+        },
+        TrapKind::Summoning => {
+            log.push("The runes blaze and an imp claws its way out of the circle!".to_string());
+            if let Some(pos) = ecs.read_storage::<Position>().get(trap) {
+                summon_imp(ecs, pos.x, pos.y);
+            }
+            // the circle burns out after one use
+            ecs.entities().delete(trap).expect("set off a dead trap");
+        },
+        TrapKind::Fire => {
+            log.push("Oil sprays across the floor and bursts into flame!".to_string());
+            if let Some(pos) = ecs.read_storage::<Position>().get(trap) {
+                let mut map = ecs.write_resource::<Map>();
+                for (x, y) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (pos.x + dx, pos.y + dy))) {
+                    if map.in_bounds(x, y) && map.is_walkable(map.get_index(x, y)) {
+                        map.ignite(x, y, BURN_TURNS);
+                    }
+                }
+            }
+        },
+        TrapKind::PoisonGas | TrapKind::ConfusionGas => {
+            let gas = if kind == TrapKind::PoisonGas { GasKind::Poison } else { GasKind::Confusion };
+            log.push(format!("A cloud of {} billows out of the floor!", gas.name()));
+            if let Some(pos) = ecs.read_storage::<Position>().get(trap) {
+                ecs.write_resource::<Map>().release_gas(pos.x, pos.y, gas, TRAP_GAS);
+            }
+        },
+        TrapKind::Amnesia => {
+            log.push("The runes spin and your memory of this floor drains away!".to_string());
+            ecs.write_resource::<Map>().forget(MemoryLoss::Amnesia);
+        }
+    }
+    for message in log {
+        ecs.write_resource::<GameLog>().log(message);
+    }
+}
+
+/// Sets off whatever trap `id` just stepped onto, found or not. Only the player triggers traps,
+/// the tower's inhabitants know where they are. Returns whether a trap went off.
+pub fn trigger_at(ecs: &World, id: Entity) -> bool {
+    if !ecs.read_storage::<Player>().contains(id) {
+        return false;
+    }
+    let here = ecs.read_storage::<Position>().get(id).map(|pos| (pos.x, pos.y));
+    match here.and_then(|(x, y)| trap_at(ecs, x, y, false)) {
+        Some(trap) => {
+            set_off(ecs, trap, id);
+            true
+        },
+        None => false
+    }
+}
+
+/// Tries to disarm the known trap at (x, y). Success removes it, a bad failure sets it off on `id`.
+/// Returns whether an attempt was made, which takes the turn.
+pub fn disarm(ecs: &World, id: Entity, x: i32, y: i32) -> bool {
+    let trap = match trap_at(ecs, x, y, true) {
+        Some(trap) => trap,
+        None => {
+            ecs.write_resource::<GameLog>().log("There is no trap there that you know of.");
+            return false;
+        }
+    };
+    let difficulty = ecs.read_storage::<Trap>().get(trap).map_or(0, |t| t.difficulty);
+    let name = name_of(ecs, trap);
+    let result = roll(ecs, id, 0);
+    if result >= difficulty {
+        ecs.entities().delete(trap).expect("disarmed a dead trap");
+        ecs.write_resource::<GameLog>().log(format!("You disarm the {}.", name));
+    } else if result + FUMBLE_MARGIN <= difficulty {
+        ecs.write_resource::<GameLog>().log(format!("You set off the {}!", name));
+        set_off(ecs, trap, id);
+    } else {
+        ecs.write_resource::<GameLog>().log(format!("You fail to disarm the {}.", name));
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_world(skill: i32) -> (World, Entity) {
+        let mut ecs = World::new();
+        ecs.register::<Position>();
+        ecs.register::<Sprite>();
+        ecs.register::<Name>();
+        ecs.register::<Description>();
+        ecs.register::<Health>();
+        ecs.register::<Hidden>();
+        ecs.register::<Trap>();
+        ecs.register::<SecretDoor>();
+        ecs.register::<Perception>();
+        ecs.register::<Player>();
+        ecs.insert(Map::new(20, 10));
+        ecs.insert(GameLog::default());
+        ecs.insert(GameRng::new(0));
+        let player = ecs.create_entity().with(Position{x: 5, y: 5})
+                                        .with(Health{current: 10, max: 10})
+                                        .with(Perception{skill})
+                                        .with(Player{}).build();
+        ecs.insert(player);
+        (ecs, player)
+    }
+
+    #[test]
+    fn searching_reveals_traps_and_secret_doors() {
+        let (mut ecs, player) = test_world(100);
+        spawn_trap(&mut ecs, TrapKind::Dart, 6, 5, 15);
+        spawn_trap(&mut ecs, TrapKind::Pit, 9, 5, 15);
+        spawn_secret_door(&mut ecs, 4, 4, 15);
+        assert!(!known_trap_at(&ecs, 6, 5));
+
+        assert!(search(&ecs, player));
+        ecs.maintain();
+        assert!(known_trap_at(&ecs, 6, 5));
+        // out of reach of the search
+        assert!(!known_trap_at(&ecs, 9, 5));
+        assert_eq!(ecs.fetch::<Map>().get_tile_type(4, 4), TileType::DOOR_CLOSED);
+        assert!(ecs.read_storage::<SecretDoor>().is_empty());
+    }
+
+    #[test]
+    fn stepping_on_a_trap_sets_it_off_even_unseen() {
+        let (mut ecs, player) = test_world(-100);
+        spawn_trap(&mut ecs, TrapKind::Pit, 5, 5, 15);
+        assert!(trigger_at(&ecs, player));
+        assert_eq!(ecs.read_storage::<Health>().get(player).unwrap().current, 7);
+        assert!(known_trap_at(&ecs, 5, 5));
+    }
+
+    #[test]
+    fn disarming_depends_on_the_roll() {
+        let (mut ecs, player) = test_world(100);
+        spawn_trap(&mut ecs, TrapKind::Dart, 6, 5, 15);
+        assert!(!disarm(&ecs, player, 6, 5), "can't disarm a trap nobody has found");
+        search(&ecs, player);
+        assert!(disarm(&ecs, player, 6, 5));
+        ecs.maintain();
+        assert!(trap_at(&ecs, 6, 5, false).is_none());
+
+        // hopeless attempts always fumble and set the trap off
+        ecs.write_storage::<Perception>().get_mut(player).unwrap().skill = -100;
+        spawn_trap(&mut ecs, TrapKind::Dart, 4, 5, 15);
+        ecs.write_storage::<Hidden>().clear();
+        assert!(disarm(&ecs, player, 4, 5));
+        assert_eq!(ecs.read_storage::<Health>().get(player).unwrap().current, 8);
+    }
+
+    #[test]
+    fn the_amnesia_trap_wipes_the_floor_from_memory() {
+        use crate::map::Tile;
+        let (mut ecs, player) = test_world(-100);
+        spawn_trap(&mut ecs, TrapKind::Amnesia, 5, 5, 15);
+        ecs.write_resource::<Map>().update_memory(&HashSet::from([Tile{x: 6, y: 5, tile_type: TileType::GROUND}]));
+        assert!(ecs.fetch::<Map>().tile_visited(6, 5));
+        assert!(trigger_at(&ecs, player));
+        assert!(!ecs.fetch::<Map>().tile_visited(6, 5));
+    }
+}
@@ -1,5 +1,21 @@
 use super::{Actor, Speed};
-use specs::{System, ReadStorage, WriteStorage, Entities, Write, Entity};
+use specs::{System, ReadStorage, WriteStorage, Entities, Write, Entity, World, WorldExt};
+
+// number of ticks since the game started
+#[derive(Default)]
+pub struct Clock {
+    pub tick: u32
+}
+
+// gives each of `ids` a turn's worth of action points, i.e. monsters roused by a noise come running
+pub fn hurry(ecs: &World, ids: &[Entity]) {
+    let mut actors = ecs.write_storage::<Actor>();
+    for id in ids {
+        if let Some(actor) = actors.get_mut(*id) {
+            actor.action_points += actor.threshold;
+        }
+    }
+}
 
 /*
 Calculate and determine which entities will act on each tick
@@ -11,11 +27,13 @@ impl<'a> System<'a> for TurnSystem {
     type SystemData = (Entities<'a>,
                        WriteStorage<'a, Actor>,
                        ReadStorage<'a, Speed>,
-                       Write<'a, Vec<Entity>>);
+                       Write<'a, Vec<Entity>>,
+                       Write<'a, Clock>);
 
 
-    fn run(&mut self, (entities, mut act, spd,  mut turns): Self::SystemData) {
+    fn run(&mut self, (entities, mut act, spd,  mut turns, mut clock): Self::SystemData) {
         use specs::Join;
+        clock.tick += 1;
         for (entity, act, spd) in (&entities, &mut act, &spd).join() {
             act.action_points += spd.speed;
             if act.action_points >= act.threshold {
@@ -24,10 +24,9 @@ impl<'a> System<'a> for VisionSystem {
             // if this is the player_entity, send their fov to the map
             let _p : Option<&Player> = player.get(ent);
             if let Some(_p) = _p {
+                map.newly_visible = v.field_of_vision.difference(&map.visible).copied().collect();
                 map.visible = v.field_of_vision.clone();
-                for tile in v.field_of_vision.iter(){
-                        map.visited.insert(*tile);
-                    }
+                map.update_memory(&v.field_of_vision);
                 }
 
         }
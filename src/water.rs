@@ -0,0 +1,178 @@
+use crate::map::Map;
+use crate::components::{Position, Name, Player, Carried, Heavy, Submerged, Aquatic, PathFollower};
+use crate::turnsystem;
+use crate::gamelog::GameLog;
+use specs::{Entity, World, WorldExt, Join};
+
+/*
+Wading and swimming, and the things that lurk under the water
+*/
+
+// how far the splashing of someone wading carries, squared
+const SPLASH_RADIUS_SQ: f32 = 64.0;
+
+fn name_of(ecs: &World, id: Entity) -> String {
+    ecs.read_storage::<Name>().get(id).map_or("something".to_string(), |n| n.name.clone())
+}
+
+/// Called once `id` has stepped onto a new tile. Wading or swimming is loud enough to hurry nearby
+/// monsters towards the player, and swimming makes anyone let go of whatever heavy thing they carry,
+/// which sinks where they are.
+pub fn enter_water(ecs: &World, id: Entity) {
+    let (x, y) = match ecs.read_storage::<Position>().get(id) {
+        Some(pos) => (pos.x, pos.y),
+        None => return
+    };
+    let depth = ecs.fetch::<Map>().get_tile_type(x, y).def().depth;
+    if depth == 0 {
+        return;
+    }
+    let is_player = ecs.read_storage::<Player>().contains(id);
+    if is_player {
+        let heard: Vec<Entity> = {
+            let map = ecs.fetch::<Map>();
+            let entities = ecs.entities();
+            let positions = ecs.read_storage::<Position>();
+            let followers = ecs.read_storage::<PathFollower>();
+            (&entities, &positions, &followers).join()
+                .filter(|(_, pos, _)| map.get_distance_sq(x, y, pos.x, pos.y) <= SPLASH_RADIUS_SQ)
+                .map(|(ent, _, _)| ent)
+                .collect()
+        };
+        if !heard.is_empty() {
+            ecs.write_resource::<GameLog>().log("Your splashing echoes off the walls.");
+            turnsystem::hurry(ecs, &heard);
+        }
+    }
+    if depth > 1 {
+        let dropped: Vec<Entity> = {
+            let entities = ecs.entities();
+            let carried = ecs.read_storage::<Carried>();
+            let heavy = ecs.read_storage::<Heavy>();
+            (&entities, &carried, &heavy).join()
+                .filter(|(_, carried, _)| carried.owner == id)
+                .map(|(ent, _, _)| ent)
+                .collect()
+        };
+        for item in dropped {
+            ecs.write_storage::<Carried>().remove(item);
+            ecs.write_storage::<Position>().insert(item, Position{x, y}).expect("dropped a dead item");
+            ecs.write_storage::<Submerged>().insert(item, Submerged{}).expect("sank a dead item");
+            if is_player {
+                let message = format!("The {} slips from your grasp and sinks.", name_of(ecs, item));
+                ecs.write_resource::<GameLog>().log(message);
+            }
+        }
+    }
+}
+
+/// Aquatic monsters stay submerged, and out of sight, until the player comes up right next to them.
+pub fn surface_lurkers(ecs: &World) {
+    let player = *ecs.fetch::<Entity>();
+    let here = match ecs.read_storage::<Position>().get(player) {
+        Some(pos) => (pos.x, pos.y),
+        None => return
+    };
+    let mut surfaced = Vec::new();
+    {
+        let map = ecs.fetch::<Map>();
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let aquatic = ecs.read_storage::<Aquatic>();
+        let mut submerged = ecs.write_storage::<Submerged>();
+        for (ent, pos, _) in (&entities, &positions, &aquatic).join() {
+            let adjacent = (pos.x - here.0).abs() <= 1 && (pos.y - here.1).abs() <= 1;
+            if adjacent {
+                if submerged.remove(ent).is_some() {
+                    surfaced.push(ent);
+                }
+            } else if map.get_tile_type(pos.x, pos.y).def().depth > 1 {
+                submerged.insert(ent, Submerged{}).expect("submerged a dead entity");
+            }
+        }
+    }
+    for lurker in surfaced {
+        let message = format!("The {} surges up out of the water!", name_of(ecs, lurker));
+        ecs.write_resource::<GameLog>().log(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Item, Unlocks};
+    use crate::map::TileType;
+    use crate::doors::pick_up_keys;
+    use crate::components::Blocking;
+    use crate::mapblockingsystem::MapBlockingSystem;
+    use specs::{Builder, RunNow, World, WorldExt};
+
+    // a pond of deep water from x = 5 to x = 9
+    fn pond() -> World {
+        let mut ecs = World::new();
+        ecs.register::<Position>();
+        ecs.register::<Name>();
+        ecs.register::<Player>();
+        ecs.register::<Item>();
+        ecs.register::<Unlocks>();
+        ecs.register::<Carried>();
+        ecs.register::<Heavy>();
+        ecs.register::<Submerged>();
+        ecs.register::<Aquatic>();
+        ecs.register::<PathFollower>();
+        let mut map = Map::new(16, 8);
+        for y in 1..7 {
+            for x in 5..10 {
+                map.set_tile_type(x, y, TileType::DEEP_WATER);
+            }
+        }
+        ecs.insert(map);
+        ecs.insert(GameLog::default());
+        ecs
+    }
+
+    #[test]
+    fn swimming_drops_heavy_items_which_can_be_found_again() {
+        let mut ecs = pond();
+        let player = ecs.create_entity().with(Position{x: 6, y: 3}).with(Player{}).build();
+        let key = ecs.create_entity().with(Item{}).with(Unlocks{lock: 1}).with(Heavy{}).with(Carried{owner: player}).build();
+        enter_water(&ecs, player);
+        assert!(ecs.read_storage::<Carried>().get(key).is_none());
+        assert!(ecs.read_storage::<Submerged>().contains(key));
+
+        // groping around on the same tile finds it again
+        pick_up_keys(&ecs, player);
+        assert!(ecs.read_storage::<Carried>().get(key).is_some());
+    }
+
+    #[test]
+    fn lurkers_surface_only_next_to_the_player() {
+        let mut ecs = pond();
+        let player = ecs.create_entity().with(Position{x: 2, y: 3}).with(Player{}).build();
+        ecs.insert(player);
+        let eel = ecs.create_entity().with(Position{x: 7, y: 3}).with(Aquatic{}).build();
+        surface_lurkers(&ecs);
+        assert!(ecs.read_storage::<Submerged>().contains(eel));
+
+        ecs.write_storage::<Position>().get_mut(player).unwrap().x = 6;
+        surface_lurkers(&ecs);
+        assert!(!ecs.read_storage::<Submerged>().contains(eel));
+    }
+
+    #[test]
+    fn lurkers_only_block_once_they_surface() {
+        let mut ecs = pond();
+        ecs.register::<Blocking>();
+        let player = ecs.create_entity().with(Position{x: 2, y: 3}).with(Player{}).build();
+        ecs.insert(player);
+        ecs.create_entity().with(Position{x: 7, y: 3}).with(Aquatic{}).with(Submerged{}).with(Blocking{}).build();
+        let eel_tile = ecs.fetch::<Map>().get_index(7, 3);
+        MapBlockingSystem{}.run_now(&ecs);
+        assert!(!ecs.fetch::<Map>().blocked[eel_tile]);
+
+        ecs.write_storage::<Position>().get_mut(player).unwrap().x = 6;
+        surface_lurkers(&ecs);
+        MapBlockingSystem{}.run_now(&ecs);
+        assert!(ecs.fetch::<Map>().blocked[eel_tile]);
+    }
+}